@@ -0,0 +1,124 @@
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::net::TcpStream;
+use async_std::task;
+use async_tungstenite::tungstenite::{Error, Message};
+use async_tungstenite::{async_std::connect_async, WebSocketStream};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use hass_rs::client::HassClient;
+use lazy_static::lazy_static;
+use serde_json::json;
+use std::env::var;
+
+lazy_static! {
+    static ref TOKEN: String =
+        var("HASS_TOKEN").expect("please set up the HASS_TOKEN env variable before running this");
+}
+
+async fn ws_incoming_messages(
+    mut stream: SplitStream<WebSocketStream<TcpStream>>,
+    to_user: Sender<Result<Message, Error>>,
+) {
+    while let Some(message) = stream.next().await {
+        let _ = to_user.send(message).await;
+    }
+}
+
+async fn ws_outgoing_messages(
+    mut sink: SplitSink<WebSocketStream<TcpStream>, Message>,
+    from_user: Receiver<Message>,
+) {
+    loop {
+        match from_user.recv().await {
+            Ok(msg) => sink.send(msg).await.expect("Failed to send message"),
+            // the client was dropped, there is nothing left to send
+            Err(_) => return,
+        }
+    }
+}
+
+#[async_std::main]
+async fn main() {
+    let url = "ws://localhost:8123/api/websocket";
+
+    println!("Connecting to - {}", url);
+    let (wsclient, _) = connect_async(url).await.expect("Failed to connect");
+    let (sink, stream) = wsclient.split();
+
+    //Channels to recieve the Client Command and send it over to Websocket server
+    let (to_gateway, from_user) = bounded::<Message>(20);
+    //Channels to receive the Response from the Websocket server and send it over to Client
+    let (to_user, from_gateway) = bounded::<Result<Message, Error>>(20);
+
+    // Handle incoming messages in a separate task
+    let read_handle = task::spawn(ws_incoming_messages(stream, to_user));
+
+    // Read from command line and send messages
+    let write_handle = task::spawn(ws_outgoing_messages(sink, from_user));
+
+    let mut client = HassClient::new(to_gateway, from_gateway);
+
+    client
+        .auth_with_longlivedtoken(&*TOKEN)
+        .await
+        .expect("Not able to autheticate");
+
+    println!("WebSocket connection and authethication works\n");
+
+    let domain = "homeassistant";
+    let service = "turn_on";
+    let entity_id = "media_player.bravia_4k_gb";
+
+    println!("Getting the Services:\n");
+    let services = client
+        .get_services()
+        .await
+        .expect("Unable to retrieve the Services");
+
+    // Validate if the selected **domain** and **service** exist, with the required fields
+    let value = json!({
+        "entity_id": entity_id,
+    });
+    if let Err(problems) = services.validate_call(domain, service, &value) {
+        println!("The service can't be called: {:?}", problems);
+        return;
+    }
+
+    println!("Getting the States (Entities):\n");
+    let states = client
+        .get_states()
+        .await
+        .expect("Unable to retrieve the States");
+
+    // Validate if the selected **entity_id** exist
+    let entity_found = states.iter().find(|e| e.entity_id == entity_id);
+    if let Some(entity) = entity_found {
+        println!("{}", entity);
+    }
+
+    println!("Calling a service:, in this specific case to turn ON the TV\n");
+    let called = client
+        .call_service(domain.to_owned(), service.to_owned(), Some(value))
+        .await
+        .expect("Unable to call the targeted service");
+    println!("service: {:?}\n", called);
+
+    //check the new Entity state
+    println!("Getting again the States (Entities):\n");
+    let states = client
+        .get_states()
+        .await
+        .expect("Unable to retrieve the States");
+
+    let entity_found = states.iter().find(|e| e.entity_id == entity_id);
+    if let Some(entity) = entity_found {
+        println!("{}", entity);
+    }
+
+    // dropping the client ends the writing task, closing the connection ends the reading one
+    drop(client);
+    write_handle.await;
+    read_handle.await;
+}
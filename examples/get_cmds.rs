@@ -19,10 +19,8 @@ async fn ws_incoming_messages(
     mut stream: SplitStream<WebSocketStream<impl AsyncRead + AsyncWrite + Unpin>>,
     to_user: Sender<Result<Message, Error>>,
 ) {
-    loop {
-        while let Some(message) = stream.next().await {
-            let _ = to_user.send(message).await;
-        }
+    while let Some(message) = stream.next().await {
+        let _ = to_user.send(message).await;
     }
 }
 
@@ -33,7 +31,8 @@ async fn ws_outgoing_messages(
     loop {
         match from_user.recv().await {
             Some(msg) => sink.send(msg).await.expect("Failed to send message"),
-            None => todo!(),
+            // the client was dropped, there is nothing left to send
+            None => return,
         }
     }
 }
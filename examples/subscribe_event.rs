@@ -22,19 +22,17 @@ async fn ws_incoming_messages(
     to_user: Sender<Result<Message, Error>>,
     event_sender: Sender<WSEvent>,
 ) {
-    loop {
-        while let Some(message) = stream.next().await {
-            // check if it is a WSEvent, if so send to the spawned tokio task, that should handle the event
-            // otherwise process the message and respond accordingly
-            match check_if_event(&message) {
-                Ok(event) => {
-                    let _ = event_sender.send(event).await;
-                    continue;
-                }
-                _ => {
-                    let _ = to_user.send(message).await;
-                    continue;
-                }
+    while let Some(message) = stream.next().await {
+        // check if it is a WSEvent, if so send to the spawned tokio task, that should handle the event
+        // otherwise process the message and respond accordingly
+        match check_if_event(&message) {
+            Ok(event) => {
+                let _ = event_sender.send(event).await;
+                continue;
+            }
+            _ => {
+                let _ = to_user.send(message).await;
+                continue;
             }
         }
     }
@@ -47,7 +45,8 @@ async fn ws_outgoing_messages(
     loop {
         match from_user.recv().await {
             Some(msg) => sink.send(msg).await.expect("Failed to send message"),
-            None => todo!(),
+            // the client was dropped, there is nothing left to send
+            None => return,
         }
     }
 }
@@ -100,13 +99,14 @@ async fn main() {
 
     // Spawn a Tokio task to do whatever we want with the received events
     tokio::spawn(async move {
-        loop {
-            while let Some(message) = event_receiver.recv().await {
-                // process only events you have subscribed to
-                match subscriptions.get(&message.id) {
-                    Some(_) => println!("Event Received: {:?}", message),
-                    None => println!("Wrong event received: {:?}", message),
-                }
+        while let Some(message) = event_receiver.recv().await {
+            // process only events you have subscribed to
+            match subscriptions.get(&message.id) {
+                Some(event_type) => println!(
+                    "Event Received on the {} subscription {}: {:?}",
+                    event_type, message.id, message
+                ),
+                None => println!("Wrong event received: {:?}", message),
             }
         }
     });
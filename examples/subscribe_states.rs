@@ -0,0 +1,74 @@
+use futures_util::StreamExt;
+use hass_rs::ClientConfig;
+use lazy_static::lazy_static;
+use std::env::var;
+use std::time::Duration;
+
+lazy_static! {
+    static ref TOKEN: String =
+        var("HASS_TOKEN").expect("please set up the HASS_TOKEN env variable before running this");
+}
+
+#[tokio::main]
+async fn main() {
+    let url = "ws://localhost:8123/api/websocket";
+
+    // the connection is opened by the client, which dispatches the events of its subscriptions
+    println!("Connecting to - {}", url);
+    let mut client = ClientConfig::default()
+        .connect_and_auth(url, &TOKEN)
+        .await
+        .expect("Not able to connect and authenticate");
+
+    println!("WebSocket connection and authethication works\n");
+
+    let id = match client.subscribe_event("state_changed").await {
+        Ok(v) => {
+            println!("Subscribed to the state changes: {:?}", v);
+            v.id
+        }
+        Err(err) => panic!("Oh no, an error: {}", err),
+    };
+
+    let events = client
+        .event_stream()
+        .expect("the events are available on a connection opened by the client");
+
+    // print the state changes for a minute
+    let printing = events
+        .filter(|event| futures_util::future::ready(event.id == id))
+        .for_each(|event| async move {
+            let data = event.event.data;
+            let state = |state: Option<hass_rs::HassEntityState>| {
+                state.map_or_else(|| "-".to_owned(), |state| state.state)
+            };
+            println!(
+                "{}: {} -> {}",
+                data.entity_id,
+                state(data.old_state),
+                state(data.new_state)
+            );
+        });
+    let _ = tokio::time::timeout(Duration::from_secs(60), printing).await;
+
+    println!("Unsubscribe the state changes");
+
+    match client.unsubscribe_event(id).await {
+        Ok(v) => println!("Succefully unsubscribed: {}", v),
+        Err(err) => println!("Oh no, an error: {}", err),
+    }
+
+    client
+        .close()
+        .await
+        .expect("Not able to close the connection");
+}
+
+// In order to Test go to Home Assistant --> Settings --> Devices & Services, and toggle any entity
+//
+// Subscribed to the state changes: WSResult { id: 1, success: true, result: None, error: None }
+// light.kitchen: off -> on
+// sun.sun: above_horizon -> below_horizon
+//
+// Unsubscribe the state changes
+// Succefully unsubscribed: Ok
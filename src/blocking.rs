@@ -0,0 +1,171 @@
+//! A blocking client, for the simple scripts and command line tools which don't run an async runtime
+
+use crate::client::HassClient;
+use crate::types::{Context, HassConfig, HassEntityState, HassServices, ServiceCall};
+use crate::{ClientConfig, HassError, HassResult};
+
+use serde_json::Value;
+use std::future::Future;
+use tokio::runtime::{Builder, Runtime};
+
+/// Wraps a HassClient and its own single threaded runtime, each method blocks until its command is answered
+///
+/// The connection is only served while a method is running: it suits the scripts sending a few commands,
+/// not the high-throughput uses, nor the subscriptions, whose events would only be read between the calls.
+/// Use the async HassClient for those.
+///
+/// ```
+/// use async_tungstenite::tungstenite::{accept, Message};
+/// use hass_rs::blocking::BlockingClient;
+/// use std::net::TcpListener;
+///
+/// // a mock server, which authenticates the client and answers its service call
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("ws://{}/api/websocket", listener.local_addr().unwrap());
+/// let server = std::thread::spawn(move || {
+///     let mut websocket = accept(listener.accept().unwrap().0).unwrap();
+///     let mut reply = |text: &str| websocket.send(Message::Text(text.to_owned())).unwrap();
+///     reply(r#"{"type": "auth_required", "ha_version": "2024.1.0"}"#);
+///     let _auth = websocket.read().unwrap();
+///     websocket.send(Message::Text(r#"{"type": "auth_ok", "ha_version": "2024.1.0"}"#.to_owned())).unwrap();
+///     let call = websocket.read().unwrap().into_text().unwrap();
+///     websocket.send(Message::Text(
+///         r#"{"id": 1, "type": "result", "success": true, "result": {"context": {"id": "01HPRMZAWN"}}}"#.to_owned(),
+///     )).unwrap();
+///     call
+/// });
+///
+/// let mut client = BlockingClient::connect(&url)?;
+/// client.auth("your_token")?;
+/// client.call_service("light", "turn_on", Some(serde_json::json!({ "entity_id": "light.kitchen" })))?;
+///
+/// assert!(server.join().unwrap().contains(r#""service":"turn_on""#));
+/// # Ok::<(), hass_rs::HassError>(())
+/// ```
+#[derive(Debug)]
+pub struct BlockingClient {
+    runtime: Runtime,
+    client: HassClient,
+}
+
+impl BlockingClient {
+    /// Connects to the Websocket server at `url`, e.g. "ws://localhost:8123/api/websocket"
+    pub fn connect(url: &str) -> HassResult<Self> {
+        BlockingClient::connect_with(ClientConfig::default(), url)
+    }
+
+    /// Connects to the Websocket server at `url` with the `config`, e.g. to set a timeout
+    pub fn connect_with(config: ClientConfig, url: &str) -> HassResult<Self> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|error| {
+                HassError::Generic(format!("Unable to start the runtime: {}", error))
+            })?;
+        let client = runtime.block_on(config.connect(url))?;
+
+        Ok(BlockingClient { runtime, client })
+    }
+
+    /// Authenticates the session with a long-lived access token, see `HassClient::auth_with_longlivedtoken`
+    pub fn auth(&mut self, token: &str) -> HassResult<()> {
+        self.block_on(|client| client.auth_with_longlivedtoken(token))
+    }
+
+    /// Pings the server, see `HassClient::ping`
+    pub fn ping(&mut self) -> HassResult<String> {
+        self.block_on(|client| client.ping())
+    }
+
+    /// Gets the config, see `HassClient::get_config`
+    pub fn get_config(&mut self) -> HassResult<HassConfig> {
+        self.block_on(|client| client.get_config())
+    }
+
+    /// Gets the states of all the entities, see `HassClient::get_states`
+    pub fn get_states(&mut self) -> HassResult<Vec<HassEntityState>> {
+        self.block_on(|client| client.get_states())
+    }
+
+    /// Gets the services, see `HassClient::get_services`
+    pub fn get_services(&mut self) -> HassResult<HassServices> {
+        self.block_on(|client| client.get_services())
+    }
+
+    /// Calls a service, see `HassClient::call_service`
+    pub fn call_service(
+        &mut self,
+        domain: &str,
+        service: &str,
+        service_data: Option<Value>,
+    ) -> HassResult<String> {
+        self.block_on(|client| {
+            client.call_service(domain.to_owned(), service.to_owned(), service_data)
+        })
+    }
+
+    /// Sends a service call made with one of the builders, see `HassClient::call`
+    pub fn call(&mut self, call: ServiceCall) -> HassResult<Context> {
+        self.block_on(|client| client.call(call))
+    }
+
+    /// Closes the connection, see `HassClient::close`
+    pub fn close(self) -> HassResult<()> {
+        let BlockingClient { runtime, client } = self;
+        runtime.block_on(client.close())
+    }
+
+    // runs a method of the async client until it completes
+    fn block_on<'a, F, Fut, T>(&'a mut self, method: F) -> HassResult<T>
+    where
+        F: FnOnce(&'a mut HassClient) -> Fut,
+        Fut: Future<Output = HassResult<T>> + 'a,
+    {
+        self.runtime.block_on(method(&mut self.client))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{entity_state, listen, MockServer};
+    use crate::LightTurnOn;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn a_blocking_client_sends_its_commands_and_returns_their_results() {
+        let (listener, url) = listen().await;
+        // the blocking client runs its own runtime, so it can't block a task of the test's one
+        let client = std::thread::spawn(move || {
+            let mut client = BlockingClient::connect(&url)?;
+            client.auth("token")?;
+            let states = client.get_states()?;
+            let context =
+                client.call(LightTurnOn::new("light.kitchen").brightness_pct(50).build())?;
+            client.close()?;
+            Ok::<_, HassError>((states, context))
+        });
+
+        let mut server = MockServer::accept(&listener).await;
+        server.auth().await;
+        let get_states = server
+            .reply(json!([entity_state("light.kitchen", "off")]))
+            .await;
+        let call = server.reply(json!({"context": {"id": "01HPRMZAWN"}})).await;
+
+        let (states, context) = tokio::task::spawn_blocking(move || client.join().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(get_states["type"], "get_states");
+        assert_eq!(call["type"], "call_service");
+        assert_eq!(call["domain"], "light");
+        assert_eq!(call["service"], "turn_on");
+        assert_eq!(call["service_data"], json!({"brightness_pct": 50}));
+        assert_eq!(call["target"], json!({"entity_id": ["light.kitchen"]}));
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].entity_id, "light.kitchen");
+        assert_eq!(states[0].state, "off");
+        assert_eq!(context.id, "01HPRMZAWN");
+    }
+}
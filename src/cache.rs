@@ -0,0 +1,217 @@
+//! Entity states kept up to date from the `state_changed` events
+
+use crate::types::{HassEntityState, HassEvent, WSEvent};
+use futures_util::stream::{Stream, StreamExt};
+use std::collections::HashMap;
+
+/// Change applied to a StateCache by an event
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    /// The entity was not in the cache yet
+    Added(String),
+    /// The state of the entity was replaced
+    Changed(String),
+    /// The entity was removed, this holds its last state
+    Removed(HassEntityState),
+}
+
+/// Map of the current entity states
+///
+/// It is built from the states returned by `HassClient::get_states` and kept current by applying
+/// the `state_changed` events on top of them. Subscribe to the events before reading the initial
+/// states, so no change is missed in between.
+///
+/// The events come e.g. from `HassClient::event_stream`, after `HassClient::subscribe_event("state_changed")`.
+///
+/// ```
+/// use hass_rs::{StateCache, StateChange, WSEvent};
+///
+/// let states = serde_json::from_str(
+///     r#"[{"entity_id": "light.kitchen", "state": "off", "attributes": {}, "context": null,
+///          "last_changed": "2024-01-01T10:00:00+00:00", "last_updated": "2024-01-01T10:00:00+00:00"}]"#,
+/// )
+/// .unwrap();
+/// let mut cache = StateCache::new(states);
+///
+/// let event: WSEvent = serde_json::from_str(
+///     r#"{"id": 1, "type": "event", "event": {
+///         "event_type": "state_changed", "time_fired": "2024-01-01T10:01:00+00:00",
+///         "origin": "LOCAL", "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"},
+///         "data": {"entity_id": "light.kitchen", "old_state": null, "new_state": {
+///             "entity_id": "light.kitchen", "state": "on", "attributes": {}, "context": null,
+///             "last_changed": "2024-01-01T10:01:00+00:00", "last_updated": "2024-01-01T10:01:00+00:00"}}}}"#,
+/// )
+/// .unwrap();
+/// let mut events = futures::stream::iter(vec![event]);
+///
+/// futures::executor::block_on(async {
+///     let change = cache.apply_next(&mut events).await;
+///     assert_eq!(change, Some(StateChange::Changed("light.kitchen".to_owned())));
+/// });
+/// assert_eq!(cache.get("light.kitchen").unwrap().state, "on");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StateCache {
+    states: HashMap<String, HassEntityState>,
+}
+
+impl StateCache {
+    /// Creates the cache from the initial states
+    pub fn new(states: Vec<HassEntityState>) -> Self {
+        let states = states
+            .into_iter()
+            .map(|state| (state.entity_id.clone(), state))
+            .collect();
+
+        StateCache { states }
+    }
+
+    /// Returns the current state of an entity
+    pub fn get(&self, entity_id: &str) -> Option<&HassEntityState> {
+        self.states.get(entity_id)
+    }
+
+    /// Returns the number of entities in the cache
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns true if the cache holds no entity
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Iterates over the current states
+    pub fn states(&self) -> impl Iterator<Item = &HassEntityState> {
+        self.states.values()
+    }
+
+    /// Applies a `state_changed` event, the other event types are ignored
+    ///
+    /// An event without `new_state` means the entity was removed. An event older than the cached
+    /// state, e.g. received while the initial states were read, is ignored.
+    pub fn apply(&mut self, event: &HassEvent) -> Option<StateChange> {
+        if event.event_type != "state_changed" {
+            return None;
+        }
+
+        let entity_id = &event.data.entity_id;
+        match &event.data.new_state {
+            // the timestamps are ISO 8601 in UTC, they compare as strings
+            Some(state)
+                if self
+                    .states
+                    .get(entity_id)
+                    .is_some_and(|current| state.last_updated < current.last_updated) =>
+            {
+                None
+            }
+            Some(state) => match self.states.insert(entity_id.clone(), state.clone()) {
+                Some(_) => Some(StateChange::Changed(entity_id.clone())),
+                None => Some(StateChange::Added(entity_id.clone())),
+            },
+            None => self.states.remove(entity_id).map(StateChange::Removed),
+        }
+    }
+
+    /// Applies the events of the stream until one changes the cache, and returns that change
+    ///
+    /// Returns None when the stream ended.
+    pub async fn apply_next<S>(&mut self, events: &mut S) -> Option<StateChange>
+    where
+        S: Stream<Item = WSEvent> + Unpin,
+    {
+        while let Some(event) = events.next().await {
+            if let Some(change) = self.apply(&event.event) {
+                return Some(change);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn state(entity_id: &str, state: &str, last_updated: &str) -> serde_json::Value {
+        json!({
+            "entity_id": entity_id, "state": state, "attributes": {},
+            "last_changed": last_updated, "last_updated": last_updated, "context": null
+        })
+    }
+
+    fn event(event_type: &str, entity_id: &str, new_state: serde_json::Value) -> WSEvent {
+        serde_json::from_value(json!({
+            "id": 1,
+            "type": "event",
+            "event": {
+                "data": {"entity_id": entity_id, "new_state": new_state, "old_state": null},
+                "event_type": event_type,
+                "time_fired": "2024-01-01T00:00:00+00:00",
+                "origin": "LOCAL",
+                "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn applies_the_added_changed_and_removed_entities() {
+        let initial = serde_json::from_value(json!([
+            state("light.kitchen", "off", "2024-01-01T10:00:00+00:00"),
+            state("sun.sun", "above_horizon", "2024-01-01T10:00:00+00:00"),
+        ]))
+        .unwrap();
+        let mut cache = StateCache::new(initial);
+
+        let events = vec![
+            event("call_service", "light.kitchen", json!(null)),
+            event(
+                "state_changed",
+                "light.hall",
+                state("light.hall", "on", "2024-01-01T10:01:00+00:00"),
+            ),
+            // older than the cached state, read while the initial states were fetched
+            event(
+                "state_changed",
+                "light.kitchen",
+                state("light.kitchen", "on", "2024-01-01T09:00:00+00:00"),
+            ),
+            event(
+                "state_changed",
+                "light.kitchen",
+                state("light.kitchen", "on", "2024-01-01T10:02:00+00:00"),
+            ),
+            event("state_changed", "sun.sun", json!(null)),
+        ];
+        let mut events = futures::stream::iter(events);
+
+        futures::executor::block_on(async {
+            assert_eq!(
+                cache.apply_next(&mut events).await,
+                Some(StateChange::Added("light.hall".to_owned()))
+            );
+            assert_eq!(
+                cache.apply_next(&mut events).await,
+                Some(StateChange::Changed("light.kitchen".to_owned()))
+            );
+            assert_eq!(cache.get("light.kitchen").unwrap().state, "on");
+
+            match cache.apply_next(&mut events).await {
+                Some(StateChange::Removed(last)) => assert_eq!(last.state, "above_horizon"),
+                other => panic!("expected the removal of the sun, got {:?}", other),
+            }
+            assert_eq!(cache.get("sun.sun"), None);
+            assert_eq!(cache.apply_next(&mut events).await, None);
+        });
+
+        assert_eq!(cache.len(), 2);
+        // removing an entity which is not cached changes nothing
+        assert_eq!(
+            cache.apply(&event("state_changed", "sun.sun", json!(null)).event),
+            None
+        );
+    }
+}
@@ -1,36 +1,153 @@
 //! Home Assistant client implementation
 
+use crate::latency::LatencyWindow;
+use crate::managed::ManagedStateCache;
+use crate::runtime::{
+    channel, receiver_stream, sleep, spawn, spawn_blocking, timeout, BroadcastReceiver,
+    BroadcastSender, Instant,
+};
+use crate::sequence::{AtomicSequence, SequenceSource};
 use crate::types::{
-    Ask, Auth, CallService, Command, HassArea, HassConfig, HassDevice, HassEntity, HassEntityState,
-    HassPanels, HassServices, Response, Subscribe, Unsubscribe, WSEvent,
+    availability_ratio, entity_id_matches, AreaRegistryUpdate, Ask, AskArea, AskEntity,
+    AskStatistics, Auth, CallService, Command, ConfigValidation, Context, CoreConfigUpdate,
+    CreateArea, EntitiesUpdate, EntityRegistryUpdate, EventData, ExecuteScript, HassArea,
+    HassConfig, HassDevice, HassEntity, HassEntityState, HassEvent, HassFloor, HassHistory,
+    HassLabel, HassPanels, HassService, HassServices, HistoryDuringPeriod, Incoming, LogbookEntry,
+    LogbookEvents, MediaPlayerControl, MediaPlayerPlayMedia, MessageType, NotificationsUpdate,
+    PersistentNotification, RegistryChange, RegistrySnapshot, RenderTemplate, Response,
+    ScriptResult, ServiceCall, ServiceCalled, StateTrigger, StatisticMeta, Subscribe,
+    SubscribeEntities, SubscribeLogbook, SubscribeTrigger, Target, TemplateRender, TriggerEvent,
+    Unsubscribe, UpdateArea, UpdateCoreConfig, UpdateDevice, UpdateEntity, UpdatedEntity,
+    ValidateConfig, WSEvent,
+};
+use crate::wsconn::{
+    leading_message_type, resume_listener, Listener, Listeners, Origin, RESET_EVENT,
 };
-use crate::{HassError, HassResult, WSResult};
+use crate::{ClientConfig, HassError, HassResult, LatencyStats, WSResult};
 use crate::{Receiver, Sender};
 
 use async_tungstenite::tungstenite::Error;
 use async_tungstenite::tungstenite::Message as TungsteniteMessage;
-use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
-};
+use futures_util::future::{self, BoxFuture};
+use futures_util::sink::{self, Sink};
+use futures_util::stream::{self, Stream, StreamExt};
+use log::warn;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// delay between the checks of the run state of Home Assistant, while waiting for it to start
+const STARTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// delay before the first retry of a read, doubled after each failed attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// number of characters of a non JSON message included in its error
+const NOT_JSON_SNIPPET_LEN: usize = 100;
+
+// number of messages kept for `incoming` while collecting the responses of a batch, the older ones are dropped
+const UNMATCHED_CAPACITY: usize = 1000;
+
+// capacity of the channels between a client built by `from_channels` and its Sink and Stream
+const CHANNELS_CAPACITY: usize = 20;
+
+// the Websocket API of Home Assistant, as seen from the add-ons
+const SUPERVISOR_URL: &str = "ws://supervisor/core/websocket";
+
+// size from which a message is deserialized on the blocking thread pool of the runtime, in bytes,
+// so a large response (e.g. the get_states of a big instance) doesn't hold up the other tasks
+pub(crate) const LARGE_MESSAGE_SIZE: usize = 64 << 10;
 
 /// HassClient is a library that is meant to simplify the conversation with HomeAssistant Web Socket Server
 /// it provides a number of convenient functions that creates the requests and read the messages from server
+///
+/// Dropping the client drops its command channel, the task writing the commands to the Websocket
+/// should treat the closed channel as a termination signal. When connected by `ClientConfig::connect`,
+/// the Websocket is then closed and the task reading it stops, so no task outlives the client.
 #[derive(Debug)]
 pub struct HassClient {
     // holds the id of the WS message
-    last_sequence: Arc<AtomicU64>,
+    last_sequence: Box<dyn SequenceSource>,
 
     // holds the Events Subscriptions
     pub subscriptions: HashMap<u64, String>,
 
+    // holds the commands of the subscriptions, sent again to renew them on a new connection
+    subscribe_commands: HashMap<u64, Command>,
+
+    // holds the latest command round-trip times
+    latencies: LatencyWindow,
+
+    // holds the last response as received, when enabled
+    keep_raw_responses: bool,
+    last_raw_response: Option<String>,
+
+    // holds the outcome of the latest pings
+    last_pong_rtt: Option<Duration>,
+    missed_heartbeats: u32,
+
+    // set when the server asked to authenticate again in the middle of the session
+    auth_required: bool,
+
+    // set once the server accepted the access token, the commands are refused before
+    authenticated: bool,
+
+    // holds the mutating commands which were not sent, in dry-run mode
+    dry_run: bool,
+    recorded_commands: Vec<Command>,
+
+    // holds the messages received while collecting the responses of a batch, which were not part of it
+    unmatched: VecDeque<Incoming>,
+
     //Client --> Gateway (send "Commands" msg to the Gateway)
     pub(crate) to_gateway: Sender<TungsteniteMessage>,
 
     //Gateway --> Client (receive "Response" msg from the Gateway)
     pub(crate) from_gateway: Receiver<Result<TungsteniteMessage, Error>>,
+
+    //Gateway --> Client (receive "Event" msg from the Gateway, when connected by the client)
+    pub(crate) events: Option<Receiver<WSEvent>>,
+
+    //Gateway --> Client (receive the non-text frames from the Gateway, when enabled)
+    pub(crate) raw_frames: Option<Receiver<TungsteniteMessage>>,
+
+    // holds the listeners of the subscriptions receiving raw events, when connected by the client
+    pub(crate) listeners: Option<Listeners>,
+
+    // feeds the receivers observing all the events, when connected by the client
+    pub(crate) broadcast: Option<BroadcastSender<WSEvent>>,
+
+    // set once the events were taken, they are dropped silently before
+    pub(crate) events_taken: Arc<AtomicBool>,
+
+    // where the client is connected, when connected by the client, to reconnect when enabled by `ClientConfig::reconnect`
+    pub(crate) origin: Option<Origin>,
+}
+
+/// Connects to the Home Assistant Websocket server at `host`:`port`
+///
+/// This uses the default ClientConfig, use it directly to customize the connection (e.g. the `base_path`).
+pub async fn connect(host: &str, port: u16) -> HassResult<HassClient> {
+    ClientConfig::default().connect_to(host, port).await
+}
+
+/// Connects to the Websocket server at `url`, sending the extra `headers` with the handshake request
+///
+/// Use it when Home Assistant is behind a reverse proxy which requires its own headers,
+/// e.g. `("Authorization", "Bearer ...")` or the Cloudflare Access ones.
+pub async fn connect_with_headers(
+    url: &str,
+    headers: Vec<(String, String)>,
+) -> HassResult<HassClient> {
+    headers
+        .iter()
+        .fold(ClientConfig::default(), |config, (name, value)| {
+            config.header(name, value)
+        })
+        .connect(url)
+        .await
 }
 
 impl HassClient {
@@ -38,17 +155,153 @@ impl HassClient {
         tx: Sender<TungsteniteMessage>,
         rx: Receiver<Result<TungsteniteMessage, Error>>,
     ) -> Self {
-        let last_sequence = Arc::new(AtomicU64::new(1));
+        let last_sequence = Box::new(AtomicSequence::default());
         let subscriptions = HashMap::new();
 
         HassClient {
             last_sequence,
             subscriptions,
+            subscribe_commands: HashMap::new(),
+            latencies: LatencyWindow::default(),
+            keep_raw_responses: false,
+            last_raw_response: None,
+            last_pong_rtt: None,
+            missed_heartbeats: 0,
+            auth_required: false,
+            authenticated: false,
+            dry_run: false,
+            recorded_commands: Vec::new(),
+            unmatched: VecDeque::new(),
             to_gateway: tx,
             from_gateway: rx,
+            events: None,
+            raw_frames: None,
+            listeners: None,
+            broadcast: None,
+            events_taken: Arc::default(),
+            origin: None,
         }
     }
 
+    /// Creates a client over any Sink of the commands and Stream of the messages of the server
+    ///
+    /// Unlike `new`, it is not tied to the channels of the runtime: the Sink and the Stream are driven
+    /// by two tasks, which end when the client is dropped or the Stream ends.
+    /// It helps when mixing runtimes, or to drive the client from a test without a server.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> hass_rs::HassResult<()> {
+    /// use async_tungstenite::tungstenite::Message;
+    /// use futures::channel::mpsc;
+    /// use futures::{SinkExt, StreamExt};
+    /// use hass_rs::HassClient;
+    ///
+    /// let (to_server, mut from_client) = mpsc::channel::<Message>(10);
+    /// let (mut to_client, from_server) = mpsc::channel(10);
+    ///
+    /// // a fake server, which authenticates the client and answers its ping
+    /// tokio::spawn(async move {
+    ///     let reply = |text: &str| Ok(Message::Text(text.to_owned()));
+    ///     to_client.send(reply(r#"{"type": "auth_required", "ha_version": "2024.1.0"}"#)).await.unwrap();
+    ///     from_client.next().await.unwrap();
+    ///     to_client.send(reply(r#"{"type": "auth_ok", "ha_version": "2024.1.0"}"#)).await.unwrap();
+    ///     from_client.next().await.unwrap();
+    ///     to_client.send(reply(r#"{"id": 1, "type": "pong"}"#)).await.unwrap();
+    /// });
+    ///
+    /// let mut client = HassClient::from_channels(to_server, from_server);
+    /// client.auth_with_longlivedtoken("token").await?;
+    /// assert_eq!(client.ping().await?, "pong");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_channels<Si, St>(sink: Si, stream: St) -> Self
+    where
+        Si: Sink<TungsteniteMessage> + Send + 'static,
+        St: Stream<Item = Result<TungsteniteMessage, Error>> + Send + 'static,
+    {
+        let (to_sink, from_client) = channel::<TungsteniteMessage>(CHANNELS_CAPACITY);
+        let (to_client, from_stream) =
+            channel::<Result<TungsteniteMessage, Error>>(CHANNELS_CAPACITY);
+
+        spawn(async move {
+            // the Sink is dropped, and so closed, once the client is dropped
+            let _ = receiver_stream(from_client).map(Ok).forward(sink).await;
+        });
+        spawn(async move {
+            let mut stream = Box::pin(stream);
+            while let Some(message) = stream.next().await {
+                if to_client.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        HassClient::new(to_sink, from_stream)
+    }
+
+    /// Connects to Home Assistant from an add-on, through the Supervisor, and authenticates.
+    ///
+    /// The Supervisor provides the add-ons with the `SUPERVISOR_TOKEN` environment variable,
+    /// `HassError::SupervisorTokenMissing` is returned when it is not set.
+    /// The add-on needs `homeassistant_api: true` in its configuration to be given access to the API.
+    pub async fn connect_supervisor() -> HassResult<HassClient> {
+        HassClient::connect_supervisor_at(SUPERVISOR_URL).await
+    }
+
+    // connects to the Supervisor proxy at `url`, with the token provided to the add-on
+    pub(crate) async fn connect_supervisor_at(url: &str) -> HassResult<HassClient> {
+        let token =
+            std::env::var("SUPERVISOR_TOKEN").map_err(|_| HassError::SupervisorTokenMissing)?;
+
+        ClientConfig::default().connect_and_auth(url, &token).await
+    }
+
+    /// Replaces the source of the message ids, by default the ids count up from 1
+    ///
+    /// Use it before sending any command, e.g. to get deterministic ids or to keep them increasing across connections.
+    pub fn with_sequence(mut self, sequence: impl SequenceSource + 'static) -> Self {
+        self.last_sequence = Box::new(sequence);
+        self
+    }
+
+    /// Keeps the raw JSON of the last `result` received, see `last_raw_response`.
+    ///
+    /// It is disabled by default, as the responses can be large (e.g. get_states).
+    pub fn with_raw_responses(mut self, enabled: bool) -> Self {
+        self.keep_raw_responses = enabled;
+        self
+    }
+
+    /// Returns the raw JSON of the last `result` received, when enabled with `with_raw_responses`.
+    ///
+    /// The other messages, e.g. the events and the pongs, are not kept.
+    /// Useful to find out what didn't match the types when a command fails with `UnableToDeserialize`,
+    /// e.g. after a Home Assistant upgrade.
+    pub fn last_raw_response(&self) -> Option<&str> {
+        self.last_raw_response.as_deref()
+    }
+
+    /// Enables or disables the dry-run mode, disabled by default
+    ///
+    /// In dry-run mode the commands changing something in Home Assistant (calling a service or a script,
+    /// updating the registries or the core configuration) are not sent, but recorded, see `recorded_commands`,
+    /// and they succeed with a made up result. The reads are still sent, so automations can be tested
+    /// against a real instance without side effects.
+    ///
+    /// The made up result is recognizable: its id is 0, which no sent command has, and it holds
+    /// a context whose id is "dry-run", e.g. the `Context` returned by `call`.
+    /// The commands returning the updated registry entry fail to read it from that result.
+    pub fn set_dry_run(&mut self, enabled: bool) {
+        self.dry_run = enabled;
+    }
+
+    /// Returns the commands which were not sent in dry-run mode, in order
+    pub fn recorded_commands(&self) -> &[Command] {
+        &self.recorded_commands
+    }
+
     /// authenticate the session using a long-lived access token
     ///
     /// When a client connects to the server, the server sends out auth_required.
@@ -57,12 +310,23 @@ impl HassClient {
     /// If the data is incorrect, the server will reply with auth_invalid message and disconnect the session.
 
     pub async fn auth_with_longlivedtoken(&mut self, token: &str) -> HassResult<()> {
-        // Auth Request from Gateway { "type": "auth_required"}
-        if let Ok(Response::AuthRequired(msg)) = self.ws_receive().await {
-            if msg.msg_type != "auth_required".to_string() {
-                return Err(HassError::Generic(
-                    "Expecting the first message from server to be auth_required".to_string(),
-                ));
+        if let Some(origin) = &mut self.origin {
+            origin.token = Some(token.to_owned());
+        }
+        self.send_auth(token).await
+    }
+
+    // authenticates without going through `command`, which reconnects with it
+    async fn send_auth(&mut self, token: &str) -> HassResult<()> {
+        // Auth Request from Gateway { "type": "auth_required"}, unless it was already received mid-session
+        let already_required = std::mem::take(&mut self.auth_required);
+        if !already_required {
+            if let Ok(Response::AuthRequired(msg)) = self.ws_receive().await {
+                if msg.msg_type != "auth_required".to_string() {
+                    return Err(HassError::Generic(
+                        "Expecting the first message from server to be auth_required".to_string(),
+                    ));
+                }
             }
         }
 
@@ -72,21 +336,87 @@ impl HassClient {
             access_token: token.to_owned(),
         });
 
-        let response = self.command(auth_message).await?;
+        let response = self.send_command(auth_message).await?;
 
         //Check if the authetication was succefully, should receive {"type": "auth_ok"}
         match response {
-            Response::AuthOk(_) => Ok(()),
-            Response::AuthInvalid(err) => return Err(HassError::AuthenticationFailed(err.message)),
+            Response::AuthOk(_) => {
+                self.authenticated = true;
+                Ok(())
+            }
+            Response::AuthInvalid(err) => {
+                // some proxies strip the message of auth_invalid
+                let message = err
+                    .message
+                    .unwrap_or_else(|| "Invalid access token or password".to_owned());
+                return Err(HassError::AuthenticationFailed(message));
+            }
             _ => return Err(HassError::UnknownPayloadReceived),
         }
     }
 
+    /// Returns true once the session is authenticated, the other commands fail before.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
     /// The API supports receiving a ping from the client and returning a pong.
     /// This serves as a heartbeat to ensure the connection is still alive.
-
+    ///
+    /// The outcome is tracked by `last_pong_rtt` and `missed_heartbeats`.
     pub async fn ping(&mut self) -> HassResult<String> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        let sent_at = Instant::now();
+        let result = self.send_ping().await;
+
+        match &result {
+            Ok(_) => {
+                self.last_pong_rtt = Some(sent_at.elapsed());
+                self.missed_heartbeats = 0;
+            }
+            Err(_) => self.missed_heartbeats = self.missed_heartbeats.saturating_add(1),
+        }
+        result
+    }
+
+    /// Pings the server like `ping`, but gives up when the pong is not received within `deadline`
+    ///
+    /// A missing pong means the connection is stalled, e.g. behind a proxy which didn't notice the server is gone,
+    /// and the connection is then considered dead: a late pong would be taken as the response of the next command.
+    /// With `ClientConfig::reconnect` the connection is reopened and pinged again, within the same `deadline`,
+    /// otherwise `HassError::Timeout` is returned. `ManagedStateCache` reconnects in that case too.
+    pub async fn ping_within(&mut self, deadline: Duration) -> HassResult<String> {
+        if let Some(result) = timeout(deadline, self.ping()).await {
+            return result;
+        }
+        self.missed_heartbeats = self.missed_heartbeats.saturating_add(1);
+        if !self.origin.as_ref().is_some_and(Origin::reconnects) || !self.authenticated {
+            return Err(HassError::Timeout);
+        }
+
+        warn!(
+            "No pong was received within {:?}, the connection is stalled",
+            deadline
+        );
+        self.reconnect().await?;
+        timeout(deadline, self.ping())
+            .await
+            .unwrap_or(Err(HassError::Timeout))
+    }
+
+    /// Returns the round-trip time of the last ping answered by the server
+    pub fn last_pong_rtt(&self) -> Option<Duration> {
+        self.last_pong_rtt
+    }
+
+    /// Returns the number of pings which failed since the last one answered by the server
+    ///
+    /// A rising count is an early sign of a connection about to be lost.
+    pub fn missed_heartbeats(&self) -> u32 {
+        self.missed_heartbeats
+    }
+
+    async fn send_ping(&mut self) -> HassResult<String> {
+        let id = self.next_id();
 
         //Send Ping command and expect Pong
         let ping_req = Command::Ping(Ask {
@@ -109,7 +439,7 @@ impl HassClient {
     /// The server will respond with a result message containing the config.
 
     pub async fn get_config(&mut self) -> HassResult<HassConfig> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        let id = self.next_id();
 
         //Send GetConfig command and expect Pong
         let config_req = Command::GetConfig(Ask {
@@ -121,9 +451,7 @@ impl HassClient {
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let config: HassConfig = serde_json::from_value(
-                        data.result.expect("Expecting to get the HassConfig"),
-                    )?;
+                    let config: HassConfig = data.typed_result()?;
                     return Ok(config);
                 }
                 false => return Err(HassError::ReponseError(data)),
@@ -132,6 +460,86 @@ impl HassClient {
         }
     }
 
+    /// This will update the core configuration of Home Assistant, e.g. its location.
+    ///
+    /// Only the fields set in `changes` are updated, this requires an admin user.
+    pub async fn update_core_config(&mut self, changes: CoreConfigUpdate) -> HassResult<()> {
+        let id = self.next_id();
+
+        let update_req = Command::UpdateCoreConfig(UpdateCoreConfig {
+            id: Some(id),
+            msg_type: "config/core/update".to_owned(),
+            changes,
+        });
+        let response = self.command(update_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => Ok(()),
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will wait until Home Assistant is fully started, i.e. its integrations are loaded.
+    ///
+    /// The client subscribes to the `homeassistant_started` event, then checks the run state of the config,
+    /// again every second until the event is received. The versions which don't report their run state
+    /// are considered started. `HassError::Timeout` is returned if Home Assistant is still starting
+    /// after `deadline`, including when a command is left unanswered.
+    /// Without the subscriptions, e.g. for a client built with `from_channels`, the run state is only checked.
+    pub async fn wait_until_started(&mut self, deadline: Duration) -> HassResult<()> {
+        let until = Instant::now() + deadline;
+        let remaining = || until.saturating_duration_since(Instant::now());
+
+        // subscribed first, so the event fired while the config is read is not missed
+        let mut subscription = None;
+        if self.listeners.is_some() {
+            let id = self.next_id();
+            let cmd = Command::SubscribeEvent(Subscribe {
+                id: Some(id),
+                msg_type: "subscribe_events".to_owned(),
+                event_type: Some("homeassistant_started".to_owned()),
+            });
+            let subscribe = self.subscribe_listener(id, cmd, "homeassistant_started");
+            let (id, events) = within(remaining(), subscribe).await?;
+            subscription = Some((id, Box::pin(events)));
+        }
+
+        let started = loop {
+            match within(remaining(), self.get_config()).await {
+                Ok(config) if config.state.as_deref().unwrap_or("RUNNING") == "RUNNING" => {
+                    break Ok(())
+                }
+                Ok(_) => {}
+                Err(error) => break Err(error),
+            }
+
+            let wait = remaining().min(STARTED_POLL_INTERVAL);
+            if wait.is_zero() {
+                break Err(HassError::Timeout);
+            }
+            match subscription.as_mut() {
+                Some((_, events)) => match timeout(wait, events.next()).await {
+                    Some(Some(_)) => break Ok(()),
+                    // the connection was lost, the next check tells
+                    Some(None) => subscription = None,
+                    None => {}
+                },
+                None => sleep(wait).await,
+            }
+        };
+
+        if let Some((id, _)) = subscription {
+            let unsubscribed = self.unsubscribe_event(id).await;
+            started?;
+            unsubscribed?;
+            return Ok(());
+        }
+        started
+    }
+
     /// This will get a dump of all the current areas in Home Assistant.
     ///
     /// The server will respond with a result message containing the areas.
@@ -157,8 +565,10 @@ impl HassClient {
     /// }
     /// ```
     pub async fn get_area_registry(&mut self) -> HassResult<Vec<HassArea>> {
+        let id = self.next_id();
+
         let config_req = Command::GetConfig(Ask {
-            id: Some(0),
+            id: Some(id),
             msg_type: "config/area_registry/list".to_owned(),
         });
         let response = self.command(config_req).await?;
@@ -166,8 +576,7 @@ impl HassClient {
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let areas =
-                        serde_json::from_value(data.result.expect("Expecting to get HassArea"))?;
+                    let areas = data.typed_result()?;
                     Ok(areas)
                 }
                 false => Err(HassError::ReponseError(data)),
@@ -176,6 +585,153 @@ impl HassClient {
         }
     }
 
+    /// This will get a dump of all the current floors in Home Assistant.
+    ///
+    /// The server will respond with a result message containing the floors.
+    pub async fn get_floor_registry(&mut self) -> HassResult<Vec<HassFloor>> {
+        let id = self.next_id();
+
+        let config_req = Command::GetConfig(Ask {
+            id: Some(id),
+            msg_type: "config/floor_registry/list".to_owned(),
+        });
+        let response = self.command(config_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let floors = data.typed_result()?;
+                    Ok(floors)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will get a dump of all the current labels in Home Assistant.
+    ///
+    /// The server will respond with a result message containing the labels.
+    pub async fn get_label_registry(&mut self) -> HassResult<Vec<HassLabel>> {
+        let id = self.next_id();
+
+        let config_req = Command::GetConfig(Ask {
+            id: Some(id),
+            msg_type: "config/label_registry/list".to_owned(),
+        });
+        let response = self.command(config_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let labels = data.typed_result()?;
+                    Ok(labels)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will fetch all the registries (areas, floors, devices, entities and labels) in one snapshot.
+    ///
+    /// The snapshot is serializable, to be persisted and compared with a later one.
+    /// The registries are fetched one after the other, a change made meanwhile may be partially included.
+    pub async fn snapshot_registries(&mut self) -> HassResult<RegistrySnapshot> {
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or_default();
+
+        Ok(RegistrySnapshot {
+            taken_at,
+            areas: self.get_area_registry().await?,
+            floors: self.get_floor_registry().await?,
+            devices: self.get_device_registry().await?,
+            entities: self.get_entity_registry().await?,
+            labels: self.get_label_registry().await?,
+        })
+    }
+
+    /// This will create a new area in Home Assistant.
+    ///
+    /// The server will respond with a result message containing the created area.
+    pub async fn create_area(&mut self, name: &str, aliases: Vec<String>) -> HassResult<HassArea> {
+        let id = self.next_id();
+
+        let create_req = Command::CreateArea(CreateArea {
+            id: Some(id),
+            msg_type: "config/area_registry/create".to_owned(),
+            name: name.to_owned(),
+            aliases,
+        });
+        let response = self.command(create_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let area = data.typed_result()?;
+                    Ok(area)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will update an existing area in Home Assistant.
+    ///
+    /// Only the fields set in `changes` are updated.
+    /// The server will respond with a result message containing the updated area.
+    pub async fn update_area(
+        &mut self,
+        area_id: &str,
+        changes: AreaRegistryUpdate,
+    ) -> HassResult<HassArea> {
+        let id = self.next_id();
+
+        let update_req = Command::UpdateArea(UpdateArea {
+            id: Some(id),
+            msg_type: "config/area_registry/update".to_owned(),
+            area_id: area_id.to_owned(),
+            changes,
+        });
+        let response = self.command(update_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let area = data.typed_result()?;
+                    Ok(area)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will delete an area from Home Assistant.
+    ///
+    /// The server will respond with a result message indicating the area is deleted.
+    pub async fn delete_area(&mut self, area_id: &str) -> HassResult<()> {
+        let id = self.next_id();
+
+        let delete_req = Command::DeleteArea(AskArea {
+            id: Some(id),
+            msg_type: "config/area_registry/delete".to_owned(),
+            area_id: area_id.to_owned(),
+        });
+        let response = self.command(delete_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => Ok(()),
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
     /// This will get a dump of all the current devices in Home Assistant.
     ///
     /// The server will respond with a result message containing the devices.
@@ -201,8 +757,10 @@ impl HassClient {
     /// }
     /// ```
     pub async fn get_device_registry(&mut self) -> HassResult<Vec<HassDevice>> {
+        let id = self.next_id();
+
         let config_req = Command::GetConfig(Ask {
-            id: Some(0),
+            id: Some(id),
             msg_type: "config/device_registry/list".to_owned(),
         });
         let response = self.command(config_req).await?;
@@ -210,8 +768,7 @@ impl HassClient {
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let devices =
-                        serde_json::from_value(data.result.expect("Expecting to get HassDevice"))?;
+                    let devices = data.typed_result()?;
                     Ok(devices)
                 }
                 false => Err(HassError::ReponseError(data)),
@@ -220,6 +777,37 @@ impl HassClient {
         }
     }
 
+    /// This will assign a device to an area in Home Assistant.
+    ///
+    /// Passing `None` as `area_id` clears the area of the device.
+    /// The server will respond with a result message containing the updated device.
+    pub async fn assign_device_area(
+        &mut self,
+        device_id: &str,
+        area_id: Option<&str>,
+    ) -> HassResult<HassDevice> {
+        let id = self.next_id();
+
+        let update_req = Command::UpdateDevice(UpdateDevice {
+            id: Some(id),
+            msg_type: "config/device_registry/update".to_owned(),
+            device_id: device_id.to_owned(),
+            area_id: area_id.map(ToOwned::to_owned),
+        });
+        let response = self.command(update_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let device = data.typed_result()?;
+                    Ok(device)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
     /// This will get a dump of all the current entities in Home Assistant.
     ///
     /// The server will respond with a result message containing the entities.
@@ -245,8 +833,10 @@ impl HassClient {
     /// }
     /// ```
     pub async fn get_entity_registry(&mut self) -> HassResult<Vec<HassEntity>> {
+        let id = self.next_id();
+
         let config_req = Command::GetConfig(Ask {
-            id: Some(0),
+            id: Some(id),
             msg_type: "config/entity_registry/list".to_owned(),
         });
         let response = self.command(config_req).await?;
@@ -254,8 +844,7 @@ impl HassClient {
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let entities =
-                        serde_json::from_value(data.result.expect("Expecting to get HassEntity"))?;
+                    let entities = data.typed_result()?;
                     Ok(entities)
                 }
                 false => Err(HassError::ReponseError(data)),
@@ -264,80 +853,217 @@ impl HassClient {
         }
     }
 
-    /// This will get all the current states from Home Assistant.
+    /// This will list the entities in the area `area_id`, the ones assigned to it and the ones of its devices.
     ///
-    /// The server will respond with a result message containing the states.
+    /// Like when targeting an area, the area of an entity overrides the one of its device, see `HassEntity::area`.
+    pub async fn entities_in_area(&mut self, area_id: &str) -> HassResult<Vec<HassEntity>> {
+        let devices = self.get_device_registry().await?;
+        let entities = self.get_entity_registry().await?;
 
-    pub async fn get_states(&mut self) -> HassResult<Vec<HassEntityState>> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        Ok(entities
+            .into_iter()
+            .filter(|entity| entity.area(&devices) == Some(area_id))
+            .collect())
+    }
 
-        //Send GetStates command and expect a number of Entities
-        let states_req = Command::GetStates(Ask {
+    /// This will group the entities of the entity registry under the device they belong to.
+    ///
+    /// Both the device and the entity registries are fetched, so every known device is present
+    /// in the map, even when it has no entities. The entities without a device are under `None`.
+    pub async fn entities_by_device(
+        &mut self,
+    ) -> HassResult<HashMap<Option<String>, Vec<HassEntity>>> {
+        let devices = self.get_device_registry().await?;
+        let entities = self.get_entity_registry().await?;
+
+        let mut grouped: HashMap<Option<String>, Vec<HassEntity>> = devices
+            .into_iter()
+            .map(|device| (Some(device.id), Vec::new()))
+            .collect();
+        for entity in entities {
+            grouped
+                .entry(entity.device_id.clone())
+                .or_default()
+                .push(entity);
+        }
+
+        Ok(grouped)
+    }
+
+    /// This will get a single entity from the Home Assistant entity registry.
+    ///
+    /// The server will respond with a result message containing the entity,
+    /// `None` is returned when the entity is not registered.
+    pub async fn get_entity(&mut self, entity_id: &str) -> HassResult<Option<HassEntity>> {
+        let id = self.next_id();
+
+        let entity_req = Command::GetEntity(AskEntity {
             id: Some(id),
-            msg_type: "get_states".to_owned(),
+            msg_type: "config/entity_registry/get".to_owned(),
+            entity_id: entity_id.to_owned(),
         });
-        let response = self.command(states_req).await?;
+        let response = self.command(entity_req).await?;
 
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let states: Vec<HassEntityState> =
-                        serde_json::from_value(data.result.expect("Expecting to get the States"))?;
-                    return Ok(states);
+                    let entity = data.typed_result()?;
+                    Ok(Some(entity))
                 }
-                false => return Err(HassError::ReponseError(data)),
+                false if is_not_found(&data) => Ok(None),
+                false => Err(HassError::ReponseError(data)),
             },
-            _ => return Err(HassError::UnknownPayloadReceived),
+            _ => Err(HassError::UnknownPayloadReceived),
         }
     }
 
-    /// This will get all the services from Home Assistant.
+    /// This will enable or disable an entity in Home Assistant.
     ///
-    /// The server will respond with a result message containing the services.
+    /// A disabled entity is marked as disabled by the user.
+    /// The server will respond with a result message containing the updated entity.
+    pub async fn set_entity_enabled(
+        &mut self,
+        entity_id: &str,
+        enabled: bool,
+    ) -> HassResult<HassEntity> {
+        let disabled_by = match enabled {
+            true => None,
+            false => Some("user".to_owned()),
+        };
+        let changes = EntityRegistryUpdate {
+            disabled_by: Some(disabled_by),
+            ..Default::default()
+        };
+        self.update_entity(entity_id, changes).await
+    }
 
-    pub async fn get_services(&mut self) -> HassResult<HassServices> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
-        //Send GetStates command and expect a number of Entities
-        let services_req = Command::GetServices(Ask {
+    /// This will update an entity registry entry in Home Assistant.
+    ///
+    /// Only the fields set in `changes` are updated, e.g. to rename, set the area or disable the entity.
+    /// The server will respond with a result message containing the updated entity.
+    pub async fn update_entity(
+        &mut self,
+        entity_id: &str,
+        changes: EntityRegistryUpdate,
+    ) -> HassResult<HassEntity> {
+        let id = self.next_id();
+
+        let update_req = Command::UpdateEntity(UpdateEntity {
             id: Some(id),
-            msg_type: "get_services".to_owned(),
+            msg_type: "config/entity_registry/update".to_owned(),
+            entity_id: entity_id.to_owned(),
+            changes,
         });
-        let response = self.command(services_req).await?;
+        let response = self.command(update_req).await?;
 
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let services: HassServices = serde_json::from_value(
-                        data.result.expect("Expecting to get the Services"),
-                    )?;
-                    return Ok(services);
+                    let updated: UpdatedEntity = data.typed_result()?;
+                    Ok(updated.entity_entry)
                 }
-                false => return Err(HassError::ReponseError(data)),
+                false => Err(HassError::ReponseError(data)),
             },
-            _ => return Err(HassError::UnknownPayloadReceived),
+            _ => Err(HassError::UnknownPayloadReceived),
         }
     }
 
-    /// This will get all the registered panels from Home Assistant.
+    /// This will customize how an entity is displayed, the `key` being `friendly_name` or `icon`.
     ///
-    /// The server will respond with a result message containing the current registered panels.
+    /// The customization is stored in the entity registry, so it persists across restarts without
+    /// reloading the configuration, and it takes precedence over the name and the icon set by the integration.
+    /// Passing `None` as `value` removes the customization. Only the entities with a unique id are in
+    /// the registry, the other ones can only be customized in `customize.yaml`.
+    pub async fn set_entity_customization(
+        &mut self,
+        entity_id: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> HassResult<HassEntity> {
+        let value = value.map(str::to_owned);
+        let changes = match key {
+            "friendly_name" | "name" => EntityRegistryUpdate {
+                name: Some(value),
+                ..Default::default()
+            },
+            "icon" => EntityRegistryUpdate {
+                icon: Some(value),
+                ..Default::default()
+            },
+            _ => {
+                return Err(HassError::Generic(format!(
+                    "{} can't be customized through the entity registry, use customize.yaml",
+                    key
+                )))
+            }
+        };
+        self.update_entity(entity_id, changes).await
+    }
 
-    pub async fn get_panels(&mut self) -> HassResult<HassPanels> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+    /// This will assign a number of entities to an area in Home Assistant.
+    ///
+    /// Passing `None` as `area_id` clears the area of the entities.
+    /// An update is issued for every entity, and the results are returned in the same order.
+    pub async fn assign_entities_area(
+        &mut self,
+        entity_ids: &[&str],
+        area_id: Option<&str>,
+    ) -> Vec<HassResult<HassEntity>> {
+        let mut results = Vec::with_capacity(entity_ids.len());
+
+        for entity_id in entity_ids {
+            let changes = EntityRegistryUpdate {
+                area_id: Some(area_id.map(ToOwned::to_owned)),
+                ..Default::default()
+            };
+            results.push(self.update_entity(entity_id, changes).await);
+        }
+
+        results
+    }
+
+    /// This will remove an entity from the Home Assistant entity registry.
+    ///
+    /// The server will respond with a result message indicating the removal is done.
+    pub async fn remove_entity(&mut self, entity_id: &str) -> HassResult<()> {
+        let id = self.next_id();
+
+        let remove_req = Command::RemoveEntity(AskEntity {
+            id: Some(id),
+            msg_type: "config/entity_registry/remove".to_owned(),
+            entity_id: entity_id.to_owned(),
+        });
+        let response = self.command(remove_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => Ok(()),
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will get all the current states from Home Assistant.
+    ///
+    /// The server will respond with a result message containing the states.
+    /// On a big instance it is several MB: like any message larger than 64 KiB, it is parsed
+    /// on the blocking thread pool of the runtime, so the events keep being delivered meanwhile.
+    pub async fn get_states(&mut self) -> HassResult<Vec<HassEntityState>> {
+        let id = self.next_id();
 
         //Send GetStates command and expect a number of Entities
-        let services_req = Command::GetPanels(Ask {
+        let states_req = Command::GetStates(Ask {
             id: Some(id),
-            msg_type: "get_panels".to_owned(),
+            msg_type: "get_states".to_owned(),
         });
-        let response = self.command(services_req).await?;
+        let response = self.command(states_req).await?;
 
         match response {
             Response::Result(data) => match data.success {
                 true => {
-                    let services: HassPanels =
-                        serde_json::from_value(data.result.expect("Expecting panels"))?;
-                    return Ok(services);
+                    let states: Vec<HassEntityState> = data.typed_result()?;
+                    return Ok(states);
                 }
                 false => return Err(HassError::ReponseError(data)),
             },
@@ -345,203 +1071,4390 @@ impl HassClient {
         }
     }
 
-    ///This will call a service in Home Assistant. Right now there is no return value.
-    ///The client can listen to state_changed events if it is interested in changed entities as a result of a service call.
+    /// This will check whether the entity currently has a state, which is neither `unavailable` nor `unknown`.
     ///
-    ///The server will indicate with a message indicating that the service is done executing.
-    /// https://developers.home-assistant.io/docs/api/websocket#calling-a-service
-    /// additional info : https://developers.home-assistant.io/docs/api/rest ==> Post /api/services/<domain>/<service>
+    /// All the states are fetched, as the Websocket API can't get a single one.
+    /// `false` is returned when the entity doesn't exist.
+    pub async fn entity_available(&mut self, entity_id: &str) -> HassResult<bool> {
+        let states = self.get_states().await?;
 
-    pub async fn call_service(
+        Ok(states
+            .iter()
+            .any(|state| state.entity_id == entity_id && state.is_available()))
+    }
+
+    /// This will get both the entity registry entry and the current state of an entity.
+    ///
+    /// Either can be missing: an entity created by a YAML integration without a unique id has no registry entry,
+    /// and a registered entity which is disabled or not loaded yet has no state. All the states are fetched,
+    /// as the Websocket API can't get a single one.
+    pub async fn entity_with_state(
         &mut self,
-        domain: String,
-        service: String,
-        service_data: Option<Value>,
-    ) -> HassResult<String> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        entity_id: &str,
+    ) -> HassResult<(Option<HassEntity>, Option<HassEntityState>)> {
+        let entity = self.get_entity(entity_id).await?;
+        let state = self
+            .get_states()
+            .await?
+            .into_iter()
+            .find(|state| state.entity_id == entity_id);
+
+        Ok((entity, state))
+    }
+
+    /// This will list the domains of the entities which currently have a state, sorted and without duplicates.
+    ///
+    /// Unlike `HassServices::list_domains`, which lists the domains providing services,
+    /// this only includes the domains with entities, e.g. to group them on a dashboard.
+    pub async fn list_domains(&mut self) -> HassResult<Vec<String>> {
+        let states = self.get_states().await?;
+
+        let domains: BTreeSet<&str> = states.iter().map(HassEntityState::domain).collect();
+        Ok(domains.into_iter().map(str::to_owned).collect())
+    }
+
+    /// This will get the metadata of the statistics recorded by Home Assistant.
+    ///
+    /// When `statistic_ids` is `None` the metadata of all the statistics is returned,
+    /// which is useful to discover the statistic ids before querying them.
+    /// The statistics are listed with `recorder/list_statistic_ids`, which can't select them by id:
+    /// the `statistic_ids` are then kept from the whole list.
+    pub async fn get_statistics_metadata(
+        &mut self,
+        statistic_ids: Option<Vec<String>>,
+    ) -> HassResult<Vec<StatisticMeta>> {
+        let id = self.next_id();
+
+        let metadata_req = Command::GetStatisticsMetadata(AskStatistics {
+            id: Some(id),
+            msg_type: "recorder/list_statistic_ids".to_owned(),
+            statistic_ids: None,
+        });
+        let response = self.command(metadata_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let mut metadata: Vec<StatisticMeta> = data.typed_result()?;
+                    if let Some(statistic_ids) = statistic_ids {
+                        metadata.retain(|meta| statistic_ids.contains(&meta.statistic_id));
+                    }
+                    Ok(metadata)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will get all the services from Home Assistant.
+    ///
+    /// The server will respond with a result message containing the services.
 
+    pub async fn get_services(&mut self) -> HassResult<HassServices> {
+        let id = self.next_id();
         //Send GetStates command and expect a number of Entities
-        let services_req = Command::CallService(CallService {
+        let services_req = Command::GetServices(Ask {
             id: Some(id),
-            msg_type: "call_service".to_owned(),
-            domain,
-            service,
-            service_data,
+            msg_type: "get_services".to_owned(),
         });
         let response = self.command(services_req).await?;
 
         match response {
             Response::Result(data) => match data.success {
-                true => return Ok("command executed successfully".to_owned()),
+                true => {
+                    let services: HassServices = data.typed_result()?;
+                    return Ok(services);
+                }
                 false => return Err(HassError::ReponseError(data)),
             },
             _ => return Err(HassError::UnknownPayloadReceived),
         }
     }
 
-    /// The command subscribe_event will subscribe your client to the event bus.
+    /// This will get the description of a single service, e.g. to build a form for it.
     ///
-    /// You can either listen to all events or to a specific event type.
-    /// If you want to listen to multiple event types, you will have to send multiple subscribe_events commands.
-    /// The server will respond with a result message to indicate that the subscription is active.
-    /// For each event that matches, the server will send a message of type event.
-    /// The id in the message will point at the original id of the listen_event command.
+    /// The Websocket API can't fetch a single service, so all of them are fetched, then only
+    /// the requested one is kept. `None` is returned when the service doesn't exist.
+    pub async fn get_service(
+        &mut self,
+        domain: &str,
+        service: &str,
+    ) -> HassResult<Option<HassService>> {
+        let mut services = self.get_services().await?;
 
-    pub async fn subscribe_event(&mut self, event_name: &str) -> HassResult<WSResult> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        Ok(services
+            .0
+            .remove(domain)
+            .and_then(|mut domain_services| domain_services.remove(service)))
+    }
 
-        //create the Event Subscribe Command
-        let cmd = Command::SubscribeEvent(Subscribe {
+    /// This will get all the registered panels from Home Assistant.
+    ///
+    /// The server will respond with a result message containing the current registered panels.
+
+    pub async fn get_panels(&mut self) -> HassResult<HassPanels> {
+        let id = self.next_id();
+
+        //Send GetStates command and expect a number of Entities
+        let services_req = Command::GetPanels(Ask {
             id: Some(id),
-            msg_type: "subscribe_events".to_owned(),
-            event_type: event_name.to_owned(),
+            msg_type: "get_panels".to_owned(),
         });
+        let response = self.command(services_req).await?;
 
-        //send command to subscribe to specific event
-        let response = self.command(cmd).await.unwrap();
-
-        //Add the callback in the event_listeners hashmap if the Subscription Response is successfull
         match response {
-            Response::Result(v) if v.success == true => {
-                self.subscriptions.insert(v.id, event_name.to_owned());
-                return Ok(v);
-            }
-            Response::Result(v) if v.success == false => return Err(HassError::ReponseError(v)),
+            Response::Result(data) => match data.success {
+                true => {
+                    let services: HassPanels = data.typed_result()?;
+                    return Ok(services);
+                }
+                false => return Err(HassError::ReponseError(data)),
+            },
             _ => return Err(HassError::UnknownPayloadReceived),
         }
     }
 
-    ///The command unsubscribe_event will unsubscribe your client from the event bus.
-    ///
-    /// You can unsubscribe from previously created subscription events.
-    /// Pass the id of the original subscription command as value to the subscription field.
+    /// This will get the persistent notifications, shown in the sidebar of the frontend.
+    pub async fn get_persistent_notifications(
+        &mut self,
+    ) -> HassResult<Vec<PersistentNotification>> {
+        let id = self.next_id();
+        let notifications_req = Command::GetNotifications(Ask {
+            id: Some(id),
+            msg_type: "persistent_notification/get".to_owned(),
+        });
+        let response = self.command(notifications_req).await?;
 
-    pub async fn unsubscribe_event(&mut self, subscription_id: u64) -> HassResult<String> {
-        let id = get_last_seq(&self.last_sequence).expect("could not read the Atomic value");
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let notifications = data.typed_result()?;
+                    Ok(notifications)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
 
-        //Unsubscribe the Event
-        let unsubscribe_req = Command::Unsubscribe(Unsubscribe {
+    /// This will create a persistent notification, or replace the one with the same `notification_id`.
+    pub async fn create_persistent_notification(
+        &mut self,
+        message: &str,
+        title: Option<&str>,
+        notification_id: Option<&str>,
+    ) -> HassResult<String> {
+        let mut service_data = json!({ "message": message });
+        if let Some(title) = title {
+            service_data["title"] = json!(title);
+        }
+        if let Some(notification_id) = notification_id {
+            service_data["notification_id"] = json!(notification_id);
+        }
+        self.call_service(
+            "persistent_notification".to_owned(),
+            "create".to_owned(),
+            Some(service_data),
+        )
+        .await
+    }
+
+    /// This will dismiss a persistent notification.
+    pub async fn dismiss_persistent_notification(
+        &mut self,
+        notification_id: &str,
+    ) -> HassResult<String> {
+        let service_data = json!({ "notification_id": notification_id });
+        self.call_service(
+            "persistent_notification".to_owned(),
+            "dismiss".to_owned(),
+            Some(service_data),
+        )
+        .await
+    }
+
+    /// This will get the history of the `entity_ids` between `start` and `end`.
+    ///
+    /// Each history starts with the state the entity had at `start`, and only holds the changes of the state,
+    /// the attributes are left out.
+    pub async fn get_history(
+        &mut self,
+        entity_ids: &[&str],
+        start: SystemTime,
+        end: SystemTime,
+    ) -> HassResult<HassHistory> {
+        let id = self.next_id();
+        let history_req = Command::HistoryDuringPeriod(HistoryDuringPeriod {
             id: Some(id),
-            msg_type: "unsubscribe_events".to_owned(),
-            subscription: subscription_id,
+            msg_type: "history/history_during_period".to_owned(),
+            start_time: iso_datetime(start),
+            end_time: iso_datetime(end),
+            entity_ids: entity_ids.iter().map(|id| id.to_string()).collect(),
+            minimal_response: true,
+            no_attributes: true,
         });
+        let response = self.command(history_req).await?;
 
-        //send command to unsubscribe from specific event
-        let response = self.command(unsubscribe_req).await.unwrap();
-
-        //Remove the event_type and the callback from the event_listeners hashmap
         match response {
-            Response::Result(v) if v.success == true => {
-                if let Some(_) = self.subscriptions.remove(&subscription_id) {
-                    return Ok("Ok".to_owned());
+            Response::Result(data) => match data.success {
+                true => {
+                    let history = data.typed_result()?;
+                    Ok(history)
                 }
-                return Err(HassError::Generic("Wrong subscription ID".to_owned()));
-            }
-            Response::Result(v) if v.success == false => return Err(HassError::ReponseError(v)),
-            _ => return Err(HassError::UnknownPayloadReceived),
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
         }
     }
 
-    //used to send commands and receive responses from the gateway
-    pub(crate) async fn command(&mut self, cmd: Command) -> HassResult<Response> {
-        //transform to TungsteniteMessage to be sent to WebSocket
-        let cmd_tungstenite = cmd.to_tungstenite_message();
+    /// This will compute the fraction of the time between `start` and `end` the entity was not `unavailable`,
+    /// from its history, see `availability_ratio`.
+    ///
+    /// The time before the first recorded state, e.g. beyond the retention of the recorder, is left out.
+    /// An error is returned when there is no history for the entity in the window.
+    pub async fn availability(
+        &mut self,
+        entity_id: &str,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> HassResult<f32> {
+        let history = self
+            .get_history(&[entity_id], start, end)
+            .await?
+            .remove(entity_id)
+            .unwrap_or_default();
 
-        // Send the auth command to gateway
-        #[cfg(feature = "use-tokio")]
-        self.to_gateway
-            .send(cmd_tungstenite)
-            .await
-            .map_err(|err| HassError::SendError(err.to_string()))?;
+        let seconds = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        };
+        availability_ratio(&history, seconds(start), seconds(end)).ok_or_else(|| {
+            HassError::Generic(format!("No history for {} in the period", entity_id))
+        })
+    }
 
-        #[cfg(feature = "use-async-std")]
-        self.to_gateway
-            .send(cmd_tungstenite)
+    /// This will get the states like `get_states`, retrying up to `retries` times on the transient errors.
+    ///
+    /// The retries are made after an increasing delay, for the errors which are `HassError::is_retryable`,
+    /// and the last error is returned when all the attempts failed. Only the reads are retried this way,
+    /// a mutating command like `call_service` may have been applied even though its response was lost.
+    pub async fn get_states_retrying(&mut self, retries: u32) -> HassResult<Vec<HassEntityState>> {
+        self.retrying(retries, |client| Box::pin(client.get_states()))
             .await
-            .map_err(|err| HassError::SendError(err.to_string()))?;
+    }
 
-        self.ws_receive().await
+    /// This will get the config like `get_config`, retrying on the transient errors, see `get_states_retrying`.
+    pub async fn get_config_retrying(&mut self, retries: u32) -> HassResult<HassConfig> {
+        self.retrying(retries, |client| Box::pin(client.get_config()))
+            .await
     }
 
-    //read the messages from the Websocket connection
-    pub(crate) async fn ws_receive(&mut self) -> HassResult<Response> {
-        #[cfg(feature = "use-tokio")]
-        match self.from_gateway.recv().await {
-            Some(Ok(item)) => match item {
-                TungsteniteMessage::Text(data) => {
-                    //Serde: The tag identifying which variant we are dealing with is now inside of the content,
-                    // next to any other fields of the variant
+    /// This will get the services like `get_services`, retrying on the transient errors, see `get_states_retrying`.
+    pub async fn get_services_retrying(&mut self, retries: u32) -> HassResult<HassServices> {
+        self.retrying(retries, |client| Box::pin(client.get_services()))
+            .await
+    }
 
-                    let payload: Result<Response, HassError> = serde_json::from_str(&data)
-                        .map_err(|err| HassError::UnableToDeserialize(err));
+    /// This will snapshot the registries like `snapshot_registries`, retrying on the transient errors,
+    /// see `get_states_retrying`.
+    pub async fn snapshot_registries_retrying(
+        &mut self,
+        retries: u32,
+    ) -> HassResult<RegistrySnapshot> {
+        self.retrying(retries, |client| Box::pin(client.snapshot_registries()))
+            .await
+    }
 
-                    payload
+    // runs the `read` until it succeeds, fails with an error which is not retryable, or `retries` retries were made
+    async fn retrying<T>(
+        &mut self,
+        retries: u32,
+        read: for<'a> fn(&'a mut HassClient) -> BoxFuture<'a, HassResult<T>>,
+    ) -> HassResult<T> {
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            match read(self).await {
+                Err(error) if attempt < retries && error.is_retryable() => {
+                    warn!("Retrying in {:?} after: {}", delay, error);
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
                 }
-                _ => Err(HassError::UnknownPayloadReceived),
-            },
-            Some(Err(error)) => {
-                let err = Err(HassError::from(&error));
-                err
+                result => return result,
             }
-
-            None => Err(HassError::UnknownPayloadReceived),
         }
+    }
 
-        #[cfg(feature = "use-async-std")]
-        match self.from_gateway.recv().await {
-            Ok(Ok(item)) => match item {
-                TungsteniteMessage::Text(data) => {
-                    //Serde: The tag identifying which variant we are dealing with is now inside of the content,
-                    // next to any other fields of the variant
+    ///This will call a service in Home Assistant. Right now there is no return value.
+    ///The client can listen to state_changed events if it is interested in changed entities as a result of a service call.
+    ///
+    ///The server will indicate with a message indicating that the service is done executing.
+    /// https://developers.home-assistant.io/docs/api/websocket#calling-a-service
+    /// additional info : https://developers.home-assistant.io/docs/api/rest ==> Post /api/services/<domain>/<service>
+
+    pub async fn call_service(
+        &mut self,
+        domain: String,
+        service: String,
+        service_data: Option<Value>,
+    ) -> HassResult<String> {
+        self.send_call_service(domain, service, service_data, None)
+            .await?;
+        Ok("command executed successfully".to_owned())
+    }
+
+    /// This will call a service in Home Assistant, same as call_service, but returns the Context of the call.
+    ///
+    /// The state_changed events caused by the service call carry this context id as their `context.parent_id`,
+    /// so the client can correlate the resulting state changes with the call.
+    /// This requires a Home Assistant version that includes the context in the call_service result.
+    pub async fn call_service_with_context(
+        &mut self,
+        domain: String,
+        service: String,
+        service_data: Option<Value>,
+    ) -> HassResult<Context> {
+        let called: ServiceCalled = self
+            .send_call_service(domain, service, service_data, None)
+            .await?
+            .typed_result()?;
+        Ok(called.context)
+    }
+
+    /// This will send a service call, e.g. made with one of the builders like `LightTurnOn`,
+    /// and returns the Context of the call, see `call_service_with_context`.
+    pub async fn call(&mut self, call: ServiceCall) -> HassResult<Context> {
+        let called: ServiceCalled = self
+            .send_call_service(call.domain, call.service, call.service_data, call.target)
+            .await?
+            .typed_result()?;
+        Ok(called.context)
+    }
+
+    /// This will send several service calls at once, without waiting for the response of each one.
+    ///
+    /// The responses are matched to the calls by their id, and a result is returned for each call, in the order of `calls`.
+    /// The outer error is only returned when the connection failed. Home Assistant may execute the calls in any order,
+    /// use `call` in sequence when the order matters, e.g. for a scene built from several calls.
+    ///
+    /// The other messages received meanwhile, e.g. the events of a client built with `from_channels`,
+    /// are not lost: they are delivered first by [`HassClient::incoming`].
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> hass_rs::HassResult<()> {
+    /// use async_tungstenite::tungstenite::Message;
+    /// use futures::channel::mpsc;
+    /// use futures::{SinkExt, StreamExt};
+    /// use hass_rs::{HassClient, LightTurnOn};
+    /// use serde_json::Value;
+    ///
+    /// let (to_server, mut from_client) = mpsc::channel::<Message>(10);
+    /// let (mut to_client, from_server) = mpsc::channel(10);
+    ///
+    /// // a fake server, which answers the three calls in the reverse order
+    /// tokio::spawn(async move {
+    ///     let reply = |text: String| Ok(Message::Text(text));
+    ///     to_client.send(reply(r#"{"type": "auth_required", "ha_version": "2024.1.0"}"#.to_owned())).await.unwrap();
+    ///     from_client.next().await.unwrap();
+    ///     to_client.send(reply(r#"{"type": "auth_ok", "ha_version": "2024.1.0"}"#.to_owned())).await.unwrap();
+    ///
+    ///     let mut ids = Vec::new();
+    ///     for _ in 0..3 {
+    ///         let call = from_client.next().await.unwrap().into_text().unwrap();
+    ///         ids.push(serde_json::from_str::<Value>(&call).unwrap()["id"].as_u64().unwrap());
+    ///     }
+    ///     for id in ids.into_iter().rev() {
+    ///         let result = format!(
+    ///             r#"{{"id": {id}, "type": "result", "success": true, "result": {{"context": {{"id": "call-{id}"}}}}}}"#
+    ///         );
+    ///         to_client.send(reply(result)).await.unwrap();
+    ///     }
+    /// });
+    ///
+    /// let mut client = HassClient::from_channels(to_server, from_server);
+    /// client.auth_with_longlivedtoken("token").await?;
+    ///
+    /// let calls = ["light.kitchen", "light.hall", "light.porch"]
+    ///     .into_iter()
+    ///     .map(|light| LightTurnOn::new(light).build())
+    ///     .collect();
+    /// let results = client.call_services_batch(calls).await?;
+    ///
+    /// let contexts: Vec<_> = results.into_iter().map(|result| result.unwrap().id).collect();
+    /// assert_eq!(contexts, ["call-1", "call-2", "call-3"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn call_services_batch(
+        &mut self,
+        calls: Vec<ServiceCall>,
+    ) -> HassResult<Vec<HassResult<Context>>> {
+        // the dry-run and the authentication checks are left to `call`
+        if self.dry_run || !self.authenticated {
+            let mut results = Vec::with_capacity(calls.len());
+            for call in calls {
+                results.push(self.call(call).await);
+            }
+            return Ok(results);
+        }
 
-                    let payload: Result<Response, HassError> =
-                        serde_json::from_str(&data).map_err(|_| HassError::UnknownPayloadReceived);
+        let mut ids = Vec::with_capacity(calls.len());
+        for call in calls {
+            let id = self.next_id();
+            let cmd = Command::CallService(CallService {
+                id: Some(id),
+                msg_type: "call_service".to_owned(),
+                domain: call.domain,
+                service: call.service,
+                service_data: call.service_data,
+                target: call.target,
+            });
+            self.to_gateway
+                .send(cmd.to_tungstenite_message())
+                .await
+                .map_err(|err| HassError::SendError(err.to_string()))?;
+            ids.push(id);
+        }
 
-                    payload
+        let mut responses = HashMap::with_capacity(ids.len());
+        while responses.len() < ids.len() {
+            match self.ws_receive().await? {
+                Response::Result(data) if ids.contains(&data.id) => {
+                    responses.insert(data.id, data);
                 }
-                _ => Err(HassError::UnknownPayloadReceived),
-            },
-            Ok(Err(error)) => {
-                let err = Err(HassError::from(&error));
-                err
+                Response::Event(event) => self.keep_unmatched(Incoming::Event(event)),
+                response => self.keep_unmatched(Incoming::Response(response)),
             }
+        }
 
-            Err(error) => Err(HassError::RecvError(error)),
+        let mut results = Vec::with_capacity(ids.len());
+        for id in ids {
+            let data = responses
+                .remove(&id)
+                .expect("all the responses were received");
+            results.push(match data.success {
+                true => data
+                    .typed_result::<ServiceCalled>()
+                    .map(|called| called.context),
+                false => Err(HassError::ReponseError(data)),
+            });
         }
+        Ok(results)
     }
-}
-
-/// convenient function that validates if the message received is an Event
-/// the Events should be processed by used in a separate async task
+
+    // keeps a message received during a batch for `incoming`
+    fn keep_unmatched(&mut self, message: Incoming) {
+        if self.unmatched.len() >= UNMATCHED_CAPACITY {
+            warn!("Too many messages received during the batch, dropping the oldest one");
+            self.unmatched.pop_front();
+        }
+        self.unmatched.push_back(message);
+    }
+
+    /// This will start or resume the playback of a media player.
+    pub async fn media_play(&mut self, entity_id: &str) -> HassResult<Context> {
+        self.call(MediaPlayerControl::play(entity_id)).await
+    }
+
+    /// This will pause the playback of a media player.
+    pub async fn media_pause(&mut self, entity_id: &str) -> HassResult<Context> {
+        self.call(MediaPlayerControl::pause(entity_id)).await
+    }
+
+    /// This will skip to the next track of a media player.
+    pub async fn media_next(&mut self, entity_id: &str) -> HassResult<Context> {
+        self.call(MediaPlayerControl::next_track(entity_id)).await
+    }
+
+    /// This will go back to the previous track of a media player.
+    pub async fn media_previous(&mut self, entity_id: &str) -> HassResult<Context> {
+        self.call(MediaPlayerControl::previous_track(entity_id))
+            .await
+    }
+
+    /// This will set the volume of a media player, from 0.0 to 1.0.
+    ///
+    /// A level out of that range is rejected without sending anything.
+    pub async fn media_set_volume(&mut self, entity_id: &str, level: f32) -> HassResult<Context> {
+        let call = MediaPlayerControl::set_volume(entity_id, level).ok_or_else(|| {
+            HassError::Generic(format!(
+                "The volume level {} is not between 0.0 and 1.0",
+                level
+            ))
+        })?;
+        self.call(call).await
+    }
+
+    /// This will play a media on a media player, see `MediaPlayerPlayMedia` for more options.
+    ///
+    /// The content type depends on the player, e.g. "music", "video" or "playlist".
+    pub async fn play_media(
+        &mut self,
+        entity_id: &str,
+        content_id: &str,
+        content_type: &str,
+    ) -> HassResult<Context> {
+        self.call(MediaPlayerPlayMedia::new(entity_id, content_id, content_type).build())
+            .await
+    }
+
+    //sends the call_service command and returns the result of the call
+    async fn send_call_service(
+        &mut self,
+        domain: String,
+        service: String,
+        service_data: Option<Value>,
+        target: Option<Target>,
+    ) -> HassResult<WSResult> {
+        let id = self.next_id();
+
+        //Send GetStates command and expect a number of Entities
+        let services_req = Command::CallService(CallService {
+            id: Some(id),
+            msg_type: "call_service".to_owned(),
+            domain,
+            service,
+            service_data,
+            target,
+        });
+        let response = self.command(services_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => Ok(data),
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will activate a scene, by calling the scene.turn_on service with the `entity_id` as target.
+    pub async fn activate_scene(&mut self, entity_id: &str) -> HassResult<String> {
+        let target = Target::entity(entity_id);
+        self.send_call_service("scene".to_owned(), "turn_on".to_owned(), None, Some(target))
+            .await?;
+        Ok("command executed successfully".to_owned())
+    }
+
+    /// This will run a script, by calling the script.<name> service of the `entity_id`.
+    ///
+    /// The `variables` are passed through as service_data, to be used by the script.
+    pub async fn run_script(
+        &mut self,
+        entity_id: &str,
+        variables: Option<Value>,
+    ) -> HassResult<String> {
+        let script = entity_id
+            .strip_prefix("script.")
+            .ok_or_else(|| HassError::Generic(format!("{} is not a script entity", entity_id)))?;
+        self.call_service("script".to_owned(), script.to_owned(), variables)
+            .await
+    }
+
+    /// This will trigger an automation, by calling the automation.trigger service with the `entity_id` as target.
+    pub async fn trigger_automation(&mut self, entity_id: &str) -> HassResult<String> {
+        let target = Target::entity(entity_id);
+        self.send_call_service(
+            "automation".to_owned(),
+            "trigger".to_owned(),
+            None,
+            Some(target),
+        )
+        .await?;
+        Ok("command executed successfully".to_owned())
+    }
+
+    /// This will validate the triggers, conditions and actions of an automation, without running them.
+    ///
+    /// The server succeeds even when the configuration is invalid: each section which was sent
+    /// carries its own outcome, check `ConfigValidation::is_valid` or the section errors.
+    pub async fn validate_config(
+        &mut self,
+        trigger: Option<Value>,
+        condition: Option<Value>,
+        action: Option<Value>,
+    ) -> HassResult<ConfigValidation> {
+        let id = self.next_id();
+        let validate_req = Command::ValidateConfig(ValidateConfig {
+            id: Some(id),
+            msg_type: "validate_config".to_owned(),
+            trigger,
+            condition,
+            action,
+        });
+        let response = self.command(validate_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let validation: ConfigValidation = data.typed_result()?;
+                    Ok(validation)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will execute a script `sequence` (a list of actions), with the optional `variables`.
+    ///
+    /// The result holds the context of the run and the response of the script, if it returned one.
+    pub async fn execute_script(
+        &mut self,
+        sequence: Value,
+        variables: Option<Value>,
+    ) -> HassResult<ScriptResult> {
+        let id = self.next_id();
+        let script_req = Command::ExecuteScript(ExecuteScript {
+            id: Some(id),
+            msg_type: "execute_script".to_owned(),
+            sequence,
+            variables,
+        });
+        let response = self.command(script_req).await?;
+
+        match response {
+            Response::Result(data) => match data.success {
+                true => {
+                    let result: ScriptResult = data.typed_result()?;
+                    Ok(result)
+                }
+                false => Err(HassError::ReponseError(data)),
+            },
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will subscribe your client to the rendering of a `template`.
+    ///
+    /// The template is rendered right away and again each time one of the entities it uses changes.
+    /// The rendering errors and warnings are reported on the stream, in the `TemplateRender::error`,
+    /// rather than ending the subscription. The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn render_template(
+        &mut self,
+        template: &str,
+        variables: Option<Value>,
+    ) -> HassResult<(u64, impl Stream<Item = TemplateRender>)> {
+        let id = self.next_id();
+        let cmd = Command::RenderTemplate(RenderTemplate {
+            id: Some(id),
+            msg_type: "render_template".to_owned(),
+            template: template.to_owned(),
+            variables,
+            report_errors: true,
+        });
+        let (id, events) = self.subscribe_listener(id, cmd, "render_template").await?;
+
+        let renders = events.filter_map(|event| async move { serde_json::from_value(event).ok() });
+        Ok((id, renders))
+    }
+
+    /// The command subscribe_event will subscribe your client to the event bus.
+    ///
+    /// You can either listen to all events or to a specific event type.
+    /// If you want to listen to multiple event types, you will have to send multiple subscribe_events commands.
+    /// The server will respond with a result message to indicate that the subscription is active.
+    /// For each event that matches, the server will send a message of type event.
+    /// The id in the message will point at the original id of the listen_event command.
+
+    pub async fn subscribe_event(&mut self, event_name: &str) -> HassResult<WSResult> {
+        let id = self.next_id();
+
+        //create the Event Subscribe Command
+        let cmd = Command::SubscribeEvent(Subscribe {
+            id: Some(id),
+            msg_type: "subscribe_events".to_owned(),
+            event_type: Some(event_name.to_owned()),
+        });
+
+        //send command to subscribe to specific event
+        let response = self.command(cmd.clone()).await?;
+
+        //Add the callback in the event_listeners hashmap if the Subscription Response is successfull
+        match response {
+            Response::Result(v) if v.success == true => {
+                self.subscriptions.insert(v.id, event_name.to_owned());
+                self.subscribe_commands.insert(v.id, cmd);
+                trace_event!(
+                    debug,
+                    subscription_id = v.id,
+                    kind = event_name,
+                    "subscribed"
+                );
+                return Ok(v);
+            }
+            Response::Result(v) if v.success == false => return Err(HassError::ReponseError(v)),
+            _ => return Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will subscribe your client to the `event_name` events, unless it is already subscribed to them.
+    ///
+    /// Home Assistant allows several subscriptions to the same event type, each one delivering
+    /// its own copy of the events, which most clients don't want. The id of the existing subscription
+    /// is returned when there is one, otherwise the id of the new subscription.
+    pub async fn subscribe_event_unique(&mut self, event_name: &str) -> HassResult<u64> {
+        let existing = self
+            .subscriptions
+            .iter()
+            .find(|(_, subscribed)| subscribed.as_str() == event_name)
+            .map(|(id, _)| *id);
+
+        match existing {
+            Some(id) => Ok(id),
+            None => self
+                .subscribe_event(event_name)
+                .await
+                .map(|result| result.id),
+        }
+    }
+
+    /// This will subscribe your client to all the events of the event bus.
+    ///
+    /// The subscribe_events command is sent without an event_type, and the subscription
+    /// is recorded with the "*" event type, which is how Home Assistant matches all events.
+    pub async fn subscribe_all_events(&mut self) -> HassResult<WSResult> {
+        let id = self.next_id();
+
+        let cmd = Command::SubscribeEvent(Subscribe {
+            id: Some(id),
+            msg_type: "subscribe_events".to_owned(),
+            event_type: None,
+        });
+        let response = self.command(cmd.clone()).await?;
+
+        match response {
+            Response::Result(v) if v.success => {
+                self.subscriptions.insert(v.id, "*".to_owned());
+                self.subscribe_commands.insert(v.id, cmd);
+                trace_event!(debug, subscription_id = v.id, kind = "*", "subscribed");
+                Ok(v)
+            }
+            Response::Result(v) => Err(HassError::ReponseError(v)),
+            _ => Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// This will watch the `entity_ids`, returning their current states and the stream of their changes.
+    ///
+    /// The subscription to the `state_changed` events is made before fetching the states, so no change
+    /// is missed in between, and the changes already part of the returned states are skipped.
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn watch_entities(
+        &mut self,
+        entity_ids: &[String],
+    ) -> HassResult<(u64, Vec<HassEntityState>, impl Stream<Item = EventData>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeEvent(Subscribe {
+            id: Some(id),
+            msg_type: "subscribe_events".to_owned(),
+            event_type: Some("state_changed".to_owned()),
+        });
+        let (id, events) = self.subscribe_listener(id, cmd, "state_changed").await?;
+
+        let states: Vec<HassEntityState> = self
+            .get_states()
+            .await?
+            .into_iter()
+            .filter(|state| entity_ids.contains(&state.entity_id))
+            .collect();
+
+        // the last update of each entity in the snapshot, the changes up to it are already part of the states,
+        // the timestamps are all UTC ISO 8601 strings, so they are compared as they are
+        let mut snapshot: HashMap<String, String> = states
+            .iter()
+            .map(|state| (state.entity_id.clone(), state.last_updated.clone()))
+            .collect();
+        let entity_ids = entity_ids.to_vec();
+
+        let changes = events.filter_map(move |event| {
+            let change = serde_json::from_value::<HassEvent>(event)
+                .ok()
+                .map(|event| event.data)
+                .filter(|data| entity_ids.contains(&data.entity_id))
+                .filter(|data| {
+                    let updated = data.new_state.as_ref().map(|state| &state.last_updated);
+                    match (snapshot.get(&data.entity_id), updated) {
+                        (Some(snapshot_updated), Some(updated)) if updated <= snapshot_updated => {
+                            false
+                        }
+                        _ => {
+                            snapshot.remove(&data.entity_id);
+                            true
+                        }
+                    }
+                });
+            future::ready(change)
+        });
+        Ok((id, states, changes))
+    }
+
+    /// This will subscribe your client to an automation `trigger`, e.g. `{"platform": "state", "entity_id": "sun.sun"}`.
+    ///
+    /// The stream yields the `trigger` variables each time the trigger fires, as an automation would see them.
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn subscribe_trigger(
+        &mut self,
+        trigger: Value,
+    ) -> HassResult<(u64, impl Stream<Item = Value>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeTrigger(SubscribeTrigger {
+            id: Some(id),
+            msg_type: "subscribe_trigger".to_owned(),
+            trigger,
+        });
+        let (id, events) = self
+            .subscribe_listener(id, cmd, "subscribe_trigger")
+            .await?;
+
+        let triggers = events.filter_map(|event| async move {
+            serde_json::from_value::<TriggerEvent>(event)
+                .ok()
+                .map(|event| event.variables.trigger)
+        });
+        Ok((id, triggers))
+    }
+
+    /// This will subscribe your client to the states of the `entity_ids`, or of all the entities, in the compact format.
+    ///
+    /// The first update adds all the current states, the next ones hold the changes as diffs, see `EntitiesUpdate::apply`
+    /// to maintain the states from them. When the client reconnects (see `ClientConfig::reconnect`) the diffs of
+    /// the lost connection can't be followed: the subscription is renewed and the stream yields `EntitiesUpdate::Reset`,
+    /// then the update adding all the current states, so the entities removed meanwhile don't stay around.
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn subscribe_entities(
+        &mut self,
+        entity_ids: Option<Vec<String>>,
+    ) -> HassResult<(u64, impl Stream<Item = EntitiesUpdate>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeEntities(SubscribeEntities {
+            id: Some(id),
+            msg_type: "subscribe_entities".to_owned(),
+            entity_ids,
+        });
+        let (id, events) = self
+            .subscribe_listener(id, cmd, "subscribe_entities")
+            .await?;
+        if let Some(listeners) = &self.listeners {
+            if let Some(listener) = listeners.lock().unwrap().get_mut(&id) {
+                listener.resets = true;
+            }
+        }
+
+        let updates = events.filter_map(|event| async move {
+            if event == RESET_EVENT {
+                return Some(EntitiesUpdate::Reset);
+            }
+            match serde_json::from_value(event) {
+                Ok(diff) => Some(EntitiesUpdate::Diff(diff)),
+                Err(err) => {
+                    warn!("Unable to read the entities update: {}", err);
+                    None
+                }
+            }
+        });
+        Ok((id, updates))
+    }
+
+    /// This will get the states of the entities matching any of the `patterns`, e.g. `light.*` or `sensor.*_temperature`.
+    ///
+    /// See `entity_id_matches` for the patterns, an entity id without wildcard matches only itself.
+    pub async fn get_states_filtered(
+        &mut self,
+        patterns: &[&str],
+    ) -> HassResult<Vec<HassEntityState>> {
+        let states = self.get_states().await?;
+
+        Ok(states
+            .into_iter()
+            .filter(|state| matches_any(patterns, &state.entity_id))
+            .collect())
+    }
+
+    /// This will subscribe to the changes of the entities matching any of the `patterns`, see `get_states_filtered`.
+    ///
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn subscribe_state_changes(
+        &mut self,
+        patterns: &[&str],
+    ) -> HassResult<(u64, impl Stream<Item = EventData>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeEvent(Subscribe {
+            id: Some(id),
+            msg_type: "subscribe_events".to_owned(),
+            event_type: Some("state_changed".to_owned()),
+        });
+        let (id, events) = self.subscribe_listener(id, cmd, "state_changed").await?;
+
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        let changes = events.filter_map(move |event| {
+            let change = serde_json::from_value::<HassEvent>(event)
+                .ok()
+                .map(|event| event.data)
+                .filter(|data| matches_any(&patterns, &data.entity_id));
+            future::ready(change)
+        });
+        Ok((id, changes))
+    }
+
+    /// This will wait until `entity_id` reaches `target_state`, e.g. until a dishwasher reports "finished".
+    ///
+    /// The entity is watched like with `watch_entities`, and its state is returned right away when it is already
+    /// the target one. Otherwise its changes are followed until it reaches it, and `HassError::Timeout` is returned
+    /// when it didn't within `deadline`. The temporary subscription is removed in every case.
+    /// This is only available when the connection was opened by the client (`connect`).
+    ///
+    /// ```no_run
+    /// # async fn example(client: &mut hass_rs::HassClient) -> hass_rs::HassResult<()> {
+    /// use std::time::Duration;
+    ///
+    /// let finished = client
+    ///     .wait_for_state("sensor.dishwasher_status", "finished", Duration::from_secs(3 * 3600))
+    ///     .await?;
+    /// println!("finished at {}", finished.last_changed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn wait_for_state(
+        &mut self,
+        entity_id: &str,
+        target_state: &str,
+        deadline: Duration,
+    ) -> HassResult<HassEntityState> {
+        let (id, states, changes) = self.watch_entities(&[entity_id.to_owned()]).await?;
+
+        let reached = match states.into_iter().find(|state| state.state == target_state) {
+            Some(state) => Ok(state),
+            None => {
+                let mut reached = Box::pin(changes.filter_map(|data| {
+                    future::ready(data.new_state.filter(|state| state.state == target_state))
+                }));
+                match timeout(deadline, reached.next()).await {
+                    Some(Some(state)) => Ok(state),
+                    // the events stream ends when the connection is lost
+                    Some(None) => Err(HassError::ConnectionClosed),
+                    None => Err(HassError::Timeout),
+                }
+            }
+        };
+
+        let unsubscribed = self.unsubscribe_event(id).await;
+        let state = reached?;
+        unsubscribed?;
+        Ok(state)
+    }
+
+    /// This will watch the state transitions of `entity_id`, with a state trigger.
+    ///
+    /// Only the transitions to `to_state` and from `from_state` are yielded, when they are set,
+    /// and only once the new state held for `for_duration`, when it is set.
+    /// Unlike subscribing to all the state_changed events, the filtering is done by Home Assistant.
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    pub async fn on_state(
+        &mut self,
+        entity_id: &str,
+        to_state: Option<&str>,
+        from_state: Option<&str>,
+        for_duration: Option<Duration>,
+    ) -> HassResult<(u64, impl Stream<Item = EventData>)> {
+        let mut trigger = json!({ "platform": "state", "entity_id": entity_id });
+        if let Some(to_state) = to_state {
+            trigger["to"] = json!(to_state);
+        }
+        if let Some(from_state) = from_state {
+            trigger["from"] = json!(from_state);
+        }
+        if let Some(duration) = for_duration {
+            trigger["for"] = json!({
+                "seconds": duration.as_secs(),
+                "milliseconds": duration.subsec_millis(),
+            });
+        }
+
+        let (id, triggers) = self.subscribe_trigger(trigger).await?;
+        let transitions = triggers.filter_map(|trigger| async move {
+            serde_json::from_value::<StateTrigger>(trigger)
+                .ok()
+                .map(|trigger| EventData {
+                    entity_id: trigger.entity_id,
+                    new_state: trigger.to_state,
+                    old_state: trigger.from_state,
+                })
+        });
+        Ok((id, transitions))
+    }
+
+    /// This will subscribe your client to the changes of the entity registry, e.g. to keep a copy of it up to date.
+    ///
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn on_entity_registry_updated(
+        &mut self,
+    ) -> HassResult<(u64, impl Stream<Item = RegistryChange>)> {
+        self.subscribe_registry_changes("entity_registry_updated", "entity_id")
+            .await
+    }
+
+    /// This will subscribe your client to the changes of the device registry, see `on_entity_registry_updated`.
+    pub async fn on_device_registry_updated(
+        &mut self,
+    ) -> HassResult<(u64, impl Stream<Item = RegistryChange>)> {
+        self.subscribe_registry_changes("device_registry_updated", "device_id")
+            .await
+    }
+
+    /// This will subscribe your client to the changes of the area registry, see `on_entity_registry_updated`.
+    pub async fn on_area_registry_updated(
+        &mut self,
+    ) -> HassResult<(u64, impl Stream<Item = RegistryChange>)> {
+        self.subscribe_registry_changes("area_registry_updated", "area_id")
+            .await
+    }
+
+    // subscribes to the `event_type` events of a registry, which tell the changed item in their `id_key` field
+    async fn subscribe_registry_changes(
+        &mut self,
+        event_type: &str,
+        id_key: &'static str,
+    ) -> HassResult<(u64, impl Stream<Item = RegistryChange>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeEvent(Subscribe {
+            id: Some(id),
+            msg_type: "subscribe_events".to_owned(),
+            event_type: Some(event_type.to_owned()),
+        });
+        let (id, events) = self.subscribe_listener(id, cmd, event_type).await?;
+
+        let changes = events
+            .filter_map(move |event| async move { RegistryChange::from_event(&event, id_key) });
+        Ok((id, changes))
+    }
+
+    /// This will subscribe your client to the changes of the persistent notifications.
+    ///
+    /// The first update holds all the current notifications, the next ones the added, updated or removed ones.
+    /// The older versions of Home Assistant don't support it, they fire a `persistent_notifications_updated`
+    /// event instead, to be followed with `subscribe_event` and `get_persistent_notifications`.
+    /// The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn subscribe_persistent_notifications(
+        &mut self,
+    ) -> HassResult<(u64, impl Stream<Item = NotificationsUpdate>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeNotifications(Ask {
+            id: Some(id),
+            msg_type: "persistent_notification/subscribe".to_owned(),
+        });
+        let (id, events) = self
+            .subscribe_listener(id, cmd, "persistent_notification")
+            .await?;
+
+        let updates = events.filter_map(|event| async move { serde_json::from_value(event).ok() });
+        Ok((id, updates))
+    }
+
+    /// This will subscribe your client to the logbook event stream.
+    ///
+    /// The logbook entries are delivered from `start_time` (an ISO 8601 datetime) and then live,
+    /// optionally only for the `entity_ids`. The returned id is used to unsubscribe with `unsubscribe_event`.
+    /// This is only available when the connection was opened by the client (`connect`).
+    pub async fn subscribe_logbook(
+        &mut self,
+        start_time: &str,
+        entity_ids: Option<Vec<String>>,
+    ) -> HassResult<(u64, impl Stream<Item = LogbookEntry>)> {
+        let id = self.next_id();
+        let cmd = Command::SubscribeLogbook(SubscribeLogbook {
+            id: Some(id),
+            msg_type: "logbook/event_stream".to_owned(),
+            start_time: start_time.to_owned(),
+            entity_ids,
+        });
+        let (id, events) = self
+            .subscribe_listener(id, cmd, "logbook/event_stream")
+            .await?;
+
+        let entries = events.flat_map(|event| {
+            let entries = match serde_json::from_value::<LogbookEvents>(event) {
+                Ok(logbook) => logbook.events,
+                Err(_) => Vec::new(),
+            };
+            stream::iter(entries)
+        });
+        Ok((id, entries))
+    }
+
+    // sends the subscription command `cmd`, which has the `id`, and returns the stream of its raw events
+    async fn subscribe_listener(
+        &mut self,
+        id: u64,
+        cmd: Command,
+        kind: &str,
+    ) -> HassResult<(u64, impl Stream<Item = Value>)> {
+        let listeners = self.listeners.clone().ok_or_else(|| {
+            HassError::Generic(
+                "The subscription requires the client to be connected with connect".to_owned(),
+            )
+        })?;
+
+        // the listener is registered first, so the events following the result are not lost
+        let (to_listener, from_subscription) = channel::<Value>(100);
+        listeners
+            .lock()
+            .unwrap()
+            .insert(id, Listener::new(to_listener));
+
+        let response = match self.command(cmd.clone()).await {
+            Ok(response) => response,
+            Err(err) => {
+                listeners.lock().unwrap().remove(&id);
+                return Err(err);
+            }
+        };
+
+        match response {
+            Response::Result(v) if v.success => {
+                // the command may have been resent with another id
+                if v.id != id {
+                    let mut listeners = listeners.lock().unwrap();
+                    if let Some(listener) = listeners.remove(&id) {
+                        listeners.insert(v.id, listener);
+                    }
+                }
+                self.subscriptions.insert(v.id, kind.to_owned());
+                self.subscribe_commands.insert(v.id, cmd);
+                trace_event!(debug, subscription_id = v.id, kind, "subscribed");
+                Ok((v.id, receiver_stream(from_subscription)))
+            }
+            response => {
+                listeners.lock().unwrap().remove(&id);
+                match response {
+                    Response::Result(v) => Err(HassError::ReponseError(v)),
+                    _ => Err(HassError::UnknownPayloadReceived),
+                }
+            }
+        }
+    }
+
+    /// Pauses the delivery of the events of a subscription, without unsubscribing from them.
+    ///
+    /// The events received while paused are kept, up to 1000 of them, then the oldest ones are dropped,
+    /// and they are delivered in order by `resume_subscription`. This applies to the subscriptions which
+    /// return their own stream (e.g. `subscribe_trigger`, `watch_entities`, `subscribe_logbook`), not to
+    /// the events of `subscribe_event`, which share a single channel. Returns false when there is no such subscription.
+    pub fn pause_subscription(&mut self, subscription_id: u64) -> bool {
+        let Some(listeners) = &self.listeners else {
+            return false;
+        };
+        match listeners.lock().unwrap().get_mut(&subscription_id) {
+            Some(listener) => {
+                listener.pause();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resumes the delivery of the events of a subscription paused by `pause_subscription`.
+    ///
+    /// The events kept while paused are delivered first, by a spawned task, as the stream is read.
+    /// Returns false when there is no such subscription.
+    pub fn resume_subscription(&mut self, subscription_id: u64) -> bool {
+        let Some(listeners) = self.listeners.clone() else {
+            return false;
+        };
+        let resuming = match listeners.lock().unwrap().get_mut(&subscription_id) {
+            Some(listener) => listener.start_resuming(),
+            None => return false,
+        };
+        if resuming {
+            spawn(resume_listener(listeners, subscription_id));
+        }
+        true
+    }
+
+    ///The command unsubscribe_event will unsubscribe your client from the event bus.
+    ///
+    /// You can unsubscribe from previously created subscription events.
+    /// Pass the id of the original subscription command as value to the subscription field.
+    /// Home Assistant ends all kinds of subscriptions with this command (events, logbook, templates...).
+    /// Unsubscribing an id which is already unsubscribed succeeds, so it is safe to call it twice.
+
+    pub async fn unsubscribe_event(&mut self, subscription_id: u64) -> HassResult<String> {
+        let id = self.next_id();
+
+        //Unsubscribe the Event
+        let unsubscribe_req = Command::Unsubscribe(Unsubscribe {
+            id: Some(id),
+            msg_type: "unsubscribe_events".to_owned(),
+            subscription: subscription_id,
+        });
+
+        //send command to unsubscribe from specific event
+        let response = self.command(unsubscribe_req).await?;
+
+        //Remove the event_type and the callback from the event_listeners hashmap
+        if let Some(listeners) = &self.listeners {
+            listeners.lock().unwrap().remove(&subscription_id);
+        }
+        self.subscriptions.remove(&subscription_id);
+        self.subscribe_commands.remove(&subscription_id);
+        trace_event!(debug, subscription_id, "unsubscribed");
+
+        match response {
+            Response::Result(v) if v.success == true => return Ok("Ok".to_owned()),
+            // the subscription doesn't exist (anymore) on the server, it is unsubscribed either way
+            Response::Result(v) if is_not_found(&v) => return Ok("Ok".to_owned()),
+            Response::Result(v) if v.success == false => return Err(HassError::ReponseError(v)),
+            _ => return Err(HassError::UnknownPayloadReceived),
+        }
+    }
+
+    /// Takes the receiver of the events delivered to the subscriptions.
+    ///
+    /// It is only available once, and only when the connection was opened by the client (`connect`),
+    /// when built with `new` the events are received by the caller's own reading task.
+    /// Events are dropped if the receiver is not drained fast enough.
+    pub fn take_events(&mut self) -> Option<Receiver<WSEvent>> {
+        self.events_taken.store(true, Ordering::Relaxed);
+        self.events.take()
+    }
+
+    /// Takes the events delivered to the subscriptions as a Stream, see [`HassClient::take_events`].
+    pub fn event_stream(&mut self) -> Option<impl Stream<Item = WSEvent>> {
+        self.take_events().map(receiver_stream)
+    }
+
+    /// Returns a Stream of both the responses to the commands and the events of the subscriptions, as they are received.
+    ///
+    /// It allows to handle everything from a single loop: the commands are sent through a [`HassClient::command_sink`],
+    /// taken beforehand, and each response is matched back to its command by its id, as the awaiting methods
+    /// of the client can't be used while the Stream is borrowing it.
+    /// The events dispatched by a connection opened by the client are taken, like `event_stream`, so they are
+    /// no longer available from it. The Stream ends when the connection is closed.
+    /// The messages which were received by `call_services_batch` but were not part of the batch are delivered first.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> hass_rs::HassResult<()> {
+    /// use async_tungstenite::tungstenite::Message;
+    /// use futures::channel::mpsc;
+    /// use futures::{SinkExt, StreamExt};
+    /// use hass_rs::{Ask, Command, HassClient, Incoming, Response};
+    ///
+    /// let (to_server, mut from_client) = mpsc::channel::<Message>(10);
+    /// let (mut to_client, from_server) = mpsc::channel(10);
+    ///
+    /// // a fake server, which answers the ping after firing an event
+    /// tokio::spawn(async move {
+    ///     let reply = |text: &str| Ok(Message::Text(text.to_owned()));
+    ///     to_client.send(reply(r#"{"type": "auth_required", "ha_version": "2024.1.0"}"#)).await.unwrap();
+    ///     from_client.next().await.unwrap();
+    ///     to_client.send(reply(r#"{"type": "auth_ok", "ha_version": "2024.1.0"}"#)).await.unwrap();
+    ///     from_client.next().await.unwrap();
+    ///     to_client.send(reply(r#"{"id": 5, "type": "event", "event": {
+    ///         "data": {"entity_id": "light.kitchen", "new_state": null, "old_state": null},
+    ///         "event_type": "state_changed", "time_fired": "2024-01-01T00:00:00+00:00",
+    ///         "origin": "LOCAL", "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+    ///     }}"#)).await.unwrap();
+    ///     to_client.send(reply(r#"{"id": 1, "type": "pong"}"#)).await.unwrap();
+    /// });
+    ///
+    /// let mut client = HassClient::from_channels(to_server, from_server);
+    /// client.auth_with_longlivedtoken("token").await?;
+    ///
+    /// let mut commands = Box::pin(client.command_sink());
+    /// let ping_id = client.next_id();
+    /// commands
+    ///     .send(Command::Ping(Ask { id: Some(ping_id), msg_type: "ping".to_owned() }))
+    ///     .await?;
+    ///
+    /// let mut incoming = Box::pin(client.incoming());
+    /// match incoming.next().await {
+    ///     Some(Ok(Incoming::Event(event))) => assert_eq!(event.event.data.entity_id, "light.kitchen"),
+    ///     other => panic!("expected an event, got {:?}", other),
+    /// }
+    /// match incoming.next().await {
+    ///     Some(Ok(Incoming::Response(Response::Pong(pong)))) => assert_eq!(pong.id, ping_id),
+    ///     other => panic!("expected the pong, got {:?}", other),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn incoming(&mut self) -> impl Stream<Item = HassResult<Incoming>> + '_ {
+        // the messages received during a batch come first
+        let unmatched = stream::iter(std::mem::take(&mut self.unmatched)).map(Ok);
+        let events = stream::iter(self.take_events())
+            .flat_map(receiver_stream)
+            .map(Incoming::Event)
+            .map(Ok);
+
+        let responses = stream::unfold(self, |client| async move {
+            match client.ws_receive().await {
+                Err(HassError::ConnectionClosed) => None,
+                Ok(Response::Event(event)) => Some((Ok(Incoming::Event(event)), client)),
+                received => Some((received.map(Incoming::Response), client)),
+            }
+        });
+
+        unmatched.chain(stream::select(responses, events))
+    }
+
+    /// Returns a new receiver of all the events delivered to the subscriptions, from a broadcast channel.
+    ///
+    /// Any number of receivers can be created, e.g. one per task, each one gets its own copy of the events
+    /// received after its creation. Like `take_events`, this is only available when the connection was opened by the client.
+    /// The receiver is a `tokio::sync::broadcast::Receiver` with `use-tokio`, an `async_broadcast::Receiver` with `use-async-std`.
+    ///
+    /// The reading of the Websocket never waits for the receivers: the channel holds the last
+    /// `ClientConfig::channel_capacity` events, and a receiver which falls further behind lags, its next `recv`
+    /// returns the lagging error (`Lagged` or `Overflowed`) with the number of events it missed, then the oldest kept event.
+    /// The other receivers are not affected. The channel is closed when the client and its connection are gone.
+    pub fn event_broadcast(&self) -> Option<BroadcastReceiver<WSEvent>> {
+        self.broadcast.as_ref().map(BroadcastSender::subscribe)
+    }
+
+    /// Starts a `ManagedStateCache` of all the entities, on its own connection to the same server
+    ///
+    /// The connection is opened with the configuration of the client and authenticated with its token,
+    /// so the client must have been connected by `ClientConfig::connect` and authenticated by `auth_with_longlivedtoken`.
+    /// The cache reconnects by itself, with the delays of the `ClientConfig::reconnect` policy when it is set,
+    /// and the client is left as it is.
+    ///
+    /// ```no_run
+    /// # async fn example() -> hass_rs::HassResult<()> {
+    /// use hass_rs::ClientConfig;
+    ///
+    /// let mut client = ClientConfig::default()
+    ///     .connect_and_auth("ws://localhost:8123/api/websocket", "your_token")
+    ///     .await?;
+    /// let cache = client.managed_state_cache().await?;
+    /// println!("{:?}", cache.get("sun.sun"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn managed_state_cache(&mut self) -> HassResult<ManagedStateCache> {
+        let origin = self.origin.as_ref().ok_or_else(|| {
+            HassError::Generic(
+                "The state cache requires the client to be connected with connect".to_owned(),
+            )
+        })?;
+        origin.managed_state_cache().await
+    }
+
+    /// Takes the receiver of the raw binary (and ping/pong) frames, e.g. camera streams.
+    ///
+    /// It is only available once, when the connection was opened with `ClientConfig::raw_frames` enabled.
+    /// The caller is then responsible for those frames, they are dropped if not drained fast enough.
+    pub fn raw_frames_stream(&mut self) -> Option<Receiver<TungsteniteMessage>> {
+        self.raw_frames.take()
+    }
+
+    /// Reserves the next message id from the client sequence, see `with_sequence`.
+    ///
+    /// All the commands of the client take their id from here, including the ones resent after a reconnection.
+    ///
+    /// Commands sent through [`HassClient::command_sink`] must carry an id taken from here,
+    /// otherwise the server will reject them for reusing an id.
+    pub fn next_id(&self) -> u64 {
+        get_last_seq(self.last_sequence.as_ref()).expect("could not read the Atomic value")
+    }
+
+    /// Returns a Sink that writes the commands straight to the gateway, without awaiting the replies.
+    ///
+    /// This is meant for high-throughput, fire-and-forget scenarios where awaiting each response
+    /// would serialize everything, so the commands can be pipelined.
+    /// The responses still arrive on the gateway channel: reading them and matching each one
+    /// back to its command (by id) is the caller's responsibility, and they should be drained
+    /// before using the awaiting methods of the client again.
+    pub fn command_sink(&self) -> impl Sink<Command, Error = HassError> {
+        sink::unfold(
+            self.to_gateway.clone(),
+            |to_gateway, cmd: Command| async move {
+                to_gateway
+                    .send(cmd.to_tungstenite_message())
+                    .await
+                    .map_err(|err| HassError::SendError(err.to_string()))?;
+                Ok(to_gateway)
+            },
+        )
+    }
+
+    /// Returns the rolling statistics (min/max/avg/p99) of the command round-trip times.
+    ///
+    /// A rising latency helps to detect a degrading Home Assistant instance or network.
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latencies.stats()
+    }
+
+    /// Sends a command provided as a complete JSON envelope, and returns the response of the server.
+    ///
+    /// The `id` is taken from the client sequence when absent, a provided `id` should come from `next_id`.
+    /// The response is returned as received, nothing is checked except its format.
+    pub async fn send_raw_json(&mut self, mut value: Value) -> HassResult<Response> {
+        let raw = value.as_object_mut().ok_or_else(|| {
+            HassError::Generic("The raw command must be a JSON object".to_owned())
+        })?;
+        if !raw.contains_key("id") {
+            raw.insert("id".to_owned(), self.next_id().into());
+        }
+
+        self.command(Command::Raw(value)).await
+    }
+
+    /// Closes the connection, by sending a close frame to the server.
+    ///
+    /// The client is consumed and its command channel is dropped, which signals
+    /// the task writing to the Websocket that there is nothing left to send.
+    pub async fn close(self) -> HassResult<()> {
+        self.to_gateway
+            .send(Command::Close.to_tungstenite_message())
+            .await
+            .map_err(|err| HassError::SendError(err.to_string()))
+    }
+
+    //used to send commands and receive responses from the gateway
+    pub(crate) async fn command(&mut self, cmd: Command) -> HassResult<Response> {
+        // the server would not answer before the authentication, rather than waiting forever
+        if !self.authenticated && !matches!(cmd, Command::AuthInit(_)) {
+            return Err(HassError::Generic("not authenticated".to_owned()));
+        }
+
+        if self.dry_run && cmd.is_mutating() {
+            log::info!("Dry-run, not sending {:?}", cmd);
+            self.recorded_commands.push(cmd);
+            return Ok(Response::Result(WSResult {
+                id: 0,
+                success: true,
+                result: Some(json!({ "context": { "id": "dry-run" } })),
+                error: None,
+            }));
+        }
+
+        let retry_cmd = cmd.clone();
+        let response = match self.send_command(cmd).await {
+            Err(error) if self.can_reconnect(&error) => {
+                self.reconnect().await?;
+
+                // a command which was not sent, or which only reads, is sent again on the new connection,
+                // Home Assistant may have executed the others before the connection was lost
+                let sent = !matches!(error, HassError::SendError(_));
+                if sent && (retry_cmd.is_mutating() || matches!(retry_cmd, Command::Raw(_))) {
+                    return Err(HassError::CommandInterrupted);
+                }
+                let mut resent = retry_cmd.clone();
+                resent.set_id(self.next_id());
+                self.send_command(resent).await?
+            }
+            response => response?,
+        };
+
+        // the server rejects ids which are not increasing (e.g. after a reconnect),
+        // move the sequence past the rejected id and retry the command once
+        match response {
+            Response::Result(data) if is_id_reuse(&data) => {
+                self.last_sequence.advance_past(data.id);
+
+                let mut retry_cmd = retry_cmd;
+                retry_cmd.set_id(self.next_id());
+                self.send_command(retry_cmd).await
+            }
+            // auth_required is only expected before the authentication, which doesn't go through here
+            Response::AuthRequired(_) => {
+                self.auth_required = true;
+                self.authenticated = false;
+                Err(HassError::AuthenticationRequired)
+            }
+            response => Ok(response),
+        }
+    }
+
+    // true when the `error` is a lost connection, which the client reopens by itself
+    fn can_reconnect(&self, error: &HassError) -> bool {
+        self.origin.as_ref().is_some_and(Origin::reconnects)
+            && self.authenticated
+            && matches!(
+                error,
+                HassError::ConnectionClosed
+                    | HassError::ConnectionClosedWith { .. }
+                    | HassError::SendError(_)
+                    | HassError::TungsteniteError(_)
+            )
+    }
+
+    // opens a new connection, authenticates again and renews the subscriptions
+    async fn reconnect(&mut self) -> HassResult<()> {
+        let origin = self.origin.as_ref().expect("connected by the client");
+        warn!("The connection to Home Assistant was lost, reconnecting");
+        trace_event!(info, "reconnecting");
+
+        let (to_gateway, from_gateway) = origin.reopen().await?;
+        let token = origin.token.clone();
+        self.to_gateway = to_gateway;
+        self.from_gateway = from_gateway;
+        // the round-trips and the heartbeats of the lost connection say nothing about the new one
+        self.latencies = LatencyWindow::default();
+        self.last_pong_rtt = None;
+        self.missed_heartbeats = 0;
+        self.authenticated = false;
+        self.auth_required = false;
+        if let Some(token) = token {
+            self.send_auth(&token).await?;
+        }
+
+        self.renew_subscriptions().await?;
+        trace_event!(
+            info,
+            subscriptions = self.subscriptions.len(),
+            "reconnected"
+        );
+        Ok(())
+    }
+
+    // subscribes again on the new connection, each subscription is moved to its new id before its command is sent,
+    // so a connection lost in the middle leaves them ready to be renewed by the next reconnection
+    async fn renew_subscriptions(&mut self) -> HassResult<()> {
+        let mut ids: Vec<u64> = self.subscribe_commands.keys().copied().collect();
+        ids.sort_unstable();
+
+        for old_id in ids {
+            let Some(mut cmd) = self.subscribe_commands.remove(&old_id) else {
+                continue;
+            };
+            let id = self.next_id();
+            cmd.set_id(id);
+
+            // the subscriptions following diffs start over, before the events of the new connection
+            if let Some(listeners) = self.listeners.clone() {
+                let mut moved = listeners.lock().unwrap();
+                if let Some(mut listener) = moved.remove(&old_id) {
+                    let flush = listener.resets && listener.reset();
+                    moved.insert(id, listener);
+                    drop(moved);
+                    if flush {
+                        spawn(resume_listener(listeners, id));
+                    }
+                }
+            }
+            if let Some(kind) = self.subscriptions.remove(&old_id) {
+                self.subscriptions.insert(id, kind);
+            }
+            self.subscribe_commands.insert(id, cmd.clone());
+
+            match self.send_command(cmd).await? {
+                Response::Result(v) if v.success => {
+                    trace_event!(debug, subscription_id = id, old_id, "subscription renewed");
+                }
+                response => {
+                    warn!(
+                        "Could not renew the subscription {}: {:?}",
+                        old_id, response
+                    );
+                    if let Some(listeners) = &self.listeners {
+                        listeners.lock().unwrap().remove(&id);
+                    }
+                    self.subscriptions.remove(&id);
+                    self.subscribe_commands.remove(&id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //sends a single command and waits for its response
+    async fn send_command(&mut self, cmd: Command) -> HassResult<Response> {
+        //transform to TungsteniteMessage to be sent to WebSocket
+        let cmd_tungstenite = cmd.to_tungstenite_message();
+
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            let span = command_span(&cmd_tungstenite);
+            return self.exchange(cmd_tungstenite).instrument(span).await;
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        self.exchange(cmd_tungstenite).await
+    }
+
+    //sends the message of a command and waits for its response
+    async fn exchange(&mut self, cmd_tungstenite: TungsteniteMessage) -> HassResult<Response> {
+        // Send the auth command to gateway
+        #[cfg(feature = "use-tokio")]
+        self.to_gateway
+            .send(cmd_tungstenite)
+            .await
+            .map_err(|err| HassError::SendError(err.to_string()))?;
+
+        #[cfg(feature = "use-async-std")]
+        self.to_gateway
+            .send(cmd_tungstenite)
+            .await
+            .map_err(|err| HassError::SendError(err.to_string()))?;
+        let sent_at = Instant::now();
+        trace_event!(trace, "command sent");
+
+        let response = self.ws_receive().await;
+        self.latencies.record(sent_at.elapsed());
+        trace_event!(
+            trace,
+            elapsed_ms = sent_at.elapsed().as_millis() as u64,
+            ok = response.is_ok(),
+            "response received"
+        );
+        response
+    }
+
+    // keeps the raw message for last_raw_response, when enabled and it is the result of a command
+    fn retain_raw_response(&mut self, data: &str) {
+        if self.keep_raw_responses && is_result(data) {
+            self.last_raw_response = Some(data.to_owned());
+        }
+    }
+
+    //read the messages from the Websocket connection
+    //
+    // tungstenite reassembles the fragmented messages, so a message which doesn't parse as a whole,
+    // e.g. one cut by a proxy, fails the command waiting for it rather than leaving it waiting forever.
+    // The messages from LARGE_MESSAGE_SIZE are deserialized on the blocking thread pool.
+    pub(crate) async fn ws_receive(&mut self) -> HassResult<Response> {
+        #[cfg(feature = "use-tokio")]
+        let received = self.from_gateway.recv().await;
+        #[cfg(feature = "use-async-std")]
+        let received = self.from_gateway.recv().await.ok();
+
+        match received {
+            Some(Ok(TungsteniteMessage::Text(data))) => {
+                //Serde: The tag identifying which variant we are dealing with is now inside of the content,
+                // next to any other fields of the variant
+                self.retain_raw_response(&data);
+                if let Some(err) = not_json_error(&data) {
+                    return Err(err);
+                }
+                if data.len() < LARGE_MESSAGE_SIZE {
+                    return serde_json::from_str(&data).map_err(HassError::UnableToDeserialize);
+                }
+                spawn_blocking(move || serde_json::from_str(&data))
+                    .await
+                    .map_err(HassError::UnableToDeserialize)
+            }
+            Some(Ok(TungsteniteMessage::Close(frame))) => Err(HassError::from(frame)),
+            Some(Ok(_)) => Err(HassError::UnknownPayloadReceived),
+            Some(Err(error)) => Err(HassError::from(&error)),
+            // the task reading the Websocket ended, the connection is gone
+            None => Err(HassError::ConnectionClosed),
+        }
+    }
+}
+
+/// convenient function that validates if the message received is an Event
+/// the Events should be processed by used in a separate async task
 
 pub fn check_if_event(message: &Result<TungsteniteMessage, Error>) -> HassResult<WSEvent> {
     match message {
         Ok(TungsteniteMessage::Text(data)) => {
+            // Only the message type is read first, without allocating the rest of the message,
+            // so the large results (e.g. get_states) are not fully deserialized here, on the receive path,
+            // but only once by the client waiting for them
+            match serde_json::from_str::<MessageType>(data) {
+                Ok(kind) if kind.msg_type == "event" => {}
+                _ => return Err(HassError::UnknownPayloadReceived),
+            }
+
             //Serde: The tag identifying which variant we are dealing with is now inside of the content,
             // next to any other fields of the variant
 
-            let payload: Result<Response, HassError> =
-                serde_json::from_str(&data).map_err(|err| HassError::from(err));
+            let payload: Result<Response, HassError> =
+                serde_json::from_str(&data).map_err(|err| HassError::from(err));
+
+            if let Ok(Response::Event(event)) = payload {
+                Ok(event)
+            } else {
+                Err(HassError::UnknownPayloadReceived)
+            }
+        }
+        Ok(TungsteniteMessage::Close(frame)) => Err(HassError::from(frame.clone())),
+        Err(error) => {
+            let err = Err(HassError::from(error));
+            err
+        }
+        _ => return Err(HassError::UnknownPayloadReceived),
+    }
+}
+
+// a frame which is obviously not JSON, e.g. an HTML error page injected by a proxy,
+// is reported with the beginning of its content rather than as a serde error
+fn not_json_error(data: &str) -> Option<HassError> {
+    if data.trim_start().starts_with(['{', '[']) {
+        return None;
+    }
+
+    let snippet: String = data.chars().take(NOT_JSON_SNIPPET_LEN).collect();
+    Some(HassError::Generic(format!(
+        "Received a message which is not JSON, is a proxy in the way? It starts with: {:?}",
+        snippet
+    )))
+}
+
+// runs the command, failing with `HassError::Timeout` when it doesn't complete `within` the duration
+async fn within<T>(
+    duration: Duration,
+    command: impl std::future::Future<Output = HassResult<T>>,
+) -> HassResult<T> {
+    timeout(duration, command)
+        .await
+        .unwrap_or(Err(HassError::Timeout))
+}
+
+// true when the message is a `result`, even if it doesn't deserialize as a Response
+fn is_result(data: &str) -> bool {
+    match leading_message_type(data) {
+        Some(kind) => kind == "result",
+        None => {
+            serde_json::from_str::<MessageType>(data).is_ok_and(|kind| kind.msg_type == "result")
+        }
+    }
+}
+
+// the id and the type of a command, the rest of it (e.g. the access token) is not read
+#[cfg(feature = "tracing")]
+#[derive(serde::Deserialize)]
+struct CommandHeader {
+    id: Option<u64>,
+    #[serde(rename = "type")]
+    msg_type: String,
+}
+
+// the span covering a command, from its sending to its response
+#[cfg(feature = "tracing")]
+fn command_span(message: &TungsteniteMessage) -> tracing::Span {
+    let span = tracing::debug_span!(
+        "hass_command",
+        id = tracing::field::Empty,
+        msg_type = tracing::field::Empty
+    );
+    if let TungsteniteMessage::Text(data) = message {
+        if let Ok(header) = serde_json::from_str::<CommandHeader>(data) {
+            if let Some(id) = header.id {
+                span.record("id", id);
+            }
+            span.record("msg_type", header.msg_type.as_str());
+        }
+    }
+    span
+}
+
+// formats the time as an ISO 8601 datetime in UTC, e.g. "2024-01-31T12:00:00Z"
+fn iso_datetime(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // the civil date of the day, from "chrono-Compatible Low-Level Date Algorithms" by Howard Hinnant
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// checks if the entity id matches any of the glob patterns
+fn matches_any<P: AsRef<str>>(patterns: &[P], entity_id: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| entity_id_matches(pattern.as_ref(), entity_id))
+}
+
+// checks if the server rejected the command id for not being increasing
+fn is_id_reuse(result: &WSResult) -> bool {
+    !result.success
+        && result
+            .error
+            .as_ref()
+            .is_some_and(|error| error.code == "id_reuse")
+}
+
+// checks if the server rejected the command for targeting something which doesn't exist
+fn is_not_found(result: &WSResult) -> bool {
+    !result.success
+        && result
+            .error
+            .as_ref()
+            .is_some_and(|error| error.code == "not_found")
+}
+
+// message sequence required by the Websocket server
+fn get_last_seq(last_sequence: &dyn SequenceSource) -> Option<u64> {
+    // Increase the last sequence and use the previous value in the request
+    match last_sequence.next_id() {
+        0 => None,
+        v => Some(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{
+        area, config, device, entity_state, failure, listen, reconnecting, registry_entry,
+        state_changed, success, MockServer,
+    };
+    use futures::SinkExt;
+
+    #[tokio::test]
+    async fn command_sink_pipelines_fire_events() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let mut commands = Box::pin(client.command_sink());
+        let ids: Vec<u64> = (0..5).map(|_| client.next_id()).collect();
+        for (n, id) in ids.iter().enumerate() {
+            let fire_event = json!({
+                "id": id,
+                "type": "fire_event",
+                "event_type": "pipelined",
+                "event_data": {"n": n},
+            });
+            commands.send(Command::Raw(fire_event)).await.unwrap();
+        }
+
+        // all the commands are written before any of them is answered
+        for (n, id) in ids.iter().enumerate() {
+            let command = server.receive().await;
+            assert_eq!(command["type"], "fire_event");
+            assert_eq!(command["id"], *id);
+            assert_eq!(command["event_data"]["n"], n);
+        }
+        for id in ids.iter().rev() {
+            let context = json!({"context": {"id": format!("fired-{}", id)}});
+            server
+                .send_json(success(&json!({ "id": id }), context))
+                .await;
+        }
+
+        let mut incoming = Box::pin(client.incoming());
+        let mut answered = Vec::new();
+        for _ in &ids {
+            match incoming.next().await {
+                Some(Ok(Incoming::Response(Response::Result(result)))) => {
+                    assert!(result.success);
+                    answered.push(result.id);
+                }
+                other => panic!("expected a result, got {:?}", other),
+            }
+        }
+        answered.reverse();
+        assert_eq!(answered, ids);
+    }
+
+    #[tokio::test]
+    async fn latency_stats_average_the_round_trips() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        // a server answering the pings after 40ms and 80ms
+        let server = tokio::spawn(async move {
+            for delay in [40, 80] {
+                let ping = server.receive().await;
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                server
+                    .send_json(json!({"id": ping["id"], "type": "pong"}))
+                    .await;
+            }
+            server
+        });
+        client.ping().await.unwrap();
+        client.ping().await.unwrap();
+        server.await.unwrap();
+
+        // the authentication is the first round-trip, it was answered right away
+        let stats = client.latency_stats();
+        assert_eq!(stats.samples, 3);
+        assert!(stats.max >= Duration::from_millis(80));
+        assert!(stats.avg >= Duration::from_millis(40));
+        assert!(stats.avg < stats.max);
+    }
+
+    #[tokio::test]
+    async fn latency_stats_start_over_after_a_reconnection() {
+        let (listener, url) = listen().await;
+        let (mut client, mut server) =
+            MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        let (pong, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            server
+                .send_json(json!({"id": ping["id"], "type": "pong"}))
+                .await;
+        });
+        pong.unwrap();
+        assert!(client.latency_stats().max >= Duration::from_millis(300));
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            let mut lost = server;
+            lost.receive().await;
+            drop(lost);
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            server.reply(json!([])).await;
+        });
+        states.unwrap();
+
+        // only the authentication and the resent command of the new connection are left
+        let stats = client.latency_stats();
+        assert_eq!(stats.samples, 2);
+        assert!(stats.max < Duration::from_millis(300));
+    }
+
+    // the client measures the round-trips with the clock of tokio, which async-std can't pause
+    #[cfg(feature = "use-tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn heartbeats_are_timed_by_the_clock() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        assert_eq!(client.last_pong_rtt(), None);
+
+        // the clock jumps to the deadline of the unanswered pings
+        for missed in 1..=2 {
+            let pong = client.ping_within(Duration::from_secs(10)).await;
+            assert!(matches!(pong, Err(HassError::Timeout)));
+            assert_eq!(server.receive().await["type"], "ping");
+            assert_eq!(client.missed_heartbeats(), missed);
+        }
+
+        let (pong, ()) = futures::join!(client.ping_within(Duration::from_secs(10)), async {
+            let ping = server.receive().await;
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            server
+                .send_json(json!({"id": ping["id"], "type": "pong"}))
+                .await;
+        });
+        assert_eq!(pong.unwrap(), "pong");
+        assert_eq!(client.last_pong_rtt(), Some(Duration::from_millis(250)));
+        assert_eq!(client.missed_heartbeats(), 0);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_start_over_after_a_reconnection() {
+        let (listener, url) = listen().await;
+        let (mut client, mut server) =
+            MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        let (pong, ()) = futures::join!(client.ping(), server.pong());
+        pong.unwrap();
+        let (pong, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            server
+                .send_json(json!({
+                    "id": ping["id"], "type": "result", "success": false,
+                    "error": {"code": "unknown_error", "message": "Unknown error"}
+                }))
+                .await;
+        });
+        assert!(pong.is_err());
+        assert!(client.last_pong_rtt().is_some());
+        assert_eq!(client.missed_heartbeats(), 1);
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            let mut lost = server;
+            lost.receive().await;
+            drop(lost);
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            server.reply(json!([])).await;
+        });
+        states.unwrap();
+        assert_eq!(client.last_pong_rtt(), None);
+        assert_eq!(client.missed_heartbeats(), 0);
+    }
+
+    #[tokio::test]
+    async fn only_the_results_are_kept_as_raw_responses() {
+        let (client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let mut client = client.with_raw_responses(true);
+        assert_eq!(client.last_raw_response(), None);
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            let command = server.receive().await;
+            server
+                .send_event(1, state_changed("light.kitchen", Some("on")))
+                .await;
+            server
+                .send_json(success(
+                    &command,
+                    json!([entity_state("light.kitchen", "on")]),
+                ))
+                .await;
+        });
+        states.unwrap();
+        let raw: Value = serde_json::from_str(client.last_raw_response().unwrap()).unwrap();
+        assert_eq!(raw["type"], "result");
+        assert_eq!(raw["result"][0]["entity_id"], "light.kitchen");
+
+        // the pong is not a result, the last one is kept
+        let (pong, ()) = futures::join!(client.ping(), server.pong());
+        pong.unwrap();
+        let raw: Value = serde_json::from_str(client.last_raw_response().unwrap()).unwrap();
+        assert_eq!(raw["type"], "result");
+
+        // a result which doesn't match the type is kept, to find out why
+        let (config, _) =
+            futures::join!(client.get_config(), server.reply(json!({"unit_system": 1})));
+        assert!(config.is_err());
+        let raw: Value = serde_json::from_str(client.last_raw_response().unwrap()).unwrap();
+        assert_eq!(raw["result"]["unit_system"], 1);
+    }
+
+    #[tokio::test]
+    async fn statistics_metadata_is_listed_and_selected_by_id() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let statistic = |statistic_id: &str, unit: &str| {
+            json!({
+                "statistic_id": statistic_id, "name": null, "source": "recorder",
+                "display_unit_of_measurement": unit, "has_mean": true, "has_sum": false,
+                "statistics_unit_of_measurement": unit, "unit_class": null
+            })
+        };
+
+        let ids = vec!["sensor.outside_temperature".to_owned()];
+        let (metadata, command) = futures::join!(
+            client.get_statistics_metadata(Some(ids)),
+            server.reply(json!([
+                statistic("sensor.outside_temperature", "°C"),
+                statistic("sensor.power", "W"),
+            ]))
+        );
+        assert_eq!(
+            command,
+            json!({"id": command["id"], "type": "recorder/list_statistic_ids"})
+        );
+        assert_eq!(
+            metadata.unwrap(),
+            [StatisticMeta {
+                statistic_id: "sensor.outside_temperature".to_owned(),
+                name: None,
+                source: "recorder".to_owned(),
+                unit_of_measurement: Some("°C".to_owned()),
+                has_mean: true,
+                has_sum: false,
+                statistics_unit_of_measurement: Some("°C".to_owned()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn scenes_scripts_and_automations_are_called_with_their_target() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let called = json!({"context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}});
+
+        let (scene, command) = futures::join!(
+            client.activate_scene("scene.movie_night"),
+            server.reply(called.clone())
+        );
+        scene.unwrap();
+        assert_eq!(
+            command,
+            json!({
+                "id": command["id"], "type": "call_service", "domain": "scene", "service": "turn_on",
+                "service_data": null, "target": {"entity_id": ["scene.movie_night"]}
+            })
+        );
+
+        let (automation, command) = futures::join!(
+            client.trigger_automation("automation.wake_up"),
+            server.reply(called.clone())
+        );
+        automation.unwrap();
+        assert_eq!(
+            command,
+            json!({
+                "id": command["id"], "type": "call_service", "domain": "automation", "service": "trigger",
+                "service_data": null, "target": {"entity_id": ["automation.wake_up"]}
+            })
+        );
+
+        let variables = json!({"message": "Good morning"});
+        let (script, command) = futures::join!(
+            client.run_script("script.notify_all", Some(variables.clone())),
+            server.reply(called)
+        );
+        script.unwrap();
+        assert_eq!(
+            command,
+            json!({
+                "id": command["id"], "type": "call_service", "domain": "script", "service": "notify_all",
+                "service_data": variables
+            })
+        );
+
+        let not_a_script = client.run_script("scene.movie_night", None).await;
+        assert!(matches!(not_a_script, Err(HassError::Generic(_))));
+    }
+
+    #[tokio::test]
+    async fn waiting_until_the_started_event() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let started_at = Instant::now();
+        let (started, ()) = futures::join!(
+            client.wait_until_started(Duration::from_secs(10)),
+            async {
+                let subscribe = server.reply(Value::Null).await;
+                assert_eq!(
+                    subscribe,
+                    json!({"id": subscribe["id"], "type": "subscribe_events", "event_type": "homeassistant_started"})
+                );
+                let get_config = server.reply(config("STARTING")).await;
+                assert_eq!(get_config["type"], "get_config");
+
+                let event = json!({
+                    "event_type": "homeassistant_started", "data": {}, "origin": "LOCAL",
+                    "time_fired": "2024-01-01T00:00:00+00:00", "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+                });
+                server
+                    .send_event(subscribe["id"].as_u64().unwrap(), event)
+                    .await;
+
+                let unsubscribe = server.reply(Value::Null).await;
+                assert_eq!(unsubscribe["type"], "unsubscribe_events");
+                assert_eq!(unsubscribe["subscription"], subscribe["id"]);
+            }
+        );
+        started.unwrap();
+        // woken up by the event, not by the next check of the config
+        assert!(started_at.elapsed() < STARTED_POLL_INTERVAL);
+        assert!(client.subscriptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiting_until_started_gives_up_on_an_unanswered_command() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (started, ()) = futures::join!(
+            client.wait_until_started(Duration::from_millis(200)),
+            async {
+                let get_config = server.reply(config("NOT_RUNNING")).await;
+                assert_eq!(get_config["type"], "get_config");
+                // the next check is never answered
+                assert_eq!(server.receive().await["type"], "get_config");
+            }
+        );
+        assert!(matches!(started, Err(HassError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn every_broadcast_receiver_sees_the_events() {
+        let config = ClientConfig::default().channel_capacity(2);
+        let (mut client, mut server) = MockServer::connected(config).await;
+        let (subscribed, _) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(Value::Null)
+        );
+        let id = subscribed.unwrap().id;
+
+        let mut first = client.event_broadcast().unwrap();
+        let mut second = client.event_broadcast().unwrap();
+        server
+            .send_event(id, state_changed("light.kitchen", Some("on")))
+            .await;
+        for receiver in [&mut first, &mut second] {
+            let event = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.id, id);
+            assert_eq!(event.event.data.entity_id, "light.kitchen");
+        }
+
+        // the first receiver falls behind, it misses the oldest event, the second one keeps up
+        for entity_id in ["light.hall", "light.porch", "light.garage"] {
+            server
+                .send_event(id, state_changed(entity_id, Some("on")))
+                .await;
+            let event = tokio::time::timeout(Duration::from_secs(5), second.recv()).await;
+            assert_eq!(event.unwrap().unwrap().event.data.entity_id, entity_id);
+        }
+        assert!(first.recv().await.is_err());
+        for entity_id in ["light.porch", "light.garage"] {
+            let event = first.recv().await.unwrap();
+            assert_eq!(event.event.data.entity_id, entity_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_events_tell_their_subscription() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let (states, _) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(Value::Null)
+        );
+        let (calls, _) = futures::join!(
+            client.subscribe_event("call_service"),
+            server.reply(Value::Null)
+        );
+        let (states, calls) = (states.unwrap().id, calls.unwrap().id);
+
+        let mut events = Box::pin(client.event_stream().unwrap());
+        let mut call = state_changed("light.kitchen", None);
+        call["event_type"] = json!("call_service");
+        server.send_event(calls, call).await;
+        server
+            .send_event(states, state_changed("light.kitchen", Some("on")))
+            .await;
+
+        for (id, event_type) in [(calls, "call_service"), (states, "state_changed")] {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.id, id);
+            assert_eq!(client.subscriptions[&event.id], event_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn every_command_takes_its_id_from_the_sequence() {
+        let (client, mut server) = MockServer::client();
+        let mut client = client.with_sequence(AtomicSequence::starting_at(1000));
+        let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("token"), server.auth());
+        auth.unwrap();
+
+        let (_, get_states) = futures::join!(client.get_states(), server.reply(json!([])));
+        let (_, subscribe) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(Value::Null)
+        );
+        let (_, call) = futures::join!(
+            client.call_service("light".to_owned(), "turn_on".to_owned(), None),
+            server.reply(json!({"context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}}))
+        );
+        let (_, unsubscribe) =
+            futures::join!(client.unsubscribe_event(1001), server.reply(Value::Null));
+        let (_, ping) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            server
+                .send_json(json!({"id": ping["id"], "type": "pong"}))
+                .await;
+            ping
+        });
+        let (_, raw) = futures::join!(
+            client.send_raw_json(json!({"type": "get_panels"})),
+            server.reply(json!({}))
+        );
+
+        let ids: Vec<&Value> = [&get_states, &subscribe, &call, &unsubscribe, &ping, &raw]
+            .iter()
+            .map(|command| &command["id"])
+            .collect();
+        assert_eq!(ids, [1000, 1001, 1002, 1003, 1004, 1005]);
+        assert_eq!(client.next_id(), 1006);
+    }
+
+    // the state of the entity updated at the `time`, e.g. "10:01"
+    fn state_at(entity_id: &str, state: &str, time: &str) -> Value {
+        let mut state = entity_state(entity_id, state);
+        let time = format!("2024-01-01T{}:00+00:00", time);
+        state["last_changed"] = json!(time);
+        state["last_updated"] = json!(time);
+        state
+    }
+
+    fn changed_at(entity_id: &str, state: &str, time: &str) -> Value {
+        let mut event = state_changed(entity_id, None);
+        event["data"]["new_state"] = state_at(entity_id, state, time);
+        event
+    }
+
+    #[tokio::test]
+    async fn watching_entities_misses_no_change_around_the_snapshot() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let watched = ["light.kitchen".to_owned(), "light.hall".to_owned()];
+
+        let (watch, ()) = futures::join!(client.watch_entities(&watched), async {
+            let subscribe = server.reply(Value::Null).await;
+            assert_eq!(subscribe["event_type"], "state_changed");
+            let id = subscribe["id"].as_u64().unwrap();
+
+            // a change made while the states are read, which they already include
+            let get_states = server.receive().await;
+            assert_eq!(get_states["type"], "get_states");
+            server
+                .send_event(id, changed_at("light.kitchen", "on", "10:01"))
+                .await;
+            let states = json!([
+                state_at("light.kitchen", "on", "10:01"),
+                state_at("light.hall", "off", "10:00"),
+                state_at("light.porch", "off", "10:00"),
+            ]);
+            server.send_json(success(&get_states, states)).await;
+
+            server
+                .send_event(id, changed_at("light.porch", "on", "10:02"))
+                .await;
+            server
+                .send_event(id, changed_at("light.hall", "on", "10:02"))
+                .await;
+            server
+                .send_event(id, changed_at("light.kitchen", "off", "10:03"))
+                .await;
+        });
+        let (_, states, changes) = watch.unwrap();
+        let states: Vec<(&str, &str)> = states
+            .iter()
+            .map(|state| (state.entity_id.as_str(), state.state.as_str()))
+            .collect();
+        assert_eq!(states, [("light.kitchen", "on"), ("light.hall", "off")]);
+
+        let changes: Vec<EventData> =
+            tokio::time::timeout(Duration::from_secs(5), changes.take(2).collect())
+                .await
+                .unwrap();
+        let changes: Vec<(&str, &str)> = changes
+            .iter()
+            .map(|data| {
+                let state = data.new_state.as_ref().unwrap();
+                (data.entity_id.as_str(), state.state.as_str())
+            })
+            .collect();
+        assert_eq!(changes, [("light.hall", "on"), ("light.kitchen", "off")]);
+    }
+
+    #[tokio::test]
+    async fn a_paused_subscription_holds_its_events_until_resumed() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let (subscribed, subscribe) = futures::join!(
+            client.on_entity_registry_updated(),
+            server.reply(Value::Null)
+        );
+        let (id, changes) = subscribed.unwrap();
+        assert_eq!(subscribe["event_type"], "entity_registry_updated");
+        let mut changes = Box::pin(changes);
+        let updated = |entity_id: &str| {
+            json!({
+                "event_type": "entity_registry_updated",
+                "data": {"action": "update", "entity_id": entity_id}
+            })
+        };
+
+        assert!(client.pause_subscription(id));
+        for entity_id in ["light.kitchen", "light.hall"] {
+            server.send_event(id, updated(entity_id)).await;
+        }
+        let held = tokio::time::timeout(Duration::from_millis(200), changes.next()).await;
+        assert!(held.is_err(), "an event was delivered while paused");
+
+        assert!(client.resume_subscription(id));
+        server.send_event(id, updated("light.porch")).await;
+        for entity_id in ["light.kitchen", "light.hall", "light.porch"] {
+            let change = tokio::time::timeout(Duration::from_secs(5), changes.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(change.id, entity_id);
+        }
+
+        assert!(!client.pause_subscription(id + 100));
+        assert!(!client.resume_subscription(id + 100));
+    }
+
+    #[tokio::test]
+    async fn entity_customization_updates_the_entity_registry() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let entry = |name: Option<&str>| {
+            json!({"entity_entry": {
+                "area_id": null, "config_entry_id": null, "device_id": null, "disabled_by": null,
+                "entity_category": null, "entity_id": "light.kitchen", "has_entity_name": false,
+                "hidden_by": null, "icon": null, "id": "5e2d", "name": name,
+                "options": {}, "original_name": "Kitchen light", "platform": "hue",
+                "translation_key": null, "unique_id": "00:17:88:01"
+            }})
+        };
+
+        let (renamed, update) = futures::join!(
+            client.set_entity_customization("light.kitchen", "friendly_name", Some("Kitchen")),
+            server.reply(entry(Some("Kitchen")))
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/entity_registry/update",
+                "entity_id": "light.kitchen",
+                "name": "Kitchen"
+            })
+        );
+        let renamed = renamed.unwrap();
+        assert_eq!(renamed.entity_id, "light.kitchen");
+        assert_eq!(renamed.name.as_deref(), Some("Kitchen"));
+
+        // None clears the customization, with an explicit null
+        let (reset, update) = futures::join!(
+            client.set_entity_customization("light.kitchen", "icon", None),
+            server.reply(entry(None))
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/entity_registry/update",
+                "entity_id": "light.kitchen",
+                "icon": null
+            })
+        );
+        assert_eq!(reset.unwrap().icon, None);
+
+        // the other keys are refused before anything is sent
+        let refused = client
+            .set_entity_customization("light.kitchen", "device_class", Some("window"))
+            .await;
+        assert!(matches!(refused, Err(HassError::Generic(_))));
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+    }
+
+    #[tokio::test]
+    async fn id_reuse_is_retried_with_a_higher_id() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let server = tokio::spawn(async move {
+            let rejected = server.receive().await;
+            server
+                .send_json(json!({
+                    "id": rejected["id"],
+                    "type": "result",
+                    "success": false,
+                    "error": {"code": "id_reuse", "message": "Identifier values have to increase."}
+                }))
+                .await;
+            let retried = server.receive().await;
+            server
+                .send_json(json!({"id": retried["id"], "type": "pong"}))
+                .await;
+            (rejected, retried)
+        });
+        assert_eq!(client.ping().await.unwrap(), "pong");
+
+        let (rejected, retried) = server.await.unwrap();
+        assert_eq!(retried["type"], "ping");
+        assert!(retried["id"].as_u64() > rejected["id"].as_u64());
+        assert!(client.next_id() > retried["id"].as_u64().unwrap());
+    }
+
+    // the error of a subscription which doesn't exist on the server
+    fn not_found(command: &Value) -> Value {
+        json!({
+            "id": command["id"],
+            "type": "result",
+            "success": false,
+            "error": {"code": "not_found", "message": "Subscription not found."}
+        })
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_an_event_subscription() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (subscribed, subscribe) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(json!(null))
+        );
+        let subscription_id = subscribed.unwrap().id;
+        assert_eq!(subscribe["type"], "subscribe_events");
+        assert!(client.subscriptions.contains_key(&subscription_id));
+
+        let (unsubscribed, unsubscribe) = futures::join!(
+            client.unsubscribe_event(subscription_id),
+            server.reply(json!(null))
+        );
+        assert_eq!(unsubscribed.unwrap(), "Ok");
+        assert_eq!(unsubscribe["type"], "unsubscribe_events");
+        assert_eq!(unsubscribe["subscription"], subscription_id);
+        assert!(client.subscriptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_a_trigger_subscription() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let trigger = json!({"platform": "state", "entity_id": "light.kitchen"});
+        let (subscribed, subscribe) =
+            futures::join!(client.subscribe_trigger(trigger), server.reply(json!(null)));
+        let (subscription_id, triggers) = subscribed.unwrap();
+        assert_eq!(subscribe["type"], "subscribe_trigger");
+
+        let variables = json!({"variables": {"trigger": {"platform": "state"}}, "context": null});
+        server.send_event(subscription_id, variables).await;
+        let mut triggers = Box::pin(triggers);
+        assert_eq!(triggers.next().await.unwrap()["platform"], "state");
+
+        // the trigger subscriptions are also removed with unsubscribe_events
+        let (unsubscribed, unsubscribe) = futures::join!(
+            client.unsubscribe_event(subscription_id),
+            server.reply(json!(null))
+        );
+        assert_eq!(unsubscribed.unwrap(), "Ok");
+        assert_eq!(unsubscribe["type"], "unsubscribe_events");
+        assert_eq!(unsubscribe["subscription"], subscription_id);
+        assert!(client.subscriptions.is_empty());
+        // the listener was dropped with the subscription
+        assert!(triggers.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unsubscribing_twice_succeeds() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (subscribed, _) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(json!(null))
+        );
+        let subscription_id = subscribed.unwrap().id;
+        let (unsubscribed, _) = futures::join!(
+            client.unsubscribe_event(subscription_id),
+            server.reply(json!(null))
+        );
+        unsubscribed.unwrap();
+
+        // the server no longer knows the subscription
+        let server = tokio::spawn(async move {
+            let unsubscribe = server.receive().await;
+            server.send_json(not_found(&unsubscribe)).await;
+        });
+        assert_eq!(
+            client.unsubscribe_event(subscription_id).await.unwrap(),
+            "Ok"
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dry_run_records_the_service_calls_without_sending_them() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        client.set_dry_run(true);
+
+        let data = json!({"entity_id": "light.kitchen"});
+        let called = client
+            .call_service("light".to_owned(), "turn_on".to_owned(), Some(data.clone()))
+            .await;
+        assert!(called.is_ok());
+        match client.recorded_commands() {
+            [Command::CallService(call)] => {
+                assert_eq!(call.domain, "light");
+                assert_eq!(call.service, "turn_on");
+                assert_eq!(call.service_data, Some(data));
+            }
+            recorded => panic!("expected the service call, got {:?}", recorded),
+        }
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+
+        // the made up result is told by its context
+        let context = client
+            .call(crate::LightTurnOn::new("light.hall").build())
+            .await
+            .unwrap();
+        assert_eq!(context.id, "dry-run");
+        assert_eq!(context.parent_id, None);
+        let result = client
+            .send_call_service("light".to_owned(), "turn_off".to_owned(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(result.id, 0);
+        assert_eq!(client.recorded_commands().len(), 3);
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+
+        // the reads are still sent
+        let (pong, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            server
+                .send_json(json!({"id": ping["id"], "type": "pong"}))
+                .await;
+        });
+        assert_eq!(pong.unwrap(), "pong");
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages_are_reassembled() {
+        use async_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+        use async_tungstenite::tungstenite::protocol::frame::Frame;
+
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let (pong, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            let pong = json!({"id": ping["id"], "type": "pong"}).to_string();
+            let (start, end) = pong.split_at(pong.len() / 2);
+            let fragments = [
+                (OpCode::Data(Data::Text), start, false),
+                (OpCode::Data(Data::Continue), end, true),
+            ];
+            for (opcode, fragment, is_final) in fragments {
+                let frame = Frame::message(fragment.as_bytes().to_vec(), opcode, is_final);
+                server.send_frame(TungsteniteMessage::Frame(frame)).await;
+            }
+        });
+        assert_eq!(pong.unwrap(), "pong");
+    }
+
+    #[tokio::test]
+    async fn a_truncated_message_fails_the_command() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (pong, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            let pong = json!({"id": ping["id"], "type": "pong"}).to_string();
+            server.send(&pong[..pong.len() - 3]).await;
+        });
+        assert!(matches!(pong, Err(HassError::UnableToDeserialize(_))));
+    }
+
+    #[tokio::test]
+    async fn an_event_is_not_held_up_by_a_large_response() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let mut events = Box::pin(client.event_stream().unwrap());
+
+        // several MB of states, followed by an event
+        let states: Vec<Value> = (0..20_000)
+            .map(|n| entity_state(&format!("sensor.temperature_{}", n), "21.5"))
+            .collect();
+        let server = tokio::spawn(async move {
+            let get_states = server.receive().await;
+            server.send_json(success(&get_states, json!(states))).await;
+            server
+                .send_event(1, state_changed("light.kitchen", Some("on")))
+                .await;
+            server
+        });
+
+        let started = Instant::now();
+        let ((states, states_at), event_at) = futures::join!(
+            async { (client.get_states().await, started.elapsed()) },
+            async {
+                let event = events.next().await.unwrap();
+                assert_eq!(event.event.data.entity_id, "light.kitchen");
+                started.elapsed()
+            },
+        );
+        assert_eq!(states.unwrap().len(), 20_000);
+        // the event was delivered while the states were deserialized, rather than after
+        assert!(event_at < states_at);
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_batch_of_three_calls_keeps_the_other_messages() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        // the responses come in the reverse order, with an event in the middle
+        let server = tokio::spawn(async move {
+            let mut calls = Vec::new();
+            for _ in 0..3 {
+                calls.push(server.receive().await);
+            }
+            for (n, call) in calls.iter().rev().enumerate() {
+                if n == 1 {
+                    server
+                        .send_event(1, state_changed("light.kitchen", Some("on")))
+                        .await;
+                }
+                let context = json!({"context": {"id": format!("call-{}", call["id"])}});
+                server.send_json(success(call, context)).await;
+            }
+            (calls, server)
+        });
+
+        let lights = ["light.kitchen", "light.hall", "light.porch"];
+        let batch = lights
+            .iter()
+            .map(|light| crate::LightTurnOn::new(light).build())
+            .collect();
+        let results = client.call_services_batch(batch).await.unwrap();
+        let (calls, _server) = server.await.unwrap();
+
+        assert_eq!(results.len(), 3);
+        for ((result, call), light) in results.into_iter().zip(&calls).zip(lights) {
+            assert_eq!(result.unwrap().id, format!("call-{}", call["id"]));
+            assert_eq!(call["target"]["entity_id"], json!([light]));
+        }
+
+        let mut incoming = Box::pin(client.incoming());
+        match incoming.next().await {
+            Some(Ok(Incoming::Event(event))) => {
+                assert_eq!(event.event.data.entity_id, "light.kitchen")
+            }
+            other => panic!("expected the event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_stalled_connection_recovers_by_reconnecting() {
+        let (listener, url) = listen().await;
+        let (mut client, mut stalled) =
+            MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        // the ping reaches the server, which never answers it, as when a proxy lost the server behind it
+        let (pong, mut server) =
+            futures::join!(client.ping_within(Duration::from_millis(200)), async {
+                let ping = stalled.receive().await;
+                assert_eq!(ping["type"], "ping");
+                let mut server = MockServer::accept(&listener).await;
+                server.auth().await;
+                server.pong().await;
+                server
+            });
+        assert_eq!(pong.unwrap(), "pong");
+        assert_eq!(client.missed_heartbeats(), 0);
+
+        // the next commands go through the new connection
+        let (pong, ()) = futures::join!(client.ping(), server.pong());
+        assert_eq!(pong.unwrap(), "pong");
+    }
+
+    #[tokio::test]
+    async fn a_command_interrupted_by_a_lost_connection_is_resent_or_fails() {
+        let (listener, url) = listen().await;
+        let (mut client, server) = MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        // a read is sent again on the new connection
+        let (states, server) = futures::join!(client.get_states(), async {
+            let mut lost = server;
+            assert_eq!(lost.receive().await["type"], "get_states");
+            drop(lost);
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            let resent = server
+                .reply(json!([entity_state("light.kitchen", "on")]))
+                .await;
+            assert_eq!(resent["type"], "get_states");
+            server
+        });
+        assert_eq!(states.unwrap()[0].entity_id, "light.kitchen");
+
+        // a service call may have been executed, it is not sent again
+        let light = ("light".to_owned(), "turn_on".to_owned());
+        let (called, mut server) =
+            futures::join!(client.call_service(light.0, light.1, None), async {
+                let mut lost = server;
+                assert_eq!(lost.receive().await["type"], "call_service");
+                drop(lost);
+
+                let mut server = MockServer::accept(&listener).await;
+                server.auth().await;
+                server
+            });
+        assert!(matches!(called, Err(HassError::CommandInterrupted)));
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+    }
+
+    #[tokio::test]
+    async fn a_ping_and_a_read_interrupted_by_a_crashed_server_are_resent() {
+        let (listener, url) = listen().await;
+        let (mut client, server) = MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        // the pong never comes, the socket is reset instead
+        let (pong, server) = futures::join!(client.ping_within(Duration::from_secs(2)), async {
+            let mut lost = server;
+            assert_eq!(lost.receive().await["type"], "ping");
+            lost.drop_connection().await;
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            server.pong().await;
+            server
+        });
+        assert_eq!(pong.unwrap(), "pong");
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            let mut lost = server;
+            assert_eq!(lost.receive().await["type"], "get_states");
+            lost.drop_connection().await;
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            let resent = server
+                .reply(json!([entity_state("light.kitchen", "on")]))
+                .await;
+            assert_eq!(resent["type"], "get_states");
+        });
+        assert_eq!(states.unwrap()[0].entity_id, "light.kitchen");
+    }
+
+    #[tokio::test]
+    async fn a_read_is_retried_after_a_reset_socket() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (states, ()) = futures::join!(client.get_states_retrying(2), async {
+            assert_eq!(server.receive().await["type"], "get_states");
+            let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+            server.send_error(Error::Io(reset)).await;
+
+            let retried = server
+                .reply(json!([entity_state("light.kitchen", "on")]))
+                .await;
+            assert_eq!(retried["type"], "get_states");
+        });
+        assert_eq!(states.unwrap()[0].entity_id, "light.kitchen");
+    }
+
+    #[tokio::test]
+    async fn a_read_is_not_retried_after_a_refused_handshake() {
+        let (listener, url) = listen().await;
+        let (mut client, server) = MockServer::connected_to(reconnecting(), &listener, &url).await;
 
-            if let Ok(Response::Event(event)) = payload {
-                Ok(event)
-            } else {
-                Err(HassError::UnknownPayloadReceived)
+        // the token was revoked while the connection was lost
+        let (states, ()) = futures::join!(client.get_states_retrying(3), async {
+            let mut lost = server;
+            assert_eq!(lost.receive().await["type"], "get_states");
+            lost.drop_connection().await;
+            MockServer::refuse(&listener, 401).await;
+        });
+        match states {
+            Err(HassError::TungsteniteError(error)) => match *error {
+                Error::Http(response) => assert_eq!(response.status(), 401),
+                other => panic!("expected the refused handshake, got {:?}", other),
+            },
+            other => panic!("expected the refused handshake, got {:?}", other),
+        }
+
+        // neither the reconnection nor the read is attempted again
+        let again = tokio::time::timeout(Duration::from_millis(700), listener.accept()).await;
+        assert!(again.is_err());
+    }
+
+    #[tokio::test]
+    async fn subscribe_entities_starts_over_after_a_reconnection() {
+        let (listener, url) = listen().await;
+        let (mut client, mut server) =
+            MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        let (subscribed, subscribe) =
+            futures::join!(client.subscribe_entities(None), server.reply(Value::Null));
+        let (_, updates) = subscribed.unwrap();
+        let mut updates = Box::pin(updates);
+        assert_eq!(subscribe["type"], "subscribe_entities");
+        let id = subscribe["id"].as_u64().unwrap();
+
+        let light = |state: &str| json!({"s": state, "a": {}, "c": "01HPRMZAWNXKVVPSP11QFJ53HB", "lc": 1704067200.0});
+        server
+            .send_event(
+                id,
+                json!({"a": {"light.kitchen": light("on"), "light.hall": light("off")}}),
+            )
+            .await;
+        server
+            .send_event(
+                id,
+                json!({"c": {"light.kitchen": {"+": {"s": "off", "lc": 1704067260.0}}}}),
+            )
+            .await;
+        let mut states = HashMap::new();
+        for _ in 0..2 {
+            next_update(&mut updates).await.apply(&mut states);
+        }
+        assert_eq!(states["light.kitchen"].state, "off");
+        assert_eq!(states.len(), 2);
+
+        // the connection is lost mid-stream, and the hall light is removed meanwhile
+        drop(server);
+        let (pong, _server) = futures::join!(client.ping(), async {
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            let renewed = server.reply(Value::Null).await;
+            assert_eq!(renewed["type"], "subscribe_entities");
+            let renewed_id = renewed["id"].as_u64().unwrap();
+            server
+                .send_event(renewed_id, json!({"a": {"light.kitchen": light("on")}}))
+                .await;
+            server.pong().await;
+            server
+        });
+        assert_eq!(pong.unwrap(), "pong");
+
+        let reset = next_update(&mut updates).await;
+        assert_eq!(reset, EntitiesUpdate::Reset);
+        reset.apply(&mut states);
+        next_update(&mut updates).await.apply(&mut states);
+        let entities: Vec<_> = states.keys().collect();
+        assert_eq!(entities, ["light.kitchen"]);
+        assert_eq!(states["light.kitchen"].state, "on");
+    }
+
+    // the next update of the entities, failing instead of waiting forever
+    async fn next_update(
+        updates: &mut (impl Stream<Item = EntitiesUpdate> + Unpin),
+    ) -> EntitiesUpdate {
+        timeout(Duration::from_secs(5), updates.next())
+            .await
+            .expect("no update of the entities")
+            .expect("the updates ended")
+    }
+
+    #[cfg(feature = "tracing")]
+    mod tracing_spans {
+        use super::*;
+
+        // a tracing subscriber recording the spans and the events, with their fields
+        #[derive(Default, Clone)]
+        struct Recorder {
+            spans: std::sync::Arc<std::sync::Mutex<Vec<(String, HashMap<String, String>)>>>,
+            // the message of each event, with the index of the span it was emitted in
+            events: std::sync::Arc<std::sync::Mutex<Vec<(String, Option<usize>)>>>,
+            entered: std::sync::Arc<std::sync::Mutex<Vec<usize>>>,
+        }
+
+        struct Fields<'a>(&'a mut HashMap<String, String>);
+
+        impl tracing::field::Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                self.0
+                    .insert(field.name().to_owned(), format!("{:?}", value));
+            }
+
+            fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                self.0.insert(field.name().to_owned(), value.to_owned());
             }
         }
-        Err(error) => {
-            let err = Err(HassError::from(error));
-            err
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+                let mut fields = HashMap::new();
+                span.record(&mut Fields(&mut fields));
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((span.metadata().name().to_owned(), fields));
+                tracing::span::Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+                let mut spans = self.spans.lock().unwrap();
+                values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1].1));
+            }
+
+            fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+            fn event(&self, event: &tracing::Event<'_>) {
+                let mut fields = HashMap::new();
+                event.record(&mut Fields(&mut fields));
+                let span = self.entered.lock().unwrap().last().copied();
+                let message = fields.remove("message").unwrap_or_default();
+                self.events.lock().unwrap().push((message, span));
+            }
+
+            fn enter(&self, span: &tracing::span::Id) {
+                let index = span.into_u64() as usize - 1;
+                self.entered.lock().unwrap().push(index);
+            }
+
+            fn exit(&self, _: &tracing::span::Id) {
+                self.entered.lock().unwrap().pop();
+            }
+        }
+
+        #[tokio::test]
+        async fn a_command_is_traced_in_a_span_with_its_id_and_type() {
+            let (mut client, mut server) = MockServer::authenticated().await;
+
+            let recorder = Recorder::default();
+            let _default = tracing::subscriber::set_default(recorder.clone());
+            let (pong, ping) = futures::join!(client.ping(), async {
+                let ping = server.receive().await;
+                server
+                    .send_json(json!({"id": ping["id"], "type": "pong"}))
+                    .await;
+                ping
+            });
+            pong.unwrap();
+
+            let spans = recorder.spans.lock().unwrap();
+            let command = spans
+                .iter()
+                .position(|(name, _)| name == "hass_command")
+                .expect("the command span");
+            let fields = &spans[command].1;
+            assert_eq!(fields["id"], ping["id"].to_string());
+            assert_eq!(fields["msg_type"], "ping");
+
+            let events = recorder.events.lock().unwrap();
+            for message in ["command sent", "response received"] {
+                assert!(
+                    events.contains(&(message.to_owned(), Some(command))),
+                    "missing the {:?} event in the command span, got {:?}",
+                    message,
+                    events
+                );
+            }
         }
-        _ => return Err(HassError::UnknownPayloadReceived),
     }
-}
 
-// message sequence required by the Websocket server
-fn get_last_seq(last_sequence: &Arc<AtomicU64>) -> Option<u64> {
-    // Increase the last sequence and use the previous value in the request
-    match last_sequence.fetch_add(1, Ordering::Relaxed) {
-        0 => None,
-        v => Some(v),
+    #[tokio::test]
+    async fn entity_registry_entries_are_updated_and_removed() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let changes = EntityRegistryUpdate {
+            area_id: Some(None),
+            new_entity_id: Some("light.pantry".to_owned()),
+            ..Default::default()
+        };
+        let (updated, update) = futures::join!(
+            client.update_entity("light.kitchen", changes),
+            server.reply(json!({"entity_entry": registry_entry("light.pantry", None)}))
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/entity_registry/update",
+                "entity_id": "light.kitchen",
+                "area_id": null,
+                "new_entity_id": "light.pantry"
+            })
+        );
+        assert_eq!(updated.unwrap().entity_id, "light.pantry");
+
+        let (removed, remove) = futures::join!(
+            client.remove_entity("light.pantry"),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            remove,
+            json!({
+                "id": remove["id"],
+                "type": "config/entity_registry/remove",
+                "entity_id": "light.pantry"
+            })
+        );
+        removed.unwrap();
+
+        let (removed, ()) = futures::join!(client.remove_entity("light.pantry"), async {
+            let remove = server.receive().await;
+            server
+                .send_json(failure(&remove, "not_found", "Entity not found"))
+                .await;
+        });
+        match removed {
+            Err(HassError::ReponseError(result)) => {
+                assert_eq!(result.error.unwrap().code, "not_found")
+            }
+            other => panic!("expected the error of the server, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn areas_are_created_updated_and_deleted() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (created, create) = futures::join!(
+            client.create_area("Kitchen", vec!["Cooking".to_owned()]),
+            server.reply(area("kitchen", "Kitchen"))
+        );
+        assert_eq!(
+            create,
+            json!({
+                "id": create["id"],
+                "type": "config/area_registry/create",
+                "name": "Kitchen",
+                "aliases": ["Cooking"]
+            })
+        );
+        let created = created.unwrap();
+        assert_eq!(
+            (created.id.as_str(), created.name.as_str()),
+            ("kitchen", "Kitchen")
+        );
+
+        let changes = AreaRegistryUpdate {
+            name: Some("Pantry".to_owned()),
+            picture: Some(None),
+            ..Default::default()
+        };
+        let (updated, update) = futures::join!(
+            client.update_area("kitchen", changes),
+            server.reply(area("kitchen", "Pantry"))
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/area_registry/update",
+                "area_id": "kitchen",
+                "name": "Pantry",
+                "picture": null
+            })
+        );
+        assert_eq!(updated.unwrap().name, "Pantry");
+
+        let (deleted, delete) =
+            futures::join!(client.delete_area("kitchen"), server.reply(Value::Null));
+        assert_eq!(
+            delete,
+            json!({"id": delete["id"], "type": "config/area_registry/delete", "area_id": "kitchen"})
+        );
+        deleted.unwrap();
+    }
+
+    #[tokio::test]
+    async fn devices_and_entities_are_assigned_to_areas() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (assigned, update) = futures::join!(
+            client.assign_device_area("hue-bridge", Some("kitchen")),
+            server.reply(device("hue-bridge", Some("kitchen")))
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/device_registry/update",
+                "device_id": "hue-bridge",
+                "area_id": "kitchen"
+            })
+        );
+        assert_eq!(assigned.unwrap().area_id.as_deref(), Some("kitchen"));
+
+        // every entity is updated in turn, and a failure doesn't stop the others
+        let (assigned, updates) = futures::join!(
+            client.assign_entities_area(&["light.kitchen", "light.gone", "light.hall"], None),
+            async {
+                let mut updates = Vec::new();
+                for entity_id in ["light.kitchen", "light.gone", "light.hall"] {
+                    let update = server.receive().await;
+                    let reply = match entity_id {
+                        "light.gone" => failure(&update, "not_found", "Entity not found"),
+                        _ => success(
+                            &update,
+                            json!({"entity_entry": registry_entry(entity_id, None)}),
+                        ),
+                    };
+                    server.send_json(reply).await;
+                    updates.push(update);
+                }
+                updates
+            }
+        );
+        for (update, entity_id) in updates
+            .iter()
+            .zip(["light.kitchen", "light.gone", "light.hall"])
+        {
+            assert_eq!(
+                *update,
+                json!({
+                    "id": update["id"],
+                    "type": "config/entity_registry/update",
+                    "entity_id": entity_id,
+                    "area_id": null
+                })
+            );
+        }
+        assert_eq!(assigned.len(), 3);
+        assert_eq!(assigned[0].as_ref().unwrap().entity_id, "light.kitchen");
+        assert!(matches!(assigned[1], Err(HassError::ReponseError(_))));
+        assert_eq!(assigned[2].as_ref().unwrap().entity_id, "light.hall");
+    }
+
+    #[tokio::test]
+    async fn a_close_frame_of_the_server_is_reported_with_its_code_and_reason() {
+        use async_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+        use async_tungstenite::tungstenite::protocol::CloseFrame;
+
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let (pinged, ()) = futures::join!(client.ping(), async {
+            let ping = server.receive().await;
+            assert_eq!(ping["type"], "ping");
+            let frame = CloseFrame {
+                code: CloseCode::Policy,
+                reason: "Too many pending messages".into(),
+            };
+            server
+                .send_frame(TungsteniteMessage::Close(Some(frame)))
+                .await;
+        });
+        match pinged {
+            Err(HassError::ConnectionClosedWith { code, reason }) => {
+                assert_eq!(code, 1008);
+                assert_eq!(reason, "Too many pending messages");
+            }
+            other => panic!("expected the close code, got {:?}", other),
+        }
+
+        // a close frame without a code is a plain closed connection
+        let (pinged, ()) = futures::join!(client.ping(), async {
+            server.receive().await;
+            server.send_frame(TungsteniteMessage::Close(None)).await;
+        });
+        assert!(matches!(pinged, Err(HassError::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn registry_entities_are_grouped_by_device() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (grouped, (devices, entities)) = futures::join!(client.entities_by_device(), async {
+            let devices = server
+                .reply(json!([
+                    device("hue-bridge", None),
+                    device("doorbell", None)
+                ]))
+                .await;
+            let entities = server
+                .reply(json!([
+                    registry_entry("light.kitchen", Some("hue-bridge")),
+                    registry_entry("sun.sun", None),
+                    registry_entry("light.hall", Some("hue-bridge")),
+                ]))
+                .await;
+            (devices, entities)
+        });
+        assert_eq!(
+            devices,
+            json!({"id": devices["id"], "type": "config/device_registry/list"})
+        );
+        assert_eq!(
+            entities,
+            json!({"id": entities["id"], "type": "config/entity_registry/list"})
+        );
+
+        let grouped = grouped.unwrap();
+        let entity_ids = |device_id: Option<&str>| -> Vec<&str> {
+            grouped[&device_id.map(str::to_owned)]
+                .iter()
+                .map(|entity| entity.entity_id.as_str())
+                .collect()
+        };
+        assert_eq!(grouped.len(), 3);
+        assert_eq!(
+            entity_ids(Some("hue-bridge")),
+            ["light.kitchen", "light.hall"]
+        );
+        // a device without entities is listed too, the entities without a device are under None
+        assert!(entity_ids(Some("doorbell")).is_empty());
+        assert_eq!(entity_ids(None), ["sun.sun"]);
+    }
+
+    #[tokio::test]
+    async fn a_service_call_returns_its_context() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let data = json!({"entity_id": "light.kitchen"});
+
+        let (context, call) = futures::join!(
+            client.call_service_with_context("light".to_owned(), "turn_on".to_owned(), Some(data)),
+            server.reply(json!({
+                "context": {"id": "01HPRMZAWN", "parent_id": null, "user_id": "8d5fe1c3"},
+                "response": null
+            }))
+        );
+        assert_eq!(
+            call,
+            json!({
+                "id": call["id"],
+                "type": "call_service",
+                "domain": "light",
+                "service": "turn_on",
+                "service_data": {"entity_id": "light.kitchen"}
+            })
+        );
+        let context = context.unwrap();
+        assert_eq!(context.id, "01HPRMZAWN");
+        assert_eq!(context.parent_id, None);
+        assert_eq!(context.user_id.as_deref(), Some("8d5fe1c3"));
+
+        // the older versions of Home Assistant answer without the context
+        let (context, _) = futures::join!(
+            client.call_service_with_context("light".to_owned(), "turn_off".to_owned(), None),
+            server.reply(Value::Null)
+        );
+        assert!(matches!(context, Err(HassError::Generic(_))));
+    }
+
+    #[tokio::test]
+    async fn a_subscription_to_all_the_events_omits_the_event_type() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let (subscribed, subscribe) =
+            futures::join!(client.subscribe_all_events(), server.reply(Value::Null));
+        assert_eq!(
+            subscribe,
+            json!({"id": subscribe["id"], "type": "subscribe_events"})
+        );
+        let id = subscribed.unwrap().id;
+        assert_eq!(client.subscriptions[&id], "*");
+
+        let mut events = Box::pin(client.event_stream().unwrap());
+        let mut call = state_changed("light.kitchen", None);
+        call["event_type"] = json!("call_service");
+        server.send_event(id, call).await;
+        server
+            .send_event(id, state_changed("light.kitchen", Some("on")))
+            .await;
+        for event_type in ["call_service", "state_changed"] {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                (event.id, event.event.event_type.as_str()),
+                (id, event_type)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_selectors_of_the_service_fields_are_parsed() {
+        use crate::types::{SelectOption, Selector};
+
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let fields = json!({
+            "brightness_pct": {"selector": {"number": {"min": 0, "max": 100, "unit_of_measurement": "%"}}},
+            "entity": {"selector": {"entity": {"domain": "light", "integration": "hue"}}},
+            "effect": {"selector": {"select": {"options": ["colorloop", {"label": "Random", "value": "random"}]}}},
+            "flash": {"selector": {"boolean": {}}},
+            "profile": {"selector": {"text": null}},
+            "rgb_color": {"selector": {"color_rgb": {}}}
+        });
+
+        let (services, get_services) = futures::join!(
+            client.get_services(),
+            server.reply(json!({"light": {"turn_on": {"name": "Turn on", "fields": fields}}}))
+        );
+        assert_eq!(
+            get_services,
+            json!({"id": get_services["id"], "type": "get_services"})
+        );
+        let services = services.unwrap();
+        let selector = |field: &str| {
+            services.0["light"]["turn_on"].fields[field]
+                .selector
+                .clone()
+                .unwrap()
+        };
+        assert_eq!(
+            selector("brightness_pct"),
+            Selector::Number {
+                min: Some(0.0),
+                max: Some(100.0),
+                step: None,
+                unit_of_measurement: Some("%".to_owned())
+            }
+        );
+        assert_eq!(
+            selector("entity"),
+            Selector::Entity {
+                domain: vec!["light".to_owned()],
+                integration: Some("hue".to_owned())
+            }
+        );
+        let option = |label: &str, value: &str| SelectOption {
+            label: label.to_owned(),
+            value: value.to_owned(),
+        };
+        assert_eq!(
+            selector("effect"),
+            Selector::Select {
+                options: vec![option("colorloop", "colorloop"), option("Random", "random")]
+            }
+        );
+        assert_eq!(selector("flash"), Selector::Boolean);
+        assert_eq!(selector("profile"), Selector::Text);
+        assert_eq!(
+            selector("rgb_color"),
+            Selector::Other(json!({"color_rgb": {}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn an_entity_is_fetched_and_enabled_or_disabled() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (entity, get) = futures::join!(
+            client.get_entity("light.kitchen"),
+            server.reply(registry_entry("light.kitchen", Some("hue-bridge")))
+        );
+        assert_eq!(
+            get,
+            json!({"id": get["id"], "type": "config/entity_registry/get", "entity_id": "light.kitchen"})
+        );
+        let entity = entity.unwrap().unwrap();
+        assert_eq!(entity.entity_id, "light.kitchen");
+        assert_eq!(entity.device_id.as_deref(), Some("hue-bridge"));
+
+        let (missing, ()) = futures::join!(client.get_entity("light.gone"), async {
+            let get = server.receive().await;
+            server
+                .send_json(failure(&get, "not_found", "Entity not found"))
+                .await;
+        });
+        assert_eq!(missing.unwrap(), None);
+
+        for (enabled, disabled_by) in [(false, json!("user")), (true, Value::Null)] {
+            let mut entry = registry_entry("light.kitchen", None);
+            entry["disabled_by"] = disabled_by.clone();
+            let (updated, update) = futures::join!(
+                client.set_entity_enabled("light.kitchen", enabled),
+                server.reply(json!({ "entity_entry": entry }))
+            );
+            assert_eq!(
+                update,
+                json!({
+                    "id": update["id"],
+                    "type": "config/entity_registry/update",
+                    "entity_id": "light.kitchen",
+                    "disabled_by": disabled_by
+                })
+            );
+            assert_eq!(updated.unwrap().disabled_by.is_none(), enabled);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_raw_json_command_is_sent_as_given() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let command = json!({
+            "type": "recorder/statistics_during_period",
+            "start_time": "2024-01-01T00:00:00Z",
+            "statistic_ids": ["sensor.energy"],
+            "period": "hour"
+        });
+
+        let (response, sent) = futures::join!(
+            client.send_raw_json(command.clone()),
+            server.reply(json!({"sensor.energy": []}))
+        );
+        // only the id is added
+        let mut expected = command;
+        expected["id"] = sent["id"].clone();
+        assert_eq!(sent, expected);
+        match response.unwrap() {
+            Response::Result(result) => {
+                assert_eq!(result.id, sent["id"]);
+                assert_eq!(result.result, Some(json!({"sensor.energy": []})));
+            }
+            other => panic!("expected a result, got {:?}", other),
+        }
+
+        // a provided id is kept, and an error of the server is returned as received
+        let id = client.next_id();
+        let (response, ()) = futures::join!(
+            client.send_raw_json(json!({"id": id, "type": "unknown/command"})),
+            async {
+                let sent = server.receive().await;
+                assert_eq!(sent, json!({"id": id, "type": "unknown/command"}));
+                server
+                    .send_json(failure(&sent, "unknown_command", "Unknown command."))
+                    .await;
+            }
+        );
+        match response.unwrap() {
+            Response::Result(result) => {
+                assert!(!result.success);
+                assert_eq!(result.error.unwrap().code, "unknown_command");
+            }
+            other => panic!("expected a result, got {:?}", other),
+        }
+
+        let refused = client.send_raw_json(json!(["get_states"])).await;
+        assert!(matches!(refused, Err(HassError::Generic(_))));
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_auth_is_reported_with_or_without_its_message() {
+        let messages = [
+            (
+                json!({"type": "auth_invalid", "message": "Invalid password"}),
+                "Invalid password",
+            ),
+            // some proxies strip the message
+            (
+                json!({"type": "auth_invalid"}),
+                "Invalid access token or password",
+            ),
+        ];
+        for (auth_invalid, message) in messages {
+            let (mut client, mut server) = MockServer::client();
+            let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("token"), async {
+                server
+                    .send_json(json!({"type": "auth_required", "ha_version": "2024.1.0"}))
+                    .await;
+                let auth = server.receive().await;
+                assert_eq!(auth, json!({"type": "auth", "access_token": "token"}));
+                server.send_json(auth_invalid).await;
+            });
+            match auth {
+                Err(HassError::AuthenticationFailed(error)) => assert_eq!(error, message),
+                other => panic!("expected the authentication to fail, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn the_logbook_entries_are_streamed() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+        let entity_ids = Some(vec!["light.kitchen".to_owned()]);
+
+        let (subscribed, subscribe) = futures::join!(
+            client.subscribe_logbook("2024-01-01T00:00:00Z", entity_ids),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            subscribe,
+            json!({
+                "id": subscribe["id"],
+                "type": "logbook/event_stream",
+                "start_time": "2024-01-01T00:00:00Z",
+                "entity_ids": ["light.kitchen"]
+            })
+        );
+        let (id, entries) = subscribed.unwrap();
+        let mut entries = Box::pin(entries);
+
+        let entry = |when: f64, state: &str| json!({"when": when, "entity_id": "light.kitchen", "state": state, "name": "Kitchen"});
+        // the history comes first, in a single event, then the live entries
+        server
+            .send_event(
+                id,
+                json!({"events": [entry(1.0, "on"), entry(2.0, "off")], "partial": true}),
+            )
+            .await;
+        // the markers without entries are skipped
+        server
+            .send_event(id, json!({"start_time": 2.0, "end_time": 2.0}))
+            .await;
+        server
+            .send_event(id, json!({"events": [entry(3.0, "on")]}))
+            .await;
+
+        for (when, state) in [(1.0, "on"), (2.0, "off"), (3.0, "on")] {
+            let entry = tokio::time::timeout(Duration::from_secs(5), entries.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(entry.when, when);
+            assert_eq!(entry.entity_id.as_deref(), Some("light.kitchen"));
+            assert_eq!(entry.state.as_deref(), Some(state));
+        }
+    }
+
+    #[tokio::test]
+    async fn an_auth_required_mid_session_asks_to_authenticate_again() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            assert_eq!(server.receive().await["type"], "get_states");
+            server
+                .send_json(json!({"type": "auth_required", "ha_version": "2024.1.0"}))
+                .await;
+        });
+        assert!(matches!(states, Err(HassError::AuthenticationRequired)));
+
+        // the commands are refused until the client authenticates again
+        let refused = client.get_states().await;
+        assert!(matches!(refused, Err(HassError::Generic(_))));
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+
+        // the auth_required was already received, the auth is sent right away
+        let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("new-token"), async {
+            let auth = server.receive().await;
+            assert_eq!(auth, json!({"type": "auth", "access_token": "new-token"}));
+            server
+                .send_json(json!({"type": "auth_ok", "ha_version": "2024.1.0"}))
+                .await;
+        });
+        auth.unwrap();
+
+        let (states, get_states) = futures::join!(
+            client.get_states(),
+            server.reply(json!([entity_state("light.kitchen", "on")]))
+        );
+        assert_eq!(get_states["type"], "get_states");
+        assert_eq!(states.unwrap()[0].state, "on");
+    }
+
+    #[tokio::test]
+    async fn the_fetched_states_are_read_by_domain() {
+        use crate::types::{BinarySensorState, CoverState, LockState};
+
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let with_attributes = |entity_id: &str, state: &str, attributes: Value| {
+            let mut state = entity_state(entity_id, state);
+            state["attributes"] = attributes;
+            state
+        };
+
+        let (states, get_states) = futures::join!(
+            client.get_states(),
+            server.reply(json!([
+                with_attributes("binary_sensor.door", "on", json!({"device_class": "door"})),
+                with_attributes(
+                    "cover.garage",
+                    "open",
+                    json!({"current_position": 40, "current_tilt_position": 150})
+                ),
+                entity_state("lock.front", "jammed"),
+                entity_state("light.kitchen", "on"),
+            ]))
+        );
+        assert_eq!(
+            get_states,
+            json!({"id": get_states["id"], "type": "get_states"})
+        );
+        let states = states.unwrap();
+
+        assert_eq!(
+            states[0].as_binary_sensor(),
+            Some(BinarySensorState {
+                is_on: true,
+                device_class: Some("door".to_owned())
+            })
+        );
+        assert_eq!(
+            states[1].as_cover(),
+            Some(CoverState {
+                position: Some(40),
+                is_open: true,
+                is_closed: false,
+                // the positions are capped to 100
+                tilt: Some(100)
+            })
+        );
+        assert_eq!(
+            states[2].as_lock(),
+            Some(LockState {
+                is_locked: false,
+                is_jammed: true
+            })
+        );
+        // each reader only reads its own domain
+        assert_eq!(states[3].as_binary_sensor(), None);
+        assert_eq!(states[3].as_cover(), None);
+        assert_eq!(states[0].as_lock(), None);
+    }
+
+    #[tokio::test]
+    async fn an_entity_is_available_when_it_has_a_known_state() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let states = json!([
+            entity_state("light.kitchen", "off"),
+            entity_state("light.hall", "unavailable"),
+            entity_state("sensor.outside", "unknown"),
+        ]);
+
+        for (entity_id, available) in [
+            ("light.kitchen", true),
+            ("light.hall", false),
+            ("sensor.outside", false),
+            ("light.gone", false),
+        ] {
+            let (is_available, get_states) = futures::join!(
+                client.entity_available(entity_id),
+                server.reply(states.clone())
+            );
+            assert_eq!(
+                get_states,
+                json!({"id": get_states["id"], "type": "get_states"})
+            );
+            assert_eq!(is_available.unwrap(), available, "{}", entity_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_add_on_connects_with_the_supervisor_token() {
+        let (listener, url) = listen().await;
+
+        std::env::remove_var("SUPERVISOR_TOKEN");
+        let missing = HassClient::connect_supervisor_at(&url).await;
+        assert!(matches!(missing, Err(HassError::SupervisorTokenMissing)));
+
+        std::env::set_var("SUPERVISOR_TOKEN", "supervisor-token");
+        let (client, ()) = futures::join!(HassClient::connect_supervisor_at(&url), async {
+            let mut server = MockServer::accept(&listener).await;
+            server
+                .send_json(json!({"type": "auth_required", "ha_version": "2024.1.0"}))
+                .await;
+            let auth = server.receive().await;
+            assert_eq!(
+                auth,
+                json!({"type": "auth", "access_token": "supervisor-token"})
+            );
+            server
+                .send_json(json!({"type": "auth_ok", "ha_version": "2024.1.0"}))
+                .await;
+        });
+        std::env::remove_var("SUPERVISOR_TOKEN");
+        assert!(client.unwrap().authenticated);
+    }
+
+    #[tokio::test]
+    async fn the_registry_options_of_an_entity_are_read_for_its_domain() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let mut temperature = registry_entry("sensor.outside", None);
+        temperature["options"] = json!({
+            "sensor": {"suggested_display_precision": 1, "unit_of_measurement": "°F"},
+            "conversation": {"should_expose": true}
+        });
+        let mut light = registry_entry("light.kitchen", None);
+        light["options"] = json!({"sensor": {"suggested_display_precision": 2}});
+
+        let (entities, list) = futures::join!(
+            client.get_entity_registry(),
+            server.reply(json!([temperature, light]))
+        );
+        assert_eq!(
+            list,
+            json!({"id": list["id"], "type": "config/entity_registry/list"})
+        );
+        let entities = entities.unwrap();
+
+        let temperature = &entities[0];
+        assert_eq!(temperature.suggested_display_precision(), Some(1));
+        assert_eq!(temperature.unit_of_measurement_override(), Some("°F"));
+        assert!(!temperature
+            .domain_options()
+            .unwrap()
+            .contains_key("should_expose"));
+        // the options of the other domains are not the light's ones
+        let light = &entities[1];
+        assert_eq!(light.domain_options(), None);
+        assert_eq!(light.suggested_display_precision(), None);
+        assert_eq!(light.unit_of_measurement_override(), None);
+    }
+
+    #[tokio::test]
+    async fn a_successful_validation_reports_the_invalid_sections() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let trigger = json!({"platform": "state", "entity_id": "light.kitchen"});
+        let action = json!({"service": "light.blink"});
+
+        let (validation, validate) = futures::join!(
+            client.validate_config(Some(trigger.clone()), None, Some(action.clone())),
+            server.reply(json!({
+                "trigger": {"valid": true, "error": null},
+                "action": {"valid": false, "error": "Service light.blink not found"}
+            }))
+        );
+        // the sections which are not set are not sent
+        assert_eq!(
+            validate,
+            json!({"id": validate["id"], "type": "validate_config", "trigger": trigger, "action": action})
+        );
+        let validation = validation.unwrap();
+        assert!(!validation.is_valid());
+        assert!(validation.trigger.unwrap().valid);
+        assert!(validation.condition.is_none());
+        assert_eq!(
+            validation.action.unwrap().error.as_deref(),
+            Some("Service light.blink not found")
+        );
+
+        let sequence = json!([{"stop": "done", "response_variable": "answer"}]);
+        let (result, execute) = futures::join!(
+            client.execute_script(sequence.clone(), Some(json!({"answer": 42}))),
+            server.reply(json!({"context": {"id": "01HPRMZAWN"}, "response": {"answer": 42}}))
+        );
+        assert_eq!(
+            execute,
+            json!({
+                "id": execute["id"],
+                "type": "execute_script",
+                "sequence": sequence,
+                "variables": {"answer": 42}
+            })
+        );
+        let result = result.unwrap();
+        assert_eq!(result.context.id, "01HPRMZAWN");
+        assert_eq!(result.response, Some(json!({"answer": 42})));
+    }
+
+    #[tokio::test]
+    async fn the_renders_of_a_template_carry_their_errors() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let (subscribed, render) = futures::join!(
+            client.render_template("{{ states('sensor.outside') | float * 2 }}", None),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            render,
+            json!({
+                "id": render["id"],
+                "type": "render_template",
+                "template": "{{ states('sensor.outside') | float * 2 }}",
+                "report_errors": true
+            })
+        );
+        let (id, renders) = subscribed.unwrap();
+        let mut renders = Box::pin(renders);
+
+        server
+            .send_event(id, json!({"error": "ValueError: could not convert string to float: 'unknown'", "level": "ERROR"}))
+            .await;
+        server
+            .send_event(
+                id,
+                json!({"result": 43.0, "listeners": {"all": false, "domains": [], "entities": ["sensor.outside"], "time": false}}),
+            )
+            .await;
+
+        let failed = tokio::time::timeout(Duration::from_secs(5), renders.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(failed.result, None);
+        assert_eq!(failed.level.as_deref(), Some("ERROR"));
+        assert!(failed.error.unwrap().contains("could not convert"));
+        let rendered = tokio::time::timeout(Duration::from_secs(5), renders.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rendered.result, Some(json!(43.0)));
+        assert_eq!(rendered.error, None);
+        assert!(!rendered.listeners.unwrap().all);
+    }
+
+    #[tokio::test]
+    async fn the_panels_are_typed_without_their_optional_flags() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (panels, get_panels) = futures::join!(
+            client.get_panels(),
+            server.reply(json!({
+                "lovelace": {
+                    "component_name": "lovelace", "icon": null, "title": null,
+                    "config": {"mode": "storage"}, "url_path": "lovelace", "require_admin": false
+                },
+                "hacs": {
+                    "component_name": "custom", "icon": "hacs:hacs", "title": "HACS", "url_path": "hacs",
+                    "config": {"_panel_custom": {"name": "hacs-frontend", "js_url": "/hacsfiles/entrypoint.js"}}
+                },
+                "config": {
+                    "component_name": "config", "icon": "hass:cog", "title": "config", "config": null,
+                    "url_path": "config", "require_admin": true, "config_panel_domain": "zha"
+                }
+            }))
+        );
+        assert_eq!(
+            get_panels,
+            json!({"id": get_panels["id"], "type": "get_panels"})
+        );
+        let panels = panels.unwrap();
+
+        let lovelace = &panels["lovelace"];
+        assert_eq!(
+            lovelace.config.as_ref().unwrap().mode.as_deref(),
+            Some("storage")
+        );
+        assert!(!lovelace.require_admin);
+
+        // the flags missing from the custom panel take their defaults
+        let hacs = &panels["hacs"];
+        assert!(!hacs.require_admin);
+        assert_eq!(hacs.config_panel_domain, None);
+        let custom = hacs.config.as_ref().unwrap().custom_panel.as_ref().unwrap();
+        assert_eq!(custom.name, "hacs-frontend");
+        assert_eq!(custom.js_url.as_deref(), Some("/hacsfiles/entrypoint.js"));
+        assert!(!custom.embed_iframe && !custom.trust_external);
+
+        let config = &panels["config"];
+        assert!(config.require_admin);
+        assert_eq!(config.config_panel_domain.as_deref(), Some("zha"));
+    }
+
+    #[tokio::test]
+    async fn the_domains_of_the_current_entities_are_listed_once_and_sorted() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (domains, get_states) = futures::join!(
+            client.list_domains(),
+            server.reply(json!([
+                entity_state("sun.sun", "above_horizon"),
+                entity_state("light.kitchen", "on"),
+                entity_state("binary_sensor.door", "off"),
+                entity_state("light.hall", "off"),
+            ]))
+        );
+        assert_eq!(
+            get_states,
+            json!({"id": get_states["id"], "type": "get_states"})
+        );
+        assert_eq!(domains.unwrap(), ["binary_sensor", "light", "sun"]);
+    }
+
+    #[tokio::test]
+    async fn a_call_is_validated_against_the_fetched_services() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (services, get_services) = futures::join!(
+            client.get_services(),
+            server.reply(json!({"notify": {"send_message": {
+                "name": "Send a message",
+                "fields": {
+                    "message": {"required": true, "selector": {"text": null}},
+                    "title": {"required": false},
+                    "advanced_fields": {
+                        "collapsed": true,
+                        "fields": {"target": {"required": true}}
+                    }
+                }
+            }}}))
+        );
+        assert_eq!(
+            get_services,
+            json!({"id": get_services["id"], "type": "get_services"})
+        );
+        let services = services.unwrap();
+
+        let complete = json!({"message": "Hello", "target": "phone"});
+        assert_eq!(
+            services.validate_call("notify", "send_message", &complete),
+            Ok(())
+        );
+        // the required fields of the sections are checked too
+        assert_eq!(
+            services.validate_call("notify", "send_message", &json!({"title": "Hi"})),
+            Err(vec![
+                "missing required field message".to_owned(),
+                "missing required field target".to_owned()
+            ])
+        );
+        assert_eq!(
+            services.validate_call("notify", "mobile_app", &complete),
+            Err(vec!["unknown service notify.mobile_app".to_owned()])
+        );
+    }
+
+    #[tokio::test]
+    async fn the_config_tells_the_run_state_and_the_recovery_mode() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let mut recovering = config("RUNNING");
+        recovering["recovery_mode"] = json!(true);
+
+        let (fetched, get_config) = futures::join!(client.get_config(), server.reply(recovering));
+        assert_eq!(
+            get_config,
+            json!({"id": get_config["id"], "type": "get_config"})
+        );
+        let fetched = fetched.unwrap();
+        assert_eq!(fetched.state.as_deref(), Some("RUNNING"));
+        assert_eq!(fetched.recovery_mode, Some(true));
+        assert!(!fetched.safe_mode);
+
+        // the older versions have safe_mode, and neither the state nor recovery_mode
+        let mut older = config("RUNNING");
+        let older_fields = older.as_object_mut().unwrap();
+        older_fields.remove("state");
+        older_fields.insert("safe_mode".to_owned(), json!(true));
+        let (fetched, _) = futures::join!(client.get_config(), server.reply(older));
+        let fetched = fetched.unwrap();
+        assert_eq!(fetched.state, None);
+        assert_eq!(fetched.recovery_mode, None);
+        assert!(fetched.safe_mode);
+    }
+
+    #[tokio::test]
+    async fn the_registries_are_snapshot_and_round_trip_through_json() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (snapshot, lists) = futures::join!(client.snapshot_registries(), async {
+            let replies = [
+                json!([area("kitchen", "Kitchen")]),
+                json!([{"floor_id": "ground", "name": "Ground floor", "icon": null, "level": 0}]),
+                json!([device("hue-bridge", Some("kitchen"))]),
+                json!([registry_entry("light.kitchen", Some("hue-bridge"))]),
+                json!([{"label_id": "lights", "name": "Lights", "color": null, "description": null, "icon": null}]),
+            ];
+            let mut lists = Vec::new();
+            for reply in replies {
+                lists.push(server.reply(reply).await["type"].clone());
+            }
+            lists
+        });
+        assert_eq!(
+            lists,
+            [
+                "config/area_registry/list",
+                "config/floor_registry/list",
+                "config/device_registry/list",
+                "config/entity_registry/list",
+                "config/label_registry/list"
+            ]
+        );
+
+        let mut snapshot = snapshot.unwrap();
+        assert!(snapshot.taken_at > 0.0);
+        assert_eq!(snapshot.areas[0].id, "kitchen");
+        assert_eq!(snapshot.floors[0].level, Some(0));
+        assert_eq!(snapshot.devices[0].area_id.as_deref(), Some("kitchen"));
+        assert_eq!(
+            snapshot.entities[0].device_id.as_deref(),
+            Some("hue-bridge")
+        );
+        assert_eq!(snapshot.labels[0].name, "Lights");
+
+        // a persisted snapshot is read back the same, with a time which serde_json reads back exactly
+        snapshot.taken_at = 1_704_067_200.5;
+        let persisted = serde_json::to_string(&snapshot).unwrap();
+        let restored: RegistrySnapshot = serde_json::from_str(&persisted).unwrap();
+        assert_eq!(restored, snapshot);
+    }
+
+    #[tokio::test]
+    async fn a_unique_subscription_is_only_sent_once_per_event_type() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (first, subscribe) = futures::join!(
+            client.subscribe_event_unique("state_changed"),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            subscribe,
+            json!({"id": subscribe["id"], "type": "subscribe_events", "event_type": "state_changed"})
+        );
+        let first = first.unwrap();
+        assert_eq!(first, subscribe["id"]);
+
+        // the existing subscription is returned, without asking the server
+        let again = client
+            .subscribe_event_unique("state_changed")
+            .await
+            .unwrap();
+        assert_eq!(again, first);
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+
+        let (other, subscribe) = futures::join!(
+            client.subscribe_event_unique("call_service"),
+            server.reply(Value::Null)
+        );
+        assert_eq!(subscribe["event_type"], "call_service");
+        assert_ne!(other.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn a_reply_which_is_not_json_is_reported_with_its_beginning() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let page = format!(
+            "<html><head><title>502 Bad Gateway</title></head>{}</html>",
+            "<p>nginx</p>".repeat(100)
+        );
+
+        let (states, ()) = futures::join!(client.get_states(), async {
+            assert_eq!(server.receive().await["type"], "get_states");
+            server.send(page.as_str()).await;
+        });
+        match states {
+            Err(HassError::Generic(message)) => {
+                assert!(message.contains("not JSON"), "{}", message);
+                assert!(
+                    message.contains("<title>502 Bad Gateway</title>"),
+                    "{}",
+                    message
+                );
+                // only the beginning is kept
+                assert!(message.len() < page.len(), "{}", message);
+            }
+            other => panic!("expected the content of the page, got {:?}", other),
+        }
+
+        // a message cut in the middle is still a serde error
+        let (states, ()) = futures::join!(client.get_states(), async {
+            let get_states = server.receive().await;
+            server
+                .send(format!(
+                    r#"{{"id": {}, "type": "result", "succ"#,
+                    get_states["id"]
+                ))
+                .await;
+        });
+        assert!(matches!(states, Err(HassError::UnableToDeserialize(_))));
+    }
+
+    #[tokio::test]
+    async fn only_the_changed_core_config_fields_are_sent() {
+        use crate::types::CoreConfigUpdate;
+
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let changes = CoreConfigUpdate {
+            latitude: Some(52.5),
+            longitude: Some(4.25),
+            location_name: Some("Boat".to_owned()),
+            ..Default::default()
+        };
+
+        let (updated, update) = futures::join!(
+            client.update_core_config(changes),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            update,
+            json!({
+                "id": update["id"],
+                "type": "config/core/update",
+                "latitude": 52.5,
+                "longitude": 4.25,
+                "location_name": "Boat"
+            })
+        );
+        updated.unwrap();
+
+        // the update is refused to the users who are not admins
+        let (updated, ()) = futures::join!(
+            client.update_core_config(CoreConfigUpdate {
+                time_zone: Some("Europe/Paris".to_owned()),
+                ..Default::default()
+            }),
+            async {
+                let update = server.receive().await;
+                assert_eq!(update["time_zone"], "Europe/Paris");
+                server
+                    .send_json(failure(&update, "unauthorized", "Unauthorized"))
+                    .await;
+            }
+        );
+        assert!(matches!(updated, Err(HassError::ReponseError(_))));
+    }
+
+    #[tokio::test]
+    async fn the_state_transitions_are_watched_with_a_state_trigger() {
+        let (mut client, mut server) = MockServer::connected(ClientConfig::default()).await;
+
+        let (watched, subscribe) = futures::join!(
+            client.on_state(
+                "binary_sensor.doorbell",
+                Some("on"),
+                Some("off"),
+                Some(Duration::from_millis(1500))
+            ),
+            server.reply(Value::Null)
+        );
+        assert_eq!(
+            subscribe,
+            json!({
+                "id": subscribe["id"],
+                "type": "subscribe_trigger",
+                "trigger": {
+                    "platform": "state",
+                    "entity_id": "binary_sensor.doorbell",
+                    "to": "on",
+                    "from": "off",
+                    "for": {"seconds": 1, "milliseconds": 500}
+                }
+            })
+        );
+        let (id, transitions) = watched.unwrap();
+        let mut transitions = Box::pin(transitions);
+
+        let trigger = json!({
+            "platform": "state",
+            "entity_id": "binary_sensor.doorbell",
+            "from_state": entity_state("binary_sensor.doorbell", "off"),
+            "to_state": entity_state("binary_sensor.doorbell", "on"),
+            "for": {"seconds": 1, "milliseconds": 500}
+        });
+        server
+            .send_event(
+                id,
+                json!({"variables": {"trigger": trigger}, "context": null}),
+            )
+            .await;
+
+        let transition = tokio::time::timeout(Duration::from_secs(5), transitions.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(transition.entity_id, "binary_sensor.doorbell");
+        assert_eq!(transition.old_state.unwrap().state, "off");
+        assert_eq!(transition.new_state.unwrap().state, "on");
+    }
+
+    #[tokio::test]
+    async fn a_rejected_command_is_told_by_the_id_of_its_result() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+
+        let (subscribed, subscribe) = futures::join!(
+            client.subscribe_event("state_changed"),
+            server.reply(Value::Null)
+        );
+        assert_eq!(subscribed.unwrap().id, subscribe["id"]);
+
+        let (called, call) = futures::join!(
+            client.call_service("light".to_owned(), "blink".to_owned(), None),
+            async {
+                let call = server.receive().await;
+                server
+                    .send_json(failure(
+                        &call,
+                        "not_found",
+                        "Service light.blink not found.",
+                    ))
+                    .await;
+                call
+            }
+        );
+        let error = called.unwrap_err();
+        assert_eq!(error.response_id(), call["id"].as_u64());
+        assert_ne!(error.response_id(), subscribe["id"].as_u64());
+
+        // the other errors are not the result of a command
+        assert_eq!(HassError::ConnectionClosed.response_id(), None);
+    }
+
+    #[tokio::test]
+    async fn the_extra_headers_are_sent_with_the_handshake() {
+        let (listener, url) = listen().await;
+        let headers = vec![
+            ("CF-Access-Client-Id".to_owned(), "client.access".to_owned()),
+            ("X-Forwarded-User".to_owned(), "ada".to_owned()),
+            ("X-Forwarded-User".to_owned(), "grace".to_owned()),
+        ];
+
+        let (client, (_server, received)) = futures::join!(
+            connect_with_headers(&url, headers),
+            MockServer::accept_with_headers(&listener)
+        );
+        client.unwrap();
+        assert_eq!(received["cf-access-client-id"], "client.access");
+        // a header given twice is sent twice
+        let users: Vec<_> = received.get_all("x-forwarded-user").iter().collect();
+        assert_eq!(users, ["ada", "grace"]);
+
+        // an invalid header fails before connecting
+        let invalid = vec![("Bad Header".to_owned(), "value".to_owned())];
+        assert!(connect_with_headers(&url, invalid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_registry_entry_and_the_state_of_an_entity_are_joined() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let states = json!([
+            entity_state("light.kitchen", "on"),
+            entity_state("sensor.yaml_only", "21.5"),
+        ]);
+
+        let (joined, (get, get_states)) =
+            futures::join!(client.entity_with_state("light.kitchen"), async {
+                let get = server
+                    .reply(registry_entry("light.kitchen", Some("hue-bridge")))
+                    .await;
+                (get, server.reply(states.clone()).await)
+            });
+        assert_eq!(
+            get,
+            json!({"id": get["id"], "type": "config/entity_registry/get", "entity_id": "light.kitchen"})
+        );
+        assert_eq!(
+            get_states,
+            json!({"id": get_states["id"], "type": "get_states"})
+        );
+        let (entity, state) = joined.unwrap();
+        assert_eq!(entity.unwrap().device_id.as_deref(), Some("hue-bridge"));
+        assert_eq!(state.unwrap().state, "on");
+
+        // an entity without a unique id has a state, but no registry entry
+        let (joined, ()) = futures::join!(client.entity_with_state("sensor.yaml_only"), async {
+            let get = server.receive().await;
+            server
+                .send_json(failure(&get, "not_found", "Entity not found"))
+                .await;
+            server.reply(states.clone()).await;
+        });
+        let (entity, state) = joined.unwrap();
+        assert!(entity.is_none());
+        assert_eq!(state.unwrap().state, "21.5");
+
+        // a disabled entity has a registry entry, but no state
+        let (joined, _) = futures::join!(client.entity_with_state("light.disabled"), async {
+            server.reply(registry_entry("light.disabled", None)).await;
+            server.reply(states.clone()).await
+        });
+        let (entity, state) = joined.unwrap();
+        assert_eq!(entity.unwrap().entity_id, "light.disabled");
+        assert!(state.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_single_service_is_kept_from_the_fetched_services() {
+        let (mut client, mut server) = MockServer::authenticated().await;
+        let services = json!({
+            "light": {
+                "turn_on": {"name": "Turn on", "description": "Turns on lights", "fields": {
+                    "brightness_pct": {"required": false, "selector": {"number": {"min": 0, "max": 100}}}
+                }},
+                "turn_off": {"name": "Turn off", "fields": {}}
+            },
+            "switch": {"toggle": {"name": "Toggle", "fields": {}}}
+        });
+
+        let (service, get_services) = futures::join!(
+            client.get_service("light", "turn_on"),
+            server.reply(services.clone())
+        );
+        assert_eq!(
+            get_services,
+            json!({"id": get_services["id"], "type": "get_services"})
+        );
+        let service = service.unwrap().unwrap();
+        assert_eq!(service.name.as_deref(), Some("Turn on"));
+        assert_eq!(service.description.as_deref(), Some("Turns on lights"));
+        assert!(service.fields.contains_key("brightness_pct"));
+
+        for (domain, name) in [("light", "toggle"), ("cover", "turn_on")] {
+            let (missing, _) = futures::join!(
+                client.get_service(domain, name),
+                server.reply(services.clone())
+            );
+            assert_eq!(missing.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_command_before_the_authentication_is_refused_without_being_sent() {
+        let (mut client, mut server) = MockServer::client();
+        assert!(!client.is_authenticated());
+
+        match client.get_states().await {
+            Err(HassError::Generic(message)) => assert_eq!(message, "not authenticated"),
+            other => panic!("expected the command to be refused, got {:?}", other),
+        }
+        assert!(server.is_silent(Duration::from_millis(100)).await);
+
+        let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("token"), server.auth());
+        auth.unwrap();
+        assert!(client.is_authenticated());
+        let (states, get_states) = futures::join!(
+            client.get_states(),
+            server.reply(json!([entity_state("light.kitchen", "on")]))
+        );
+        assert_eq!(get_states["type"], "get_states");
+        assert_eq!(states.unwrap().len(), 1);
     }
 }
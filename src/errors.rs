@@ -2,6 +2,7 @@
 
 use crate::types::WSResult;
 use async_tungstenite::tungstenite;
+use async_tungstenite::tungstenite::protocol::CloseFrame;
 
 #[cfg(feature = "use-async-std")]
 use async_std::channel::RecvError;
@@ -15,20 +16,45 @@ pub enum HassError {
     /// Returned when it is unable to authenticate
     AuthenticationFailed(String),
 
+    /// Returned when the server asks to authenticate again in the middle of the session,
+    /// usually after the connection was reestablished underneath the client,
+    /// the client can then authenticate again
+    AuthenticationRequired,
+
+    /// Returned when connecting through the Supervisor outside of an add-on, without the SUPERVISOR_TOKEN env var
+    SupervisorTokenMissing,
+
     /// Returned when serde was unable to deserialize the values
     UnableToDeserialize(serde_json::error::Error),
 
     /// Returned when connection has unexpected failed
     ConnectionClosed,
 
+    /// Returned when the server closed the connection with a close code and reason
+    ConnectionClosedWith { code: u16, reason: String },
+
+    /// Returned when the server sent a message larger than the `ClientConfig::max_message_size`
+    MessageTooLarge { size: usize, max_size: usize },
+
+    /// Returned when the operation did not complete in time
+    Timeout,
+
+    /// Returned when the connection was lost while waiting for the response of a command changing something,
+    /// the connection was reopened but the command is not sent again, as Home Assistant may have executed it
+    CommandInterrupted,
+
     /// Mpsc channel SendError<T> message
     SendError(String),
 
     #[cfg(feature = "use-async-std")]
     RecvError(RecvError),
 
-    /// Tungstenite error
-    TungsteniteError(tungstenite::error::Error),
+    /// Tungstenite error, boxed to keep the size of `HassResult` small
+    TungsteniteError(Box<tungstenite::error::Error>),
+
+    /// Returned when a request of the RestClient failed, including the error statuses
+    #[cfg(feature = "rest")]
+    RestError(reqwest::Error),
 
     ///Tokio Tungstenite error
     //TokioTungsteniteError(tokio_tungstenite::tungstenite::Error),
@@ -45,17 +71,111 @@ pub enum HassError {
 
 impl std::error::Error for HassError {}
 
+impl HassError {
+    /// Returns the id of the command which was rejected by Home Assistant, for the `ReponseError`
+    pub fn response_id(&self) -> Option<u64> {
+        match self {
+            Self::ReponseError(result) => Some(result.id),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the same request may succeed when retried later
+    ///
+    /// It is the case for the lost connections and the timeouts, and for the errors Home Assistant
+    /// reports when an integration or a device is temporarily unavailable (`home_assistant_error`).
+    /// The invalid requests, e.g. an unknown service, a wrong format or a missing permission, are not retryable,
+    /// nor is a Websocket handshake refused with a 4xx status.
+    ///
+    /// ```
+    /// use hass_rs::{HassError, WSResult};
+    ///
+    /// let result: WSResult = serde_json::from_str(
+    ///     r#"{"id": 5, "type": "result", "success": false,
+    ///         "error": {"code": "home_assistant_error", "message": "Device is offline"}}"#,
+    /// )
+    /// .unwrap();
+    /// assert!(HassError::ReponseError(result).is_retryable());
+    ///
+    /// let result: WSResult = serde_json::from_str(
+    ///     r#"{"id": 6, "type": "result", "success": false,
+    ///         "error": {"code": "not_found", "message": "Service light.blink not found."}}"#,
+    /// )
+    /// .unwrap();
+    /// assert!(!HassError::ReponseError(result).is_retryable());
+    /// ```
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ConnectionClosed
+            | Self::ConnectionClosedWith { .. }
+            | Self::Timeout
+            | Self::SendError(_) => true,
+            Self::TungsteniteError(e) => match e.as_ref() {
+                tungstenite::error::Error::ConnectionClosed
+                | tungstenite::error::Error::AlreadyClosed
+                | tungstenite::error::Error::Io(_)
+                | tungstenite::error::Error::Protocol(_) => true,
+                // the handshake was refused, e.g. 401/403 for a wrong token or 404 for a wrong path
+                tungstenite::error::Error::Http(response) => !response.status().is_client_error(),
+                _ => false,
+            },
+            #[cfg(feature = "use-async-std")]
+            Self::RecvError(_) => true,
+            #[cfg(feature = "rest")]
+            Self::RestError(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.status().is_some_and(|status| status.is_server_error())
+            }
+            Self::ReponseError(result) => match &result.error {
+                Some(error) => match error.code.as_str() {
+                    "timeout" => true,
+                    // older versions also report the unknown services with this code
+                    "home_assistant_error" => !error.message.contains("not found"),
+                    _ => false,
+                },
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for HassError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             // Self::CantConnectToGateway => write!(f, "Cannot connect to gateway"),
             Self::ConnectionClosed => write!(f, "Connection closed unexpectedly"),
+            Self::ConnectionClosedWith { code, reason } => write!(
+                f,
+                "Connection closed by the server with code {}: {}",
+                code, reason
+            ),
+            Self::MessageTooLarge { size, max_size } => write!(
+                f,
+                "The received message of {} bytes is larger than the maximum of {} bytes",
+                size, max_size
+            ),
+            Self::Timeout => write!(f, "The operation timed out"),
+            Self::CommandInterrupted => write!(
+                f,
+                "The connection was lost before the response of the command, which may have been executed"
+            ),
             Self::SendError(e) => write!(f, "Unable to send the message on channel: {}", e),
             Self::AuthenticationFailed(e) => write!(f, "Authentication has failed: {}", e),
+            Self::AuthenticationRequired => {
+                write!(f, "The server requires to authenticate again")
+            }
+            Self::SupervisorTokenMissing => write!(
+                f,
+                "The SUPERVISOR_TOKEN environment variable is not set, is this running as an add-on?"
+            ),
             Self::UnableToDeserialize(e) => {
                 write!(f, "Unable to deserialize the received value: {}", e)
             }
             Self::TungsteniteError(e) => write!(f, "Tungstenite Error: {}", e),
+            #[cfg(feature = "rest")]
+            Self::RestError(e) => write!(f, "REST API Error: {}", e),
             #[cfg(feature = "use-async-std")]
             Self::RecvError(e) => write!(f, "Receiver Error: {}", e),
             //Self::TokioTungsteniteError(e) => write!(f, "Tokio Tungstenite Error: {}", e),
@@ -78,6 +198,25 @@ impl From<RecvError> for HassError {
     }
 }
 
+#[cfg(feature = "rest")]
+impl From<reqwest::Error> for HassError {
+    fn from(error: reqwest::Error) -> Self {
+        HassError::RestError(error)
+    }
+}
+
+impl From<Option<CloseFrame<'_>>> for HassError {
+    fn from(frame: Option<CloseFrame<'_>>) -> Self {
+        match frame {
+            Some(frame) => HassError::ConnectionClosedWith {
+                code: frame.code.into(),
+                reason: frame.reason.into_owned(),
+            },
+            None => HassError::ConnectionClosed,
+        }
+    }
+}
+
 impl From<serde_json::error::Error> for HassError {
     fn from(error: serde_json::error::Error) -> Self {
         HassError::UnableToDeserialize(error)
@@ -88,20 +227,115 @@ impl From<tungstenite::error::Error> for HassError {
     fn from(error: tungstenite::error::Error) -> Self {
         match error {
             tungstenite::error::Error::ConnectionClosed => HassError::ConnectionClosed,
-            _ => HassError::TungsteniteError(error),
+            tungstenite::error::Error::Capacity(
+                tungstenite::error::CapacityError::MessageTooLong { size, max_size },
+            ) => HassError::MessageTooLarge { size, max_size },
+            _ => HassError::TungsteniteError(Box::new(error)),
         }
     }
 }
 
 impl From<&tungstenite::error::Error> for HassError {
     fn from(error: &tungstenite::error::Error) -> Self {
+        // the errors of a lost connection are kept, e.g. a TCP reset (Io) or a socket
+        // closed without the closing handshake (Protocol), so the client reconnects
         let e = match error {
-            tungstenite::error::Error::ConnectionClosed => {
-                tungstenite::error::Error::ConnectionClosed
+            tungstenite::error::Error::ConnectionClosed
+            | tungstenite::error::Error::AlreadyClosed => return HassError::ConnectionClosed,
+            tungstenite::error::Error::Io(e) => {
+                tungstenite::error::Error::Io(std::io::Error::new(e.kind(), e.to_string()))
+            }
+            tungstenite::error::Error::Protocol(e) => {
+                tungstenite::error::Error::Protocol(e.clone())
+            }
+            tungstenite::error::Error::Capacity(
+                tungstenite::error::CapacityError::MessageTooLong { size, max_size },
+            ) => {
+                return HassError::MessageTooLarge {
+                    size: *size,
+                    max_size: *max_size,
+                }
             }
-            tungstenite::error::Error::AlreadyClosed => tungstenite::error::Error::AlreadyClosed,
             _ => return HassError::Generic(format!("Error from ws {}", error)),
         };
-        HassError::TungsteniteError(e)
+        HassError::TungsteniteError(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_tungstenite::tungstenite::error::{Error, ProtocolError};
+    use async_tungstenite::tungstenite::http::Response;
+
+    fn refused(status: u16) -> HassError {
+        let response = Response::builder().status(status).body(None).unwrap();
+        HassError::from(Error::Http(response))
+    }
+
+    fn response_error(code: &str, message: &str) -> HassError {
+        HassError::ReponseError(WSResult {
+            id: 1,
+            success: false,
+            result: None,
+            error: Some(crate::types::ErrorCode {
+                code: code.to_owned(),
+                message: message.to_owned(),
+            }),
+        })
+    }
+
+    #[test]
+    fn a_lost_connection_is_retryable() {
+        assert!(HassError::ConnectionClosed.is_retryable());
+        assert!(HassError::ConnectionClosedWith {
+            code: 1001,
+            reason: "going away".to_owned()
+        }
+        .is_retryable());
+        assert!(HassError::Timeout.is_retryable());
+        assert!(HassError::SendError("closed".to_owned()).is_retryable());
+    }
+
+    #[test]
+    fn a_reset_socket_is_retryable() {
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(HassError::from(Error::Io(reset)).is_retryable());
+
+        let protocol = Error::Protocol(ProtocolError::ResetWithoutClosingHandshake);
+        assert!(HassError::from(&protocol).is_retryable());
+        assert!(HassError::from(protocol).is_retryable());
+        assert!(HassError::from(&Error::AlreadyClosed).is_retryable());
+    }
+
+    #[test]
+    fn a_refused_handshake_is_retryable_for_a_server_error_only() {
+        assert!(!refused(401).is_retryable());
+        assert!(!refused(403).is_retryable());
+        assert!(!refused(404).is_retryable());
+        assert!(refused(502).is_retryable());
+    }
+
+    #[test]
+    fn other_websocket_errors_are_not_retryable() {
+        let url = Error::Url(async_tungstenite::tungstenite::error::UrlError::NoHostName);
+        assert!(!HassError::from(url).is_retryable());
+        assert!(!HassError::MessageTooLarge {
+            size: 2,
+            max_size: 1
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn an_error_result_is_retryable_when_transient() {
+        assert!(response_error("home_assistant_error", "Device is offline").is_retryable());
+        assert!(response_error("timeout", "Timed out").is_retryable());
+        assert!(
+            !response_error("home_assistant_error", "Service light.blink not found.")
+                .is_retryable()
+        );
+        assert!(!response_error("unauthorized", "Unauthorized").is_retryable());
+        assert!(!HassError::Generic("not authenticated".to_owned()).is_retryable());
     }
 }
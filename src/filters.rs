@@ -0,0 +1,142 @@
+//! Stream adapters filtering the received events
+
+use crate::types::WSEvent;
+use futures_util::future;
+use futures_util::stream::{Stream, StreamExt};
+
+/// Keeps only the events where the attribute `key` changed, see `EventData::attribute_changed`
+///
+/// The events come e.g. from `HassClient::event_stream`, after `HassClient::subscribe_event("state_changed")`.
+///
+/// ```
+/// use futures::StreamExt;
+/// use hass_rs::{filters, WSEvent};
+///
+/// let event = |old: u8, new: u8| -> WSEvent {
+///     let state = |brightness: u8| {
+///         serde_json::json!({
+///             "entity_id": "light.kitchen", "state": "on", "attributes": {"brightness": brightness},
+///             "last_changed": "2024-01-01T10:00:00+00:00", "last_updated": "2024-01-01T10:00:00+00:00",
+///             "context": null
+///         })
+///     };
+///     serde_json::from_value(serde_json::json!({
+///         "id": 1, "type": "event", "event": {
+///             "event_type": "state_changed", "time_fired": "2024-01-01T10:00:00+00:00",
+///             "origin": "LOCAL", "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"},
+///             "data": {"entity_id": "light.kitchen", "old_state": state(old), "new_state": state(new)}
+///         }
+///     }))
+///     .unwrap()
+/// };
+///
+/// let events = futures::stream::iter(vec![event(100, 100), event(100, 255)]);
+/// let changes: Vec<WSEvent> =
+///     futures::executor::block_on(filters::only_attribute_changes(events, "brightness").collect());
+/// assert_eq!(changes.len(), 1);
+/// assert_eq!(changes[0].event.data.new_state.as_ref().unwrap().attributes["brightness"], 255);
+/// ```
+pub fn only_attribute_changes<S>(events: S, key: &str) -> impl Stream<Item = WSEvent>
+where
+    S: Stream<Item = WSEvent>,
+{
+    let key = key.to_owned();
+    events.filter(move |event| future::ready(event.event.data.attribute_changed(&key)))
+}
+
+/// Keeps only the events where the state itself changed, see `EventData::state_changed`
+pub fn only_state_changes<S>(events: S) -> impl Stream<Item = WSEvent>
+where
+    S: Stream<Item = WSEvent>,
+{
+    events.filter(|event| future::ready(event.event.data.state_changed()))
+}
+
+/// Keeps only the events initiated by a user, see `HassEvent::triggered_by_user`
+///
+/// The changes made by Home Assistant itself, e.g. by the automations or the integrations, are skipped.
+pub fn only_user_events<S>(events: S) -> impl Stream<Item = WSEvent>
+where
+    S: Stream<Item = WSEvent>,
+{
+    events.filter(|event| future::ready(event.event.triggered_by_user().is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn state(state: &str, brightness: u8) -> Value {
+        json!({
+            "entity_id": "light.kitchen", "state": state, "attributes": {"brightness": brightness},
+            "last_changed": "2024-01-01T10:00:00+00:00", "last_updated": "2024-01-01T10:00:00+00:00",
+            "context": null
+        })
+    }
+
+    fn event(old_state: Value, new_state: Value, user_id: Option<&str>) -> WSEvent {
+        serde_json::from_value(json!({
+            "id": 1,
+            "type": "event",
+            "event": {
+                "data": {"entity_id": "light.kitchen", "new_state": new_state, "old_state": old_state},
+                "event_type": "state_changed",
+                "time_fired": "2024-01-01T10:00:00+00:00",
+                "origin": "LOCAL",
+                "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB", "parent_id": null, "user_id": user_id}
+            }
+        }))
+        .unwrap()
+    }
+
+    fn kept<S: Stream<Item = WSEvent>>(filtered: S) -> Vec<WSEvent> {
+        futures::executor::block_on(filtered.collect())
+    }
+
+    #[test]
+    fn only_the_changed_attributes_are_kept() {
+        let events = vec![
+            event(state("on", 100), state("on", 100), None),
+            event(state("on", 100), state("on", 255), None),
+            event(state("off", 0), Value::Null, None),
+        ];
+        let changes = kept(only_attribute_changes(
+            futures::stream::iter(events.clone()),
+            "brightness",
+        ));
+        assert_eq!(changes, [events[1].clone(), events[2].clone()]);
+
+        // an attribute missing from both states didn't change
+        let colors = kept(only_attribute_changes(
+            futures::stream::iter(events),
+            "rgb_color",
+        ));
+        assert!(colors.is_empty());
+    }
+
+    #[test]
+    fn only_the_changed_states_are_kept() {
+        let events = vec![
+            event(state("on", 100), state("on", 255), None),
+            event(state("on", 255), state("off", 0), None),
+            event(Value::Null, state("on", 100), None),
+        ];
+        let changes = kept(only_state_changes(futures::stream::iter(events.clone())));
+        assert_eq!(changes, [events[1].clone(), events[2].clone()]);
+    }
+
+    #[test]
+    fn only_the_events_of_a_user_are_kept() {
+        let events = vec![
+            event(
+                state("off", 0),
+                state("on", 255),
+                Some("31ddb597e03147118cf8d2f8fbea5553"),
+            ),
+            event(state("on", 255), state("off", 0), None),
+        ];
+        let changes = kept(only_user_events(futures::stream::iter(events.clone())));
+        assert_eq!(changes, [events[0].clone()]);
+    }
+}
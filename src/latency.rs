@@ -0,0 +1,89 @@
+//! Command round-trip latency statistics
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// number of round-trips kept for computing the statistics
+const WINDOW_SIZE: usize = 100;
+
+/// Rolling statistics of the command round-trip times
+///
+/// Computed over the last 100 commands sent by the client, the durations are zero when no command was sent yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration,
+    pub p99: Duration,
+}
+
+// holds the latest round-trip times
+#[derive(Debug, Default)]
+pub(crate) struct LatencyWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyWindow {
+    pub(crate) fn record(&mut self, rtt: Duration) {
+        if self.samples.len() == WINDOW_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+    }
+
+    pub(crate) fn stats(&self) -> LatencyStats {
+        if self.samples.is_empty() {
+            return LatencyStats::default();
+        }
+
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+
+        let samples = sorted.len();
+        let total: Duration = sorted.iter().sum();
+        let p99_index = (samples * 99).div_ceil(100) - 1;
+
+        LatencyStats {
+            samples,
+            min: sorted[0],
+            max: sorted[samples - 1],
+            avg: total / samples as u32,
+            p99: sorted[p99_index],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_of_the_window() {
+        let mut window = LatencyWindow::default();
+        assert_eq!(window.stats(), LatencyStats::default());
+
+        for millis in [30, 10, 20] {
+            window.record(Duration::from_millis(millis));
+        }
+        let stats = window.stats();
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.min, Duration::from_millis(10));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert_eq!(stats.avg, Duration::from_millis(20));
+        assert_eq!(stats.p99, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn only_the_latest_round_trips_are_kept() {
+        let mut window = LatencyWindow::default();
+        window.record(Duration::from_secs(60));
+        for _ in 0..WINDOW_SIZE {
+            window.record(Duration::from_millis(5));
+        }
+        let stats = window.stats();
+        assert_eq!(stats.samples, WINDOW_SIZE);
+        assert_eq!(stats.max, Duration::from_millis(5));
+        assert_eq!(stats.avg, Duration::from_millis(5));
+    }
+}
@@ -4,6 +4,31 @@
 //!
 //! It is based on the [official API specifications](https://developers.home-assistant.io/docs/api/websocket).
 //!
+//! The client requires a runtime, selected with the `use-tokio` (default) or the `use-async-std` feature.
+//! Without any of them, e.g. with `default-features = false, features = ["types-only"]`, only the `types`,
+//! the `errors`, the `StateCache` and the `filters` are built, to parse the Home Assistant payloads
+//! without pulling in a runtime.
+//! The `rest` feature adds the `RestClient`, for the REST API.
+//! The `tracing` feature instruments the client with [tracing](https://docs.rs/tracing): a span per command,
+//! with its id and type, and events for the frames, the subscriptions and the reconnections.
+//! The `blocking` feature adds the `blocking::BlockingClient`, for the scripts which don't run an async runtime.
+//! The `chrono` feature reads the `timestamp` and `date` sensors as [chrono](https://docs.rs/chrono) types.
+//!
+
+#[cfg(all(
+    feature = "client",
+    not(any(feature = "use-tokio", feature = "use-async-std"))
+))]
+compile_error!("the client feature requires a runtime, enable use-tokio or use-async-std");
+
+// emits a tracing event when the tracing feature is enabled, and nothing otherwise
+#[allow(unused_macros)]
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
 
 pub mod errors;
 pub use errors::{HassError, HassResult};
@@ -11,8 +36,48 @@ pub use errors::{HassError, HassResult};
 pub mod types;
 pub use types::*;
 
+#[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "client")]
 pub use client::HassClient;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "rest")]
+pub use rest::RestClient;
+
+pub mod filters;
+
+pub mod cache;
+pub use cache::{StateCache, StateChange};
+
+#[cfg(feature = "client")]
+pub mod managed;
+#[cfg(feature = "client")]
+pub use managed::{CacheUpdate, ManagedStateCache};
+
+#[cfg(feature = "client")]
+pub mod latency;
+#[cfg(feature = "client")]
+pub use latency::LatencyStats;
+
+#[cfg(feature = "client")]
+pub mod sequence;
+#[cfg(feature = "client")]
+pub use sequence::{AtomicSequence, SequenceSource};
+
+#[cfg(feature = "client")]
+mod wsconn;
+#[cfg(feature = "client")]
+pub use wsconn::{ClientConfig, ReconnectPolicy};
+
+#[cfg(feature = "client")]
 mod runtime;
+#[cfg(feature = "client")]
 use runtime::{Receiver, Sender};
+
+#[cfg(all(test, feature = "client"))]
+mod mock;
@@ -0,0 +1,342 @@
+//! A StateCache which reconnects to Home Assistant and re-primes itself
+
+use crate::cache::{StateCache, StateChange};
+use crate::client::HassClient;
+use crate::runtime::{channel, receiver_stream, sleep, timeout};
+use crate::types::{HassEntityState, WSEvent};
+use crate::{ClientConfig, HassResult, Receiver, Sender};
+
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use log::warn;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+
+// delay before the first reconnection attempt, doubled after each failed attempt,
+// unless the ClientConfig has a reconnect policy
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(60);
+
+// the connection is pinged after this long without any event, and considered lost without a pong
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const PONG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Notification sent by a ManagedStateCache
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheUpdate {
+    /// A `state_changed` event was applied to the cache
+    Changed(StateChange),
+    /// The connection was lost, the cache keeps the last known states until it is resynced
+    Disconnected,
+    /// The connection was opened again and the cache was replaced by the current states
+    ///
+    /// No change is reported for the states which changed during the outage: the consumers keeping
+    /// their own copy of the states should rebuild it from the `snapshot`, or at least drop the `removed` entities,
+    /// which disappeared during the outage.
+    Resynced { removed: Vec<HassEntityState> },
+}
+
+#[derive(Debug)]
+struct Shared {
+    cache: StateCache,
+    stale_since: Option<SystemTime>,
+}
+
+/// StateCache of all the entities, kept current across the restarts of Home Assistant
+///
+/// It is created by `ClientConfig::managed_state_cache`, or by `HassClient::managed_state_cache` from a connected client,
+/// and it owns its own connection: a task applies the `state_changed` events to the cache, and when the connection is lost
+/// it reconnects with an increasing delay, then replaces the cache with the states returned by `get_states`.
+/// The delays are those of the `ClientConfig::reconnect` policy when it is set, and the attempts go on
+/// until Home Assistant is back.
+/// A connection which stays quiet is pinged, and a missing pong is handled as a lost connection,
+/// so a stalled connection is replaced too.
+///
+/// While Home Assistant is down the last known states are still returned, and `stale_since`
+/// tells since when they may be outdated. The task stops when the ManagedStateCache is dropped.
+///
+/// ```no_run
+/// # async fn example() -> hass_rs::HassResult<()> {
+/// use futures_util::StreamExt;
+/// use hass_rs::{CacheUpdate, ClientConfig};
+///
+/// let mut cache = ClientConfig::default()
+///     .managed_state_cache("ws://localhost:8123/api/websocket", "your_token")
+///     .await?;
+/// let mut updates = Box::pin(cache.updates_stream().expect("the updates were already taken"));
+///
+/// while let Some(update) = updates.next().await {
+///     match update {
+///         CacheUpdate::Disconnected => println!("stale since {:?}", cache.stale_since()),
+///         _ => println!("{:?}", cache.get("sun.sun")),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ManagedStateCache {
+    shared: Arc<Mutex<Shared>>,
+    updates: Option<Receiver<CacheUpdate>>,
+}
+
+impl ManagedStateCache {
+    pub(crate) async fn start(
+        config: ClientConfig,
+        capacity: usize,
+        url: String,
+        token: String,
+    ) -> HassResult<Self> {
+        let (client, events, states) = connect_and_prime(&config, &url, &token).await?;
+        let shared = Arc::new(Mutex::new(Shared {
+            cache: StateCache::new(states),
+            stale_since: None,
+        }));
+        let (to_updates, updates) = channel::<CacheUpdate>(capacity);
+
+        let task = maintain(
+            config.clone(),
+            url,
+            token,
+            client,
+            events,
+            Arc::downgrade(&shared),
+            to_updates,
+        );
+        config.spawn_task(task);
+
+        Ok(ManagedStateCache {
+            shared,
+            updates: Some(updates),
+        })
+    }
+
+    /// Returns the last known state of an entity
+    pub fn get(&self, entity_id: &str) -> Option<HassEntityState> {
+        self.shared.lock().unwrap().cache.get(entity_id).cloned()
+    }
+
+    /// Returns a copy of the whole cache
+    pub fn snapshot(&self) -> StateCache {
+        self.shared.lock().unwrap().cache.clone()
+    }
+
+    /// Returns when the connection was lost, None while the cache is current
+    pub fn stale_since(&self) -> Option<SystemTime> {
+        self.shared.lock().unwrap().stale_since
+    }
+
+    /// Returns true while the connection is lost
+    pub fn is_stale(&self) -> bool {
+        self.stale_since().is_some()
+    }
+
+    /// Takes the receiver of the changes, disconnections and resyncs of the cache
+    ///
+    /// The updates are never waited on: they are dropped while the receiver is full, or when it was not taken.
+    pub fn take_updates(&mut self) -> Option<Receiver<CacheUpdate>> {
+        self.updates.take()
+    }
+
+    /// Takes the updates of the cache as a Stream, see [`ManagedStateCache::take_updates`].
+    pub fn updates_stream(&mut self) -> Option<impl Stream<Item = CacheUpdate>> {
+        self.updates.take().map(receiver_stream)
+    }
+}
+
+// subscribes to the state changes before reading the states, so no change is missed in between
+async fn connect_and_prime(
+    config: &ClientConfig,
+    url: &str,
+    token: &str,
+) -> HassResult<(
+    HassClient,
+    BoxStream<'static, WSEvent>,
+    Vec<HassEntityState>,
+)> {
+    let mut client = config
+        .clone()
+        .without_reconnect()
+        .connect_and_auth(url, token)
+        .await?;
+    client.subscribe_event("state_changed").await?;
+    let events = client
+        .event_stream()
+        .expect("the events of a new connection are available")
+        .boxed();
+    let states = client.get_states().await?;
+    Ok((client, events, states))
+}
+
+async fn maintain(
+    config: ClientConfig,
+    url: String,
+    token: String,
+    mut client: HassClient,
+    mut events: BoxStream<'static, WSEvent>,
+    shared: Weak<Mutex<Shared>>,
+    updates: Sender<CacheUpdate>,
+) {
+    loop {
+        // the events stream ends when the connection is lost
+        loop {
+            // the Websocket ping frames replace the heartbeat, a dead connection ends the events
+            let next = match config.has_protocol_keepalive() {
+                true => Some(events.next().await),
+                false => timeout(HEARTBEAT_INTERVAL, events.next()).await,
+            };
+            let event = match next {
+                Some(Some(event)) => event,
+                Some(None) => break,
+                None => match client.ping_within(PONG_TIMEOUT).await {
+                    Ok(_) => continue,
+                    Err(error) => {
+                        warn!("The connection to Home Assistant is stalled: {}", error);
+                        break;
+                    }
+                },
+            };
+
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            let change = shared.lock().unwrap().cache.apply(&event.event);
+            if let Some(change) = change {
+                let _ = updates.try_send(CacheUpdate::Changed(change));
+            }
+        }
+        drop(client);
+
+        match shared.upgrade() {
+            Some(shared) => shared.lock().unwrap().stale_since = Some(SystemTime::now()),
+            None => return,
+        }
+        warn!("Lost the connection to Home Assistant, the state cache is stale");
+        trace_event!(info, "state cache disconnected");
+        let _ = updates.try_send(CacheUpdate::Disconnected);
+
+        let (delay_min, delay_max) = match config.reconnect_policy() {
+            Some(policy) => (policy.min_delay, policy.max_delay),
+            None => (RECONNECT_DELAY_MIN, RECONNECT_DELAY_MAX),
+        };
+        let mut delay = delay_min;
+        let removed;
+        (client, events, removed) = loop {
+            sleep(delay).await;
+            if shared.strong_count() == 0 {
+                return;
+            }
+
+            match connect_and_prime(&config, &url, &token).await {
+                Ok((client, events, states)) => {
+                    let Some(shared) = shared.upgrade() else {
+                        return;
+                    };
+                    let mut shared = shared.lock().unwrap();
+                    let cache = StateCache::new(states);
+                    let removed = shared
+                        .cache
+                        .states()
+                        .filter(|state| cache.get(&state.entity_id).is_none())
+                        .cloned()
+                        .collect();
+                    shared.cache = cache;
+                    shared.stale_since = None;
+                    trace_event!(info, entities = shared.cache.len(), "state cache resynced");
+                    break (client, events, removed);
+                }
+                Err(error) => {
+                    warn!("Could not reconnect to Home Assistant: {}", error);
+                    trace_event!(debug, error = %error, delay_ms = delay.as_millis() as u64, "reconnection failed");
+                    delay = (delay * 2).min(delay_max);
+                }
+            }
+        };
+        let _ = updates.try_send(CacheUpdate::Resynced { removed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{entity_state, listen, reconnecting, state_changed, MockServer};
+    use serde_json::{json, Value};
+
+    // the next update of the cache, failing instead of waiting forever
+    async fn next_update(updates: &mut (impl Stream<Item = CacheUpdate> + Unpin)) -> CacheUpdate {
+        timeout(Duration::from_secs(5), updates.next())
+            .await
+            .expect("the cache was not updated")
+            .expect("the updates ended")
+    }
+
+    // answers the priming of the cache, the subscription to the state changes then get_states,
+    // and returns the id of the subscription
+    async fn prime(server: &mut MockServer, states: Value) -> u64 {
+        let subscribe = server.reply(Value::Null).await;
+        assert_eq!(subscribe["event_type"], "state_changed");
+        let get_states = server.reply(states).await;
+        assert_eq!(get_states["type"], "get_states");
+        subscribe["id"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_cache_is_reprimed_after_a_restart() {
+        let (listener, url) = listen().await;
+        let (mut client, _server) = MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        let (cache, (mut server, subscription)) =
+            futures::join!(client.managed_state_cache(), async {
+                let mut server = MockServer::accept(&listener).await;
+                server.auth().await;
+                let states = json!([
+                    entity_state("light.kitchen", "on"),
+                    entity_state("light.hall", "off")
+                ]);
+                let subscription = prime(&mut server, states).await;
+                (server, subscription)
+            });
+        let mut cache = cache.unwrap();
+        let mut updates = Box::pin(cache.updates_stream().unwrap());
+        assert_eq!(cache.get("light.hall").unwrap().state, "off");
+
+        server
+            .send_event(subscription, state_changed("light.kitchen", Some("off")))
+            .await;
+        assert!(matches!(
+            next_update(&mut updates).await,
+            CacheUpdate::Changed(_)
+        ));
+        assert_eq!(cache.get("light.kitchen").unwrap().state, "off");
+
+        // Home Assistant restarts, the last known states are kept meanwhile
+        drop(server);
+        assert_eq!(next_update(&mut updates).await, CacheUpdate::Disconnected);
+        assert!(cache.is_stale());
+        assert_eq!(cache.get("light.kitchen").unwrap().state, "off");
+
+        // it is back without the hall light, the cache is replaced by its states
+        let mut server = MockServer::accept(&listener).await;
+        server.auth().await;
+        let subscription = prime(&mut server, json!([entity_state("light.kitchen", "on")])).await;
+        match next_update(&mut updates).await {
+            CacheUpdate::Resynced { removed } => {
+                let removed: Vec<_> = removed.iter().map(|state| &state.entity_id).collect();
+                assert_eq!(removed, ["light.hall"]);
+            }
+            update => panic!("expected the resync, got {:?}", update),
+        }
+        assert!(!cache.is_stale());
+        assert!(cache.get("light.hall").is_none());
+        assert_eq!(cache.get("light.kitchen").unwrap().state, "on");
+
+        // and it is kept current again
+        server
+            .send_event(subscription, state_changed("light.kitchen", Some("off")))
+            .await;
+        assert!(matches!(
+            next_update(&mut updates).await,
+            CacheUpdate::Changed(_)
+        ));
+        assert_eq!(cache.get("light.kitchen").unwrap().state, "off");
+    }
+}
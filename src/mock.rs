@@ -0,0 +1,304 @@
+// a fake Home Assistant server, for the tests of the client
+
+use crate::{ClientConfig, HassClient, ReconnectPolicy};
+
+use async_tungstenite::tungstenite::handshake::server::{Request, Response};
+use async_tungstenite::tungstenite::http;
+use async_tungstenite::tungstenite::{Error, Message};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+// the longest wait for a message, so a broken test fails instead of hanging
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the server side of a connection, reading the commands of the client and writing the replies
+pub(crate) struct MockServer {
+    to_client: mpsc::Sender<Result<Message, Error>>,
+    from_client: mpsc::Receiver<Message>,
+    // the tasks pumping the frames of a Websocket accepted by `accept`
+    pumps: Vec<JoinHandle<()>>,
+}
+
+impl MockServer {
+    // a client connected to a new server, through `HassClient::from_channels`
+    pub(crate) fn client() -> (HassClient, MockServer) {
+        let (to_server, from_client) = mpsc::channel(100);
+        let (to_client, from_server) = mpsc::channel(100);
+
+        let client = HassClient::from_channels(to_server, from_server);
+        let server = MockServer {
+            to_client,
+            from_client,
+            pumps: Vec::new(),
+        };
+        (client, server)
+    }
+
+    // a client connected to a new server, and authenticated
+    pub(crate) async fn authenticated() -> (HassClient, MockServer) {
+        let (mut client, mut server) = MockServer::client();
+        let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("token"), server.auth());
+        auth.unwrap();
+        (client, server)
+    }
+
+    // a client connected by the `config` to a new server listening on localhost, and authenticated
+    pub(crate) async fn connected(config: ClientConfig) -> (HassClient, MockServer) {
+        let (listener, url) = listen().await;
+        MockServer::connected_to(config, &listener, &url).await
+    }
+
+    // a client connected by the `config` to the `listener` at `url`, and authenticated,
+    // the listener is kept by the caller to accept the next connections of a reconnecting client
+    pub(crate) async fn connected_to(
+        config: ClientConfig,
+        listener: &TcpListener,
+        url: &str,
+    ) -> (HassClient, MockServer) {
+        let (client, mut server) =
+            futures::join!(config.connect(url), MockServer::accept(listener));
+        let mut client = client.unwrap();
+        let (auth, ()) = futures::join!(client.auth_with_longlivedtoken("token"), server.auth());
+        auth.unwrap();
+        (client, server)
+    }
+
+    // accepts the next Websocket connection of the `listener`
+    pub(crate) async fn accept(listener: &TcpListener) -> MockServer {
+        let (server, _) = MockServer::accept_with_headers(listener).await;
+        server
+    }
+
+    // accepts the next Websocket connection of the `listener`, with the headers of its handshake request
+    pub(crate) async fn accept_with_headers(
+        listener: &TcpListener,
+    ) -> (MockServer, http::HeaderMap) {
+        let (socket, _) = tokio::time::timeout(RECEIVE_TIMEOUT, listener.accept())
+            .await
+            .expect("the client didn't connect")
+            .unwrap();
+        let mut headers = http::HeaderMap::new();
+        let websocket =
+            tokio_tungstenite::accept_hdr_async(socket, |request: &Request, response| {
+                headers = request.headers().clone();
+                Ok(response)
+            })
+            .await
+            .unwrap();
+        let (mut sink, mut stream) = websocket.split();
+
+        let (to_client, mut from_server) = mpsc::channel::<Result<Message, Error>>(100);
+        let (mut to_server, from_client) = mpsc::channel(100);
+        let sending = tokio::spawn(async move {
+            // the Websocket is closed once the server is dropped
+            while let Some(Ok(message)) = from_server.next().await {
+                if sink.send(message).await.is_err() {
+                    return;
+                }
+            }
+            let _ = sink.close().await;
+        });
+        let receiving = tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if to_server.send(message).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let server = MockServer {
+            to_client,
+            from_client,
+            pumps: vec![sending, receiving],
+        };
+        (server, headers)
+    }
+
+    // refuses the Websocket handshake of the next connection of the `listener` with the HTTP `status`
+    pub(crate) async fn refuse(listener: &TcpListener, status: u16) {
+        let (socket, _) = tokio::time::timeout(RECEIVE_TIMEOUT, listener.accept())
+            .await
+            .expect("the client didn't connect")
+            .unwrap();
+        let refused = tokio_tungstenite::accept_hdr_async(socket, |_: &Request, _: Response| {
+            Err(http::Response::builder().status(status).body(None).unwrap())
+        })
+        .await;
+        assert!(refused.is_err());
+    }
+
+    // drops the socket of an accepted Websocket without the closing handshake, like a crashed server
+    pub(crate) async fn drop_connection(self) {
+        for pump in self.pumps {
+            pump.abort();
+            let _ = pump.await;
+        }
+    }
+
+    // sends a text frame to the client
+    pub(crate) async fn send(&mut self, text: impl Into<String>) {
+        self.send_frame(Message::Text(text.into())).await;
+    }
+
+    pub(crate) async fn send_json(&mut self, value: Value) {
+        self.send(value.to_string()).await;
+    }
+
+    // fails the reading of the client with the `error`, as if it came from its socket
+    pub(crate) async fn send_error(&mut self, error: Error) {
+        self.to_client.send(Err(error)).await.unwrap();
+    }
+
+    pub(crate) async fn send_frame(&mut self, frame: Message) {
+        self.to_client.send(Ok(frame)).await.unwrap();
+    }
+
+    // receives the next text frame of the client, as JSON
+    pub(crate) async fn receive(&mut self) -> Value {
+        loop {
+            let message = tokio::time::timeout(RECEIVE_TIMEOUT, self.from_client.next())
+                .await
+                .expect("the client didn't send anything")
+                .expect("the client is gone");
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    // returns true if the client sends nothing within `duration`
+    pub(crate) async fn is_silent(&mut self, duration: Duration) -> bool {
+        tokio::time::timeout(duration, self.from_client.next())
+            .await
+            .is_err()
+    }
+
+    // answers the authentication of the client
+    pub(crate) async fn auth(&mut self) {
+        self.send_json(json!({"type": "auth_required", "ha_version": "2024.1.0"}))
+            .await;
+        let auth = self.receive().await;
+        assert_eq!(auth["type"], "auth");
+        self.send_json(json!({"type": "auth_ok", "ha_version": "2024.1.0"}))
+            .await;
+    }
+
+    // receives the next ping, and answers it
+    pub(crate) async fn pong(&mut self) {
+        let ping = self.receive().await;
+        assert_eq!(ping["type"], "ping");
+        self.send_json(json!({"id": ping["id"], "type": "pong"}))
+            .await;
+    }
+
+    // receives the next command, and answers it with a successful `result`
+    pub(crate) async fn reply(&mut self, result: Value) -> Value {
+        let command = self.receive().await;
+        self.send_json(success(&command, result)).await;
+        command
+    }
+
+    // sends an event of the subscription `id`
+    pub(crate) async fn send_event(&mut self, id: u64, event: Value) {
+        self.send_json(json!({"id": id, "type": "event", "event": event}))
+            .await;
+    }
+}
+
+// a config reconnecting right away, so the tests don't wait
+pub(crate) fn reconnecting() -> ClientConfig {
+    ClientConfig::default().reconnect(ReconnectPolicy {
+        min_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(100),
+        max_attempts: 3,
+    })
+}
+
+// a listener for the connections of the client, and its Websocket URL
+pub(crate) async fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/api/websocket", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+// the successful result of the `command`
+pub(crate) fn success(command: &Value, result: Value) -> Value {
+    json!({"id": command["id"], "type": "result", "success": true, "result": result})
+}
+
+// the error result of the `command`, with the `code` and `message` of Home Assistant
+pub(crate) fn failure(command: &Value, code: &str, message: &str) -> Value {
+    json!({
+        "id": command["id"],
+        "type": "result",
+        "success": false,
+        "error": {"code": code, "message": message}
+    })
+}
+
+// a state_changed event of the entity, removed when `new_state` is None
+pub(crate) fn state_changed(entity_id: &str, new_state: Option<&str>) -> Value {
+    let new_state = new_state.map(|state| entity_state(entity_id, state));
+    json!({
+        "data": {"entity_id": entity_id, "new_state": new_state, "old_state": null},
+        "event_type": "state_changed",
+        "time_fired": "2024-01-01T00:00:00+00:00",
+        "origin": "LOCAL",
+        "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+    })
+}
+
+// the state of an entity, as listed by get_states
+pub(crate) fn entity_state(entity_id: &str, state: &str) -> Value {
+    json!({
+        "entity_id": entity_id,
+        "state": state,
+        "attributes": {},
+        "last_changed": "2024-01-01T00:00:00+00:00",
+        "last_updated": "2024-01-01T00:00:00+00:00",
+        "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+    })
+}
+
+// the config of the instance, in the run `state`, as returned by get_config
+pub(crate) fn config(state: &str) -> Value {
+    json!({
+        "latitude": 52.37, "longitude": 4.89, "elevation": 0,
+        "unit_system": {
+            "length": "km", "mass": "g", "pressure": "Pa", "temperature": "°C", "volume": "L"
+        },
+        "location_name": "Home", "time_zone": "Europe/Amsterdam", "components": ["light"],
+        "config_dir": "/config", "whitelist_external_dirs": [], "version": "2024.1.0",
+        "config_source": "storage", "external_url": null, "internal_url": null, "state": state
+    })
+}
+
+// an entry of the entity registry, of the device `device_id` when set
+pub(crate) fn registry_entry(entity_id: &str, device_id: Option<&str>) -> Value {
+    json!({
+        "area_id": null, "config_entry_id": null, "device_id": device_id, "disabled_by": null,
+        "entity_category": null, "entity_id": entity_id, "has_entity_name": false,
+        "hidden_by": null, "icon": null, "id": format!("id-{}", entity_id), "name": null,
+        "options": {}, "original_name": null, "platform": "hue", "translation_key": null,
+        "unique_id": format!("unique-{}", entity_id)
+    })
+}
+
+// an entry of the device registry, in the area `area_id` when set
+pub(crate) fn device(device_id: &str, area_id: Option<&str>) -> Value {
+    json!({
+        "id": device_id, "name": device_id, "area_id": area_id, "config_entries": [],
+        "configuration_url": null, "connections": [], "disabled_by": null, "entry_type": null,
+        "hw_version": null, "identifiers": [], "manufacturer": null, "model": null,
+        "name_by_user": null, "serial_number": null, "sw_version": null, "via_device_id": null
+    })
+}
+
+// an entry of the area registry
+pub(crate) fn area(area_id: &str, name: &str) -> Value {
+    json!({"area_id": area_id, "name": name, "aliases": [], "picture": null})
+}
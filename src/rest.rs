@@ -0,0 +1,236 @@
+//! Home Assistant REST API client
+//!
+//! A fallback to the Websocket client, for one-off scripts or when only HTTP is reachable.
+//! It is enabled by the `rest` feature.
+
+use crate::types::HassEntityState;
+use crate::HassResult;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+
+/// Client of the Home Assistant [REST API](https://developers.home-assistant.io/docs/api/rest/)
+///
+/// Each request is authenticated with the long-lived access token as a bearer token.
+///
+/// ```no_run
+/// use hass_rs::RestClient;
+///
+/// # async fn example() -> hass_rs::HassResult<()> {
+/// let client = RestClient::new("http://localhost:8123", "your_token");
+///
+/// if let Some(sun) = client.get_state("sun.sun").await? {
+///     println!("the sun is {}", sun.state);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl RestClient {
+    /// Creates a client for the Home Assistant instance at `base_url`, e.g. "http://localhost:8123"
+    pub fn new(base_url: &str, token: &str) -> Self {
+        RestClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+        }
+    }
+
+    /// Gets the current state of an entity, `None` is returned when the entity doesn't exist
+    pub async fn get_state(&self, entity_id: &str) -> HassResult<Option<HassEntityState>> {
+        let url = format!("{}/api/states/{}", self.base_url, entity_id);
+        let response = self.http.get(url).bearer_auth(&self.token).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let state = response.error_for_status()?.json().await?;
+        Ok(Some(state))
+    }
+
+    /// Gets all the current states
+    pub async fn get_states(&self) -> HassResult<Vec<HassEntityState>> {
+        let url = format!("{}/api/states", self.base_url);
+        let response = self.http.get(url).bearer_auth(&self.token).send().await?;
+
+        let states = response.error_for_status()?.json().await?;
+        Ok(states)
+    }
+
+    /// Calls a service, the states which changed while the service was executing are returned
+    pub async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        service_data: Option<Value>,
+    ) -> HassResult<Vec<HassEntityState>> {
+        let url = format!("{}/api/services/{}/{}", self.base_url, domain, service);
+        let response = self
+            .http
+            .post(url)
+            .bearer_auth(&self.token)
+            .json(&service_data.unwrap_or_else(|| Value::Object(Default::default())))
+            .send()
+            .await?;
+
+        let states = response.error_for_status()?.json().await?;
+        Ok(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HassError;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // the request received by the mock server
+    #[derive(Debug)]
+    struct Request {
+        // e.g. "GET /api/states"
+        line: String,
+        authorization: Option<String>,
+        body: String,
+    }
+
+    // serves a single request with the `status` and the JSON `body`, returns the base URL of the server
+    async fn serve_once(status: u16, body: Value) -> (String, JoinHandle<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let head_end = loop {
+                let mut chunk = [0; 1024];
+                let read = socket.read(&mut chunk).await.unwrap();
+                received.extend_from_slice(&chunk[..read]);
+                if let Some(at) = received.windows(4).position(|end| end == b"\r\n\r\n") {
+                    break at + 4;
+                }
+            };
+
+            let head = String::from_utf8(received[..head_end].to_vec()).unwrap();
+            let mut lines = head.lines();
+            let line = lines
+                .next()
+                .unwrap()
+                .trim_end_matches(" HTTP/1.1")
+                .to_owned();
+            let header = |name: &str| {
+                head.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    key.eq_ignore_ascii_case(name)
+                        .then(|| value.trim().to_owned())
+                })
+            };
+            let length: usize =
+                header("content-length").map_or(0, |length| length.parse().unwrap());
+            while received.len() < head_end + length {
+                let mut chunk = [0; 1024];
+                let read = socket.read(&mut chunk).await.unwrap();
+                received.extend_from_slice(&chunk[..read]);
+            }
+
+            let body = body.to_string();
+            let response = format!(
+                "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+
+            Request {
+                line,
+                authorization: header("authorization"),
+                body: String::from_utf8(received[head_end..].to_vec()).unwrap(),
+            }
+        });
+        (base_url, server)
+    }
+
+    fn state(entity_id: &str, state: &str) -> Value {
+        json!({
+            "entity_id": entity_id, "state": state, "attributes": {},
+            "last_changed": "2024-01-01T00:00:00+00:00",
+            "last_updated": "2024-01-01T00:00:00+00:00",
+            "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+        })
+    }
+
+    #[tokio::test]
+    async fn get_state_of_an_entity() {
+        let (base_url, server) = serve_once(200, state("sun.sun", "above_horizon")).await;
+        let client = RestClient::new(&format!("{}/", base_url), "token");
+
+        let sun = client.get_state("sun.sun").await.unwrap().unwrap();
+        assert_eq!(sun.state, "above_horizon");
+
+        let request = server.await.unwrap();
+        assert_eq!(request.line, "GET /api/states/sun.sun");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer token"));
+    }
+
+    #[tokio::test]
+    async fn get_state_of_a_missing_entity() {
+        let (base_url, server) = serve_once(404, json!({"message": "Entity not found."})).await;
+        let client = RestClient::new(&base_url, "token");
+
+        assert_eq!(client.get_state("light.missing").await.unwrap(), None);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_states_of_all_entities() {
+        let states = json!([state("sun.sun", "below_horizon"), state("light.hall", "on")]);
+        let (base_url, server) = serve_once(200, states).await;
+        let client = RestClient::new(&base_url, "token");
+
+        let states = client.get_states().await.unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[1].entity_id, "light.hall");
+        assert_eq!(server.await.unwrap().line, "GET /api/states");
+    }
+
+    #[tokio::test]
+    async fn call_service_posts_the_data() {
+        let (base_url, server) = serve_once(200, json!([state("light.hall", "on")])).await;
+        let client = RestClient::new(&base_url, "token");
+
+        let data = json!({"entity_id": "light.hall", "brightness": 128});
+        let changed = client
+            .call_service("light", "turn_on", Some(data.clone()))
+            .await
+            .unwrap();
+        assert_eq!(changed[0].state, "on");
+
+        let request = server.await.unwrap();
+        assert_eq!(request.line, "POST /api/services/light/turn_on");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer token"));
+        assert_eq!(serde_json::from_str::<Value>(&request.body).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn an_invalid_token_is_an_error() {
+        let (base_url, server) = serve_once(401, json!({"message": "Unauthorized"})).await;
+        let client = RestClient::new(&base_url, "wrong");
+
+        match client.get_states().await {
+            Err(HassError::RestError(error)) => {
+                assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED))
+            }
+            other => panic!("expected the unauthorized status, got {:?}", other),
+        }
+        server.await.unwrap();
+    }
+}
@@ -5,8 +5,153 @@
 #[cfg(feature = "use-async-std")]
 pub use async_std::channel::{Receiver, Sender};
 
+#[cfg(feature = "use-async-std")]
+pub(crate) use async_std::task::spawn;
+
+#[cfg(feature = "use-async-std")]
+pub(crate) use async_tungstenite::async_std::connect_async_with_config;
+
+#[cfg(feature = "use-async-std")]
+pub(crate) fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    async_std::channel::bounded(capacity)
+}
+
+#[cfg(feature = "use-async-std")]
+pub(crate) fn receiver_stream<T>(receiver: Receiver<T>) -> impl futures_util::Stream<Item = T> {
+    receiver
+}
+
+#[cfg(feature = "use-async-std")]
+pub(crate) async fn timeout<F: std::future::Future>(
+    duration: std::time::Duration,
+    future: F,
+) -> Option<F::Output> {
+    async_std::future::timeout(duration, future).await.ok()
+}
+
+#[cfg(feature = "use-async-std")]
+pub(crate) use async_std::task::sleep;
+
+#[cfg(feature = "use-async-std")]
+pub(crate) use std::time::Instant;
+
+#[cfg(feature = "use-async-std")]
+pub use async_broadcast::Receiver as BroadcastReceiver;
+
+// the sending side of a broadcast channel, which drops the oldest message when it is full,
+// the inactive receiver keeps the channel open while nobody subscribed
+#[cfg(feature = "use-async-std")]
+#[derive(Debug, Clone)]
+pub(crate) struct BroadcastSender<T> {
+    sender: async_broadcast::Sender<T>,
+    _inactive: async_broadcast::InactiveReceiver<T>,
+}
+
+#[cfg(feature = "use-async-std")]
+impl<T: Clone> BroadcastSender<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(capacity);
+        sender.set_overflow(true);
+        BroadcastSender {
+            sender,
+            _inactive: receiver.deactivate(),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> BroadcastReceiver<T> {
+        self.sender.new_receiver()
+    }
+
+    // never waits, the message is dropped when there is no receiver
+    pub(crate) fn send(&self, message: T) {
+        let _ = self.sender.try_broadcast(message);
+    }
+}
+
+#[cfg(feature = "use-async-std")]
+pub(crate) async fn spawn_blocking<F, T>(work: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    async_std::task::spawn_blocking(work).await
+}
+
 // ******************************
 // Tokio Channels
 // *****************************
 #[cfg(feature = "use-tokio")]
 pub use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(feature = "use-tokio")]
+pub(crate) use tokio::spawn;
+
+#[cfg(feature = "use-tokio")]
+pub(crate) use async_tungstenite::tokio::connect_async_with_config;
+
+#[cfg(feature = "use-tokio")]
+pub(crate) fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    tokio::sync::mpsc::channel(capacity)
+}
+
+#[cfg(feature = "use-tokio")]
+pub(crate) fn receiver_stream<T>(receiver: Receiver<T>) -> impl futures_util::Stream<Item = T> {
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    })
+}
+
+#[cfg(feature = "use-tokio")]
+pub(crate) async fn timeout<F: std::future::Future>(
+    duration: std::time::Duration,
+    future: F,
+) -> Option<F::Output> {
+    tokio::time::timeout(duration, future).await.ok()
+}
+
+#[cfg(feature = "use-tokio")]
+pub(crate) use tokio::time::sleep;
+
+// the clock of the runtime, which the tests can pause and advance
+#[cfg(feature = "use-tokio")]
+pub(crate) use tokio::time::Instant;
+
+#[cfg(feature = "use-tokio")]
+pub use tokio::sync::broadcast::Receiver as BroadcastReceiver;
+
+// the sending side of a broadcast channel, which drops the oldest message when it is full
+#[cfg(feature = "use-tokio")]
+#[derive(Debug, Clone)]
+pub(crate) struct BroadcastSender<T> {
+    sender: tokio::sync::broadcast::Sender<T>,
+}
+
+#[cfg(feature = "use-tokio")]
+impl<T: Clone> BroadcastSender<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = tokio::sync::broadcast::channel(capacity);
+        BroadcastSender { sender }
+    }
+
+    pub(crate) fn subscribe(&self) -> BroadcastReceiver<T> {
+        self.sender.subscribe()
+    }
+
+    // never waits, the message is dropped when there is no receiver
+    pub(crate) fn send(&self, message: T) {
+        let _ = self.sender.send(message);
+    }
+}
+
+// runs the work on the thread pool for the blocking work, a panic is resumed in the caller
+#[cfg(feature = "use-tokio")]
+pub(crate) async fn spawn_blocking<F, T>(work: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(output) => output,
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
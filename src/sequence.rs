@@ -0,0 +1,70 @@
+//! Sources of the ids used by the client requests
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Provides the ids of the messages sent by the client
+///
+/// The Websocket server requires the ids to be increasing within a session.
+/// A custom source can be passed with `HassClient::with_sequence`, e.g. to get deterministic ids,
+/// or to keep the ids increasing across several connections.
+///
+/// ```
+/// use hass_rs::SequenceSource;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+///
+/// // ids counting by ten, starting at 100
+/// #[derive(Debug)]
+/// struct ByTen(AtomicU64);
+///
+/// impl SequenceSource for ByTen {
+///     fn next_id(&self) -> u64 {
+///         self.0.fetch_add(10, Ordering::Relaxed)
+///     }
+///
+///     fn advance_past(&self, id: u64) {
+///         self.0.fetch_max(id + 10, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let sequence = ByTen(AtomicU64::new(100));
+/// assert_eq!(sequence.next_id(), 100);
+/// assert_eq!(sequence.next_id(), 110);
+///
+/// sequence.advance_past(500);
+/// assert_eq!(sequence.next_id(), 510);
+/// ```
+pub trait SequenceSource: fmt::Debug + Send + Sync {
+    /// Returns the id for the next message, it must not be 0
+    fn next_id(&self) -> u64;
+
+    /// Called when the server rejected `id` as already used, the next ids should be greater
+    fn advance_past(&self, id: u64);
+}
+
+/// The default SequenceSource, counting up from 1
+#[derive(Debug)]
+pub struct AtomicSequence(AtomicU64);
+
+impl AtomicSequence {
+    /// Creates a sequence starting at `first`
+    pub fn starting_at(first: u64) -> Self {
+        AtomicSequence(AtomicU64::new(first))
+    }
+}
+
+impl Default for AtomicSequence {
+    fn default() -> Self {
+        AtomicSequence::starting_at(1)
+    }
+}
+
+impl SequenceSource for AtomicSequence {
+    fn next_id(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn advance_past(&self, id: u64) {
+        self.0.fetch_max(id + 1, Ordering::Relaxed);
+    }
+}
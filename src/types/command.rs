@@ -1,10 +1,32 @@
+use crate::types::{AreaRegistryUpdate, CoreConfigUpdate, EntityRegistryUpdate, Target};
 use async_tungstenite::tungstenite::Message as TungsteniteMessage;
 use serde::Serialize;
 use serde_json::Value;
 
 /// This enum defines the type of commands that the client is allowed to send to the Websocket server
-#[derive(Debug)]
-pub(crate) enum Command {
+///
+/// The commands are public so that other crates can build on top of the client,
+/// e.g. by sending them through `HassClient::command_sink`. Commands not covered by a variant
+/// can be sent as `Raw` JSON envelopes, rather than forking the crate.
+///
+/// New variants are added as Home Assistant grows, hence the enum is non exhaustive.
+/// The payload structs follow the Home Assistant message format and change with it.
+///
+/// ```
+/// use hass_rs::Command;
+/// use serde_json::json;
+///
+/// let cmd = Command::Raw(json!({ "id": 42, "type": "frontend/get_themes" }));
+/// let message = cmd.to_tungstenite_message();
+///
+/// assert_eq!(
+///     message.to_text().unwrap(),
+///     r#"{"id":42,"type":"frontend/get_themes"}"#
+/// );
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Command {
     AuthInit(Auth),
     Ping(Ask),
     SubscribeEvent(Subscribe),
@@ -13,15 +35,90 @@ pub(crate) enum Command {
     GetServices(Ask),
     GetStates(Ask),
     GetPanels(Ask),
+    GetNotifications(Ask),
+    SubscribeNotifications(Ask),
     CallService(CallService),
-    #[allow(dead_code)]
+    UpdateEntity(UpdateEntity),
+    RemoveEntity(AskEntity),
+    GetEntity(AskEntity),
+    CreateArea(CreateArea),
+    UpdateArea(UpdateArea),
+    DeleteArea(AskArea),
+    UpdateDevice(UpdateDevice),
+    GetStatisticsMetadata(AskStatistics),
+    SubscribeLogbook(SubscribeLogbook),
+    ValidateConfig(ValidateConfig),
+    ExecuteScript(ExecuteScript),
+    RenderTemplate(RenderTemplate),
+    UpdateCoreConfig(UpdateCoreConfig),
+    SubscribeTrigger(SubscribeTrigger),
+    SubscribeEntities(SubscribeEntities),
+    HistoryDuringPeriod(HistoryDuringPeriod),
+    Raw(Value),
     Close,
 }
 
 impl Command {
-    /// This function transform a command into a TungsteniteMessage and needs the last
-    /// gateway sequence in order to send it correctly
-    pub(crate) fn to_tungstenite_message(self) -> TungsteniteMessage {
+    /// This function replaces the id of the command, used when the command has to be resent
+    #[cfg(feature = "client")]
+    pub(crate) fn set_id(&mut self, id: u64) {
+        match self {
+            Self::Ping(ask)
+            | Self::GetConfig(ask)
+            | Self::GetServices(ask)
+            | Self::GetStates(ask)
+            | Self::GetPanels(ask)
+            | Self::GetNotifications(ask)
+            | Self::SubscribeNotifications(ask) => ask.id = Some(id),
+            Self::SubscribeEvent(subscribe) => subscribe.id = Some(id),
+            Self::Unsubscribe(unsubscribe) => unsubscribe.id = Some(id),
+            Self::CallService(callservice) => callservice.id = Some(id),
+            Self::UpdateEntity(updateentity) => updateentity.id = Some(id),
+            Self::RemoveEntity(removeentity) => removeentity.id = Some(id),
+            Self::GetEntity(getentity) => getentity.id = Some(id),
+            Self::CreateArea(createarea) => createarea.id = Some(id),
+            Self::UpdateArea(updatearea) => updatearea.id = Some(id),
+            Self::DeleteArea(deletearea) => deletearea.id = Some(id),
+            Self::UpdateDevice(updatedevice) => updatedevice.id = Some(id),
+            Self::GetStatisticsMetadata(askstatistics) => askstatistics.id = Some(id),
+            Self::SubscribeLogbook(subscribelogbook) => subscribelogbook.id = Some(id),
+            Self::ValidateConfig(validateconfig) => validateconfig.id = Some(id),
+            Self::ExecuteScript(executescript) => executescript.id = Some(id),
+            Self::RenderTemplate(rendertemplate) => rendertemplate.id = Some(id),
+            Self::UpdateCoreConfig(updatecoreconfig) => updatecoreconfig.id = Some(id),
+            Self::SubscribeTrigger(subscribetrigger) => subscribetrigger.id = Some(id),
+            Self::SubscribeEntities(subscribeentities) => subscribeentities.id = Some(id),
+            Self::HistoryDuringPeriod(historyduringperiod) => historyduringperiod.id = Some(id),
+            Self::Raw(value) => {
+                if let Some(raw) = value.as_object_mut() {
+                    raw.insert("id".to_owned(), id.into());
+                }
+            }
+            Self::AuthInit(_) | Self::Close => {}
+        }
+    }
+
+    /// Returns true for the commands changing something in Home Assistant, e.g. calling a service
+    ///
+    /// The `Raw` commands are not known, they are considered as reads.
+    #[cfg(feature = "client")]
+    pub(crate) fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Self::CallService(_)
+                | Self::UpdateEntity(_)
+                | Self::RemoveEntity(_)
+                | Self::CreateArea(_)
+                | Self::UpdateArea(_)
+                | Self::DeleteArea(_)
+                | Self::UpdateDevice(_)
+                | Self::ExecuteScript(_)
+                | Self::UpdateCoreConfig(_)
+        )
+    }
+
+    /// This function transform a command into a TungsteniteMessage, ready to be sent to the Websocket
+    pub fn to_tungstenite_message(self) -> TungsteniteMessage {
         match self {
             Self::AuthInit(auth) => {
                 let cmd_str = serde_json::to_string(&auth).unwrap();
@@ -55,56 +152,292 @@ impl Command {
                 let cmd_str = serde_json::to_string(&getpanels).unwrap();
                 TungsteniteMessage::Text(cmd_str)
             }
+            Self::GetNotifications(getnotifications) => {
+                let cmd_str = serde_json::to_string(&getnotifications).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::SubscribeNotifications(subscribenotifications) => {
+                let cmd_str = serde_json::to_string(&subscribenotifications).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
             Self::CallService(callservice) => {
                 let cmd_str = serde_json::to_string(&callservice).unwrap();
                 TungsteniteMessage::Text(cmd_str)
             }
-            Self::Close => todo!(),
+            Self::UpdateEntity(updateentity) => {
+                let cmd_str = serde_json::to_string(&updateentity).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::RemoveEntity(removeentity) => {
+                let cmd_str = serde_json::to_string(&removeentity).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::GetEntity(getentity) => {
+                let cmd_str = serde_json::to_string(&getentity).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::CreateArea(createarea) => {
+                let cmd_str = serde_json::to_string(&createarea).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::UpdateArea(updatearea) => {
+                let cmd_str = serde_json::to_string(&updatearea).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::DeleteArea(deletearea) => {
+                let cmd_str = serde_json::to_string(&deletearea).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::UpdateDevice(updatedevice) => {
+                let cmd_str = serde_json::to_string(&updatedevice).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::GetStatisticsMetadata(askstatistics) => {
+                let cmd_str = serde_json::to_string(&askstatistics).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::SubscribeLogbook(subscribelogbook) => {
+                let cmd_str = serde_json::to_string(&subscribelogbook).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::ValidateConfig(validateconfig) => {
+                let cmd_str = serde_json::to_string(&validateconfig).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::ExecuteScript(executescript) => {
+                let cmd_str = serde_json::to_string(&executescript).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::RenderTemplate(rendertemplate) => {
+                let cmd_str = serde_json::to_string(&rendertemplate).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::UpdateCoreConfig(updatecoreconfig) => {
+                let cmd_str = serde_json::to_string(&updatecoreconfig).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::SubscribeTrigger(subscribetrigger) => {
+                let cmd_str = serde_json::to_string(&subscribetrigger).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::SubscribeEntities(subscribeentities) => {
+                let cmd_str = serde_json::to_string(&subscribeentities).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::HistoryDuringPeriod(historyduringperiod) => {
+                let cmd_str = serde_json::to_string(&historyduringperiod).unwrap();
+                TungsteniteMessage::Text(cmd_str)
+            }
+            Self::Raw(value) => TungsteniteMessage::Text(value.to_string()),
+            Self::Close => TungsteniteMessage::Close(None),
         }
     }
 }
 
-//used to authenticate the session
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct Auth {
+/// Used to authenticate the session
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Auth {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub access_token: String,
+}
+
+/// Used to fetch from server
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Ask {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+}
+
+/// Used for Event subscription
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Subscribe {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<String>,
+}
+
+/// Used for Event Unsubscribe
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Unsubscribe {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub subscription: u64,
+}
+
+/// Used to call a service
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CallService {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub domain: String,
+    pub service: String,
+    pub service_data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+}
+
+/// Used to update an entity registry entry
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct UpdateEntity {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub entity_id: String,
+    #[serde(flatten)]
+    pub changes: EntityRegistryUpdate,
+}
+
+/// Used to address a single entity registry entry
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AskEntity {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub entity_id: String,
+}
+
+/// Used to create an area
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CreateArea {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub name: String,
+    pub aliases: Vec<String>,
+}
+
+/// Used to update an area
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct UpdateArea {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub area_id: String,
+    #[serde(flatten)]
+    pub changes: AreaRegistryUpdate,
+}
+
+/// Used to address a single area
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AskArea {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub area_id: String,
+}
+
+/// Used to update the area of a device
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct UpdateDevice {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub device_id: String,
+    pub area_id: Option<String>,
+}
+
+/// Used to fetch the metadata of the recorded statistics
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AskStatistics {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statistic_ids: Option<Vec<String>>,
+}
+
+/// Used to subscribe to the logbook event stream
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SubscribeLogbook {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub start_time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_ids: Option<Vec<String>>,
+}
+
+/// Used to validate the triggers, conditions and actions of an automation
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ValidateConfig {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Value>,
+}
+
+/// Used to execute a script sequence
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExecuteScript {
+    pub id: Option<u64>,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub sequence: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+}
+
+/// Used to subscribe to the rendering of a template
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RenderTemplate {
+    pub id: Option<u64>,
     #[serde(rename = "type")]
-    pub(crate) msg_type: String,
-    pub(crate) access_token: String,
+    pub msg_type: String,
+    pub template: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+    pub report_errors: bool,
 }
 
-//used to fetch from server
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct Ask {
-    pub(crate) id: Option<u64>,
+/// Used to update the core configuration
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct UpdateCoreConfig {
+    pub id: Option<u64>,
     #[serde(rename = "type")]
-    pub(crate) msg_type: String,
+    pub msg_type: String,
+    #[serde(flatten)]
+    pub changes: CoreConfigUpdate,
 }
 
-//used for Event subscribtion
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct Subscribe {
-    pub(crate) id: Option<u64>,
+/// Used to subscribe to an automation trigger
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SubscribeTrigger {
+    pub id: Option<u64>,
     #[serde(rename = "type")]
-    pub(crate) msg_type: String,
-    pub(crate) event_type: String,
+    pub msg_type: String,
+    pub trigger: Value,
 }
 
-//used for Event Unsubscribe
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct Unsubscribe {
-    pub(crate) id: Option<u64>,
+/// Used to subscribe to the states of the entities, in the compact format
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SubscribeEntities {
+    pub id: Option<u64>,
     #[serde(rename = "type")]
-    pub(crate) msg_type: String,
-    pub(crate) subscription: u64,
+    pub msg_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_ids: Option<Vec<String>>,
 }
 
-//used to call a service
-#[derive(Debug, Serialize, PartialEq)]
-pub(crate) struct CallService {
-    pub(crate) id: Option<u64>,
+/// Used to fetch the history of entities over a period
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct HistoryDuringPeriod {
+    pub id: Option<u64>,
     #[serde(rename = "type")]
-    pub(crate) msg_type: String,
-    pub(crate) domain: String,
-    pub(crate) service: String,
-    pub(crate) service_data: Option<Value>,
+    pub msg_type: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub entity_ids: Vec<String>,
+    pub minimal_response: bool,
+    pub no_attributes: bool,
 }
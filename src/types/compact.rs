@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// This object represents the state of an entity in the compact format of `subscribe_entities`
+///
+/// The timestamps are seconds since the epoch, `last_updated` is only set when it differs from `last_changed`.
+/// The `context` is either its id alone, or the whole context.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CompactState {
+    #[serde(rename = "s")]
+    pub state: String,
+    #[serde(rename = "a", default)]
+    pub attributes: Map<String, Value>,
+    #[serde(rename = "c", default)]
+    pub context: Value,
+    #[serde(rename = "lc", default)]
+    pub last_changed: Option<f64>,
+    #[serde(rename = "lu", default)]
+    pub last_updated: Option<f64>,
+}
+
+/// This is part of CompactChange, the parts of the state which were set, the attributes are merged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CompactStateDiff {
+    #[serde(rename = "s", default)]
+    pub state: Option<String>,
+    #[serde(rename = "a", default)]
+    pub attributes: Option<Map<String, Value>>,
+    #[serde(rename = "c", default)]
+    pub context: Option<Value>,
+    #[serde(rename = "lc", default)]
+    pub last_changed: Option<f64>,
+    #[serde(rename = "lu", default)]
+    pub last_updated: Option<f64>,
+}
+
+/// This is part of CompactChange, the names of the attributes which were removed
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CompactRemovals {
+    #[serde(rename = "a", default)]
+    pub attributes: Vec<String>,
+}
+
+/// This object represents the change of an entity in the compact format of `subscribe_entities`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CompactChange {
+    #[serde(rename = "+", default)]
+    pub additions: Option<CompactStateDiff>,
+    #[serde(rename = "-", default)]
+    pub removals: Option<CompactRemovals>,
+}
+
+/// This object represents a message of `subscribe_entities`, by entity id
+///
+/// The first message adds all the entities, the next ones hold the changes since the previous one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct EntitiesDiff {
+    #[serde(rename = "a", default)]
+    pub added: HashMap<String, CompactState>,
+    #[serde(rename = "c", default)]
+    pub changed: HashMap<String, CompactChange>,
+    #[serde(rename = "r", default)]
+    pub removed: Vec<String>,
+}
+
+/// An update of the stream returned by `HassClient::subscribe_entities`
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntitiesUpdate {
+    /// The connection was reopened and the subscription renewed: the states built so far are outdated,
+    /// e.g. an entity may have been removed meanwhile, and the next diff adds all the current states
+    Reset,
+    /// A message of the subscription
+    Diff(EntitiesDiff),
+}
+
+impl EntitiesUpdate {
+    /// Applies the update to the `states`, by entity id
+    ///
+    /// ```
+    /// use hass_rs::{EntitiesDiff, EntitiesUpdate};
+    /// use std::collections::HashMap;
+    ///
+    /// let added: EntitiesDiff = serde_json::from_str(
+    ///     r#"{"a": {"light.kitchen": {"s": "on", "a": {"brightness": 255}, "c": "01HPRMZAWNXKVVPSP11QFJ53HB", "lc": 1704067200.0}}}"#,
+    /// )
+    /// .unwrap();
+    /// let changed: EntitiesDiff = serde_json::from_str(
+    ///     r#"{"c": {"light.kitchen": {"+": {"s": "off", "lc": 1704067260.0}, "-": {"a": ["brightness"]}}}}"#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut states = HashMap::new();
+    /// EntitiesUpdate::Diff(added).apply(&mut states);
+    /// EntitiesUpdate::Diff(changed).apply(&mut states);
+    /// assert_eq!(states["light.kitchen"].state, "off");
+    /// assert!(states["light.kitchen"].attributes.is_empty());
+    ///
+    /// EntitiesUpdate::Reset.apply(&mut states);
+    /// assert!(states.is_empty());
+    /// ```
+    pub fn apply(&self, states: &mut HashMap<String, CompactState>) {
+        let diff = match self {
+            EntitiesUpdate::Reset => {
+                states.clear();
+                return;
+            }
+            EntitiesUpdate::Diff(diff) => diff,
+        };
+
+        for (entity_id, state) in &diff.added {
+            states.insert(entity_id.clone(), state.clone());
+        }
+        for (entity_id, change) in &diff.changed {
+            let Some(state) = states.get_mut(entity_id) else {
+                continue;
+            };
+            if let Some(additions) = &change.additions {
+                if let Some(value) = &additions.state {
+                    state.state.clone_from(value);
+                }
+                if let Some(attributes) = &additions.attributes {
+                    state.attributes.extend(attributes.clone());
+                }
+                if let Some(context) = &additions.context {
+                    state.context = context.clone();
+                }
+                // a change of the state updates both timestamps
+                if additions.last_changed.is_some() {
+                    state.last_changed = additions.last_changed;
+                    state.last_updated = None;
+                } else if additions.last_updated.is_some() {
+                    state.last_updated = additions.last_updated;
+                }
+            }
+            if let Some(removals) = &change.removals {
+                for attribute in &removals.attributes {
+                    state.attributes.remove(attribute);
+                }
+            }
+        }
+        for entity_id in &diff.removed {
+            states.remove(entity_id);
+        }
+    }
+}
@@ -1,14 +1,32 @@
+use crate::types::{DisabledBy, HassEntity};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt;
 
 /// This object represents the Home Assistant Config
 ///
 /// This will get a dump of the current config in Home Assistant.
 /// [Fetch Config](https://developers.home-assistant.io/docs/api/websocket/#fetching-config)
+///
+/// ```
+/// use hass_rs::HassConfig;
+///
+/// let config: HassConfig = serde_json::from_str(r#"{
+///     "latitude": 52.3731339, "longitude": 4.8924534, "elevation": 2,
+///     "unit_system": {"length": "km", "mass": "g", "pressure": "Pa", "temperature": "°C", "volume": "L"},
+///     "location_name": "Home", "time_zone": "Europe/Amsterdam", "components": [], "config_dir": "/config",
+///     "whitelist_external_dirs": [], "version": "2024.1.0", "config_source": "storage",
+///     "external_url": null, "internal_url": null
+/// }"#).unwrap();
+///
+/// assert_eq!(config.latitude, 52.3731339);
+/// assert_eq!(config.longitude, 4.8924534);
+/// ```
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct HassConfig {
-    pub latitude: f32,
-    pub longitude: f32,
+    /// In degrees, kept as f64: a f32 would move the location by up to a meter
+    pub latitude: f64,
+    pub longitude: f64,
     pub elevation: u32,
     pub unit_system: UnitSystem,
     pub location_name: String,
@@ -18,9 +36,49 @@ pub struct HassConfig {
     pub whitelist_external_dirs: Vec<String>,
     pub version: String,
     pub config_source: String,
+    /// Replaced by `recovery_mode` on the recent versions, false when missing
+    #[serde(default)]
     pub safe_mode: bool,
     pub external_url: Option<String>,
     pub internal_url: Option<String>,
+    /// The run state of the instance, e.g. "RUNNING" once fully started (missing on older versions)
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Set when Home Assistant started in recovery mode, after a configuration error (missing on older versions)
+    #[serde(default)]
+    pub recovery_mode: Option<bool>,
+}
+
+/// This object represents the changes to apply to the core configuration
+///
+/// Only the fields which are set are sent to Home Assistant, e.g. to follow the location of a mobile setup.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct CoreConfigUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_name: Option<String>,
+    /// "metric" or "us_customary"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub internal_url: Option<String>,
 }
 
 /// This is part of HassConfig
@@ -33,6 +91,69 @@ pub struct UnitSystem {
     pub volume: String,
 }
 
+impl HassConfig {
+    /// Converts a temperature expressed in the unit system of this Home Assistant instance to the `to` unit
+    ///
+    /// Useful to normalize the temperatures when aggregating sensors from installs with mixed units.
+    pub fn normalize_temperature(&self, value: f64, to: &str) -> Option<f64> {
+        convert_temperature(value, &self.unit_system.temperature, to)
+    }
+
+    /// Returns true if the integration is loaded
+    ///
+    /// The platforms are listed as `domain.platform`, e.g. "sensor.mqtt": `name` matches them
+    /// either in full or by their domain, so "sensor" matches "sensor.mqtt".
+    ///
+    /// For many lookups, build the set once with `component_set`.
+    pub fn has_component(&self, name: &str) -> bool {
+        self.components
+            .iter()
+            .any(|component| component == name || component_domain(component) == name)
+    }
+
+    /// Returns the loaded components, with the domains of the platforms also listed on their own
+    ///
+    /// ```
+    /// # use hass_rs::HassConfig;
+    /// # fn example(config: &HassConfig) {
+    /// let components = config.component_set();
+    /// if components.contains("recorder") && components.contains("mqtt") {
+    ///     // ...
+    /// }
+    /// # }
+    /// ```
+    pub fn component_set(&self) -> HashSet<&str> {
+        self.components
+            .iter()
+            .flat_map(|component| [component.as_str(), component_domain(component)])
+            .collect()
+    }
+}
+
+// the domain of a component, `domain` or `domain.platform`
+fn component_domain(component: &str) -> &str {
+    component.split('.').next().unwrap_or(component)
+}
+
+/// Converts a temperature between the Home Assistant temperature units: "°C", "°F" and "K"
+///
+/// Returns `None` when one of the units is unknown.
+pub fn convert_temperature(value: f64, from: &str, to: &str) -> Option<f64> {
+    let celsius = match from {
+        "°C" => value,
+        "°F" => (value - 32.0) * 5.0 / 9.0,
+        "K" => value - 273.15,
+        _ => return None,
+    };
+
+    match to {
+        "°C" => Some(celsius),
+        "°F" => Some(celsius * 9.0 / 5.0 + 32.0),
+        "K" => Some(celsius + 273.15),
+        _ => None,
+    }
+}
+
 impl fmt::Display for HassConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "HassConfig {{\n")?;
@@ -54,6 +175,8 @@ impl fmt::Display for HassConfig {
         write!(f, "  safe_mode: {},\n", self.safe_mode)?;
         write!(f, "  external_url: {:?},\n", self.external_url)?;
         write!(f, "  internal_url: {:?},\n", self.internal_url)?;
+        writeln!(f, "  state: {:?},", self.state)?;
+        writeln!(f, "  recovery_mode: {:?},", self.recovery_mode)?;
         write!(f, "}}")?;
         Ok(())
     }
@@ -75,7 +198,7 @@ impl fmt::Display for UnitSystem {
 /// This object represents a Home Assistant Area
 ///
 /// [Area](https://developers.home-assistant.io/docs/area_registry_index)
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct HassArea {
     #[serde(rename = "area_id")]
     pub id: String,
@@ -84,10 +207,50 @@ pub struct HassArea {
     pub picture: Option<String>,
 }
 
+/// This object represents a Home Assistant Floor, grouping areas
+///
+/// [Floor](https://www.home-assistant.io/docs/organizing/floors/)
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HassFloor {
+    #[serde(rename = "floor_id")]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub icon: Option<String>,
+    pub level: Option<i32>,
+}
+
+/// This object represents a Home Assistant Label, which can be put on areas, devices and entities
+///
+/// [Label](https://www.home-assistant.io/docs/organizing/labels/)
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HassLabel {
+    #[serde(rename = "label_id")]
+    pub id: String,
+    pub name: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// This object represents the changes to apply to a Home Assistant Area
+///
+/// Only the fields which are set are sent to Home Assistant, `picture: Some(None)` removes the picture.
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct AreaRegistryUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<Option<String>>,
+}
+
 /// This object represents a Home Assistant Device
 ///
 /// [Device](https://developers.home-assistant.io/docs/device_registry_index)
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct HassDevice {
     pub id: String,
     pub name: String,
@@ -106,3 +269,105 @@ pub struct HassDevice {
     pub sw_version: Option<String>,
     pub via_device_id: Option<String>,
 }
+
+impl HassDevice {
+    /// Returns who disabled the device, parsed from `disabled_by`, None while it is enabled
+    pub fn disabled_by_kind(&self) -> Option<DisabledBy> {
+        self.disabled_by.as_deref().map(DisabledBy::from)
+    }
+}
+
+/// This object represents the content of all the registries at a point in time
+///
+/// It can be persisted and compared with a later snapshot, e.g. to find the new devices
+/// or the entities which moved to another area.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RegistrySnapshot {
+    /// When the snapshot was taken, in seconds since the Unix epoch
+    pub taken_at: f64,
+    pub areas: Vec<HassArea>,
+    pub floors: Vec<HassFloor>,
+    pub devices: Vec<HassDevice>,
+    pub entities: Vec<HassEntity>,
+    pub labels: Vec<HassLabel>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the values are compared with a tolerance, the conversions are not exact in binary
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("the units are known");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn celsius_and_fahrenheit() {
+        assert_close(convert_temperature(100.0, "°C", "°F"), 212.0);
+        assert_close(convert_temperature(-40.0, "°C", "°F"), -40.0);
+        assert_close(convert_temperature(68.0, "°F", "°C"), 20.0);
+        assert_close(convert_temperature(32.0, "°F", "°C"), 0.0);
+        assert_close(convert_temperature(21.5, "°C", "°C"), 21.5);
+        assert_close(convert_temperature(0.0, "°C", "K"), 273.15);
+        assert_close(convert_temperature(0.0, "K", "°F"), -459.67);
+    }
+
+    #[test]
+    fn unknown_units() {
+        assert_eq!(convert_temperature(20.0, "C", "°F"), None);
+        assert_eq!(convert_temperature(20.0, "°C", "celsius"), None);
+    }
+
+    #[test]
+    fn normalize_from_the_unit_system() {
+        let config: HassConfig = serde_json::from_value(serde_json::json!({
+            "latitude": 40.7, "longitude": -74.0, "elevation": 10,
+            "unit_system": {"length": "mi", "mass": "lb", "pressure": "psi", "temperature": "°F", "volume": "gal"},
+            "location_name": "Home", "time_zone": "America/New_York", "components": [],
+            "config_dir": "/config", "whitelist_external_dirs": [], "version": "2024.1.0",
+            "config_source": "storage", "external_url": null, "internal_url": null
+        }))
+        .unwrap();
+
+        assert_close(config.normalize_temperature(212.0, "°C"), 100.0);
+        assert_close(config.normalize_temperature(70.0, "°F"), 70.0);
+    }
+
+    #[test]
+    fn areas_and_devices_are_deduplicated_in_a_set() {
+        let area = |id: &str| HassArea {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            aliases: vec!["downstairs".to_owned()],
+            picture: None,
+        };
+        let areas: HashSet<HassArea> = [area("kitchen"), area("kitchen"), area("hall")]
+            .into_iter()
+            .collect();
+        assert_eq!(areas.len(), 2);
+        assert!(areas.contains(&area("hall")));
+
+        let device = |id: &str| -> HassDevice {
+            serde_json::from_value(serde_json::json!({
+                "id": id, "name": "Hue bridge", "area_id": "kitchen", "config_entries": ["hue"],
+                "configuration_url": null, "connections": [["mac", "00:17:88:01:02:03"]],
+                "disabled_by": null, "entry_type": null, "hw_version": null,
+                "identifiers": [["hue", id]], "manufacturer": "Signify", "model": "BSB002",
+                "name_by_user": null, "serial_number": null, "sw_version": "1.60",
+                "via_device_id": null
+            }))
+            .unwrap()
+        };
+        let devices: HashSet<HassDevice> = [device("a1"), device("b2"), device("a1")]
+            .into_iter()
+            .collect();
+        assert_eq!(devices.len(), 2);
+        assert!(devices.contains(&device("b2")));
+    }
+}
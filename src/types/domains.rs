@@ -0,0 +1,371 @@
+use crate::types::{HassEntity, HassEntityState};
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde_json::Value;
+
+/// Typed view of the state of a `binary_sensor` entity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinarySensorState {
+    pub is_on: bool,
+    pub device_class: Option<String>,
+}
+
+/// Typed view of the state of a `cover` entity
+///
+/// The positions go from 0 (closed) to 100 (open), they are only reported by the covers supporting them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverState {
+    pub position: Option<u8>,
+    pub is_open: bool,
+    pub is_closed: bool,
+    pub tilt: Option<u8>,
+}
+
+/// Typed view of the state of a `lock` entity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockState {
+    pub is_locked: bool,
+    pub is_jammed: bool,
+}
+
+/// Typed view of the state of the `sun.sun` entity
+///
+/// The next events are the timestamps reported by Home Assistant, e.g. "2024-03-20T05:52:11.430000+00:00".
+///
+/// ```
+/// use hass_rs::HassEntityState;
+///
+/// let sun: HassEntityState = serde_json::from_str(r#"{
+///     "entity_id": "sun.sun",
+///     "state": "above_horizon",
+///     "attributes": {
+///         "next_dawn": "2024-03-21T05:22:48.601316+00:00",
+///         "next_dusk": "2024-03-20T18:44:03.447906+00:00",
+///         "next_midnight": "2024-03-21T00:03:24+00:00",
+///         "next_noon": "2024-03-21T12:03:32+00:00",
+///         "next_rising": "2024-03-21T05:52:11.430000+00:00",
+///         "next_setting": "2024-03-20T18:14:43.542375+00:00",
+///         "elevation": 41.12,
+///         "azimuth": 174.28,
+///         "rising": true,
+///         "friendly_name": "Sun"
+///     },
+///     "last_changed": "2024-03-20T05:53:01.231480+00:00",
+///     "last_updated": "2024-03-20T11:52:30.012045+00:00",
+///     "context": { "id": "01HSDHB6DW1ZE3V6GWPWQ8X5J3", "parent_id": null, "user_id": null }
+/// }"#).unwrap();
+///
+/// let sun = sun.as_sun().unwrap();
+/// assert!(sun.above_horizon);
+/// assert!(sun.rising);
+/// assert_eq!(sun.elevation, 41.12);
+/// assert_eq!(sun.next_setting, "2024-03-20T18:14:43.542375+00:00");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SunState {
+    pub above_horizon: bool,
+    pub next_rising: String,
+    pub next_setting: String,
+    pub next_dawn: String,
+    pub next_dusk: String,
+    /// Angle of the sun above the horizon, in degrees, negative below it
+    pub elevation: f64,
+    /// Angle of the sun from the north, in degrees
+    pub azimuth: f64,
+    /// True from midnight to noon
+    pub rising: bool,
+}
+
+/// Typed view of the state of a `sensor` entity
+///
+/// ```
+/// use hass_rs::HassEntityState;
+/// use serde_json::json;
+///
+/// let sensor = HassEntityState {
+///     entity_id: "sensor.living_room_temperature".to_owned(),
+///     last_changed: "2024-01-01T00:00:00+00:00".to_owned(),
+///     state: "21.56".to_owned(),
+///     attributes: json!({
+///         "unit_of_measurement": "°C",
+///         "device_class": "temperature",
+///         "state_class": "measurement"
+///     }),
+///     last_updated: "2024-01-01T00:00:00+00:00".to_owned(),
+///     context: None,
+/// };
+///
+/// let reading = sensor.as_sensor().unwrap();
+/// assert_eq!(reading.as_quantity(), Some((21.56, "°C")));
+/// assert_eq!(reading.device_class(), Some("temperature"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorReading<'a> {
+    state: &'a HassEntityState,
+}
+
+impl<'a> SensorReading<'a> {
+    /// Returns the numeric value, None for a non numeric sensor, or when it is `unavailable` or `unknown`
+    pub fn value(&self) -> Option<f64> {
+        if !self.state.is_available() {
+            return None;
+        }
+        self.state.state.parse().ok()
+    }
+
+    pub fn unit(&self) -> Option<&'a str> {
+        self.state.attributes.get("unit_of_measurement")?.as_str()
+    }
+
+    /// Returns what the sensor measures, e.g. "temperature" or "energy"
+    pub fn device_class(&self) -> Option<&'a str> {
+        self.state.attributes.get("device_class")?.as_str()
+    }
+
+    /// Returns "measurement", "total" or "total_increasing", for the sensors with long-term statistics
+    pub fn state_class(&self) -> Option<&'a str> {
+        self.state.attributes.get("state_class")?.as_str()
+    }
+
+    /// Returns the value with its unit, None when either is missing
+    pub fn as_quantity(&self) -> Option<(f64, &'a str)> {
+        Some((self.value()?, self.unit()?))
+    }
+
+    /// Returns the time of a `timestamp` sensor, e.g. the next bus arrival, None for the other device classes
+    ///
+    /// The offset sent by Home Assistant is kept, it is usually UTC.
+    ///
+    /// ```
+    /// use chrono::{FixedOffset, NaiveDate, TimeZone};
+    /// use hass_rs::HassEntityState;
+    /// use serde_json::json;
+    ///
+    /// let sensor = |state: &str, device_class: &str| HassEntityState {
+    ///     entity_id: "sensor.example".to_owned(),
+    ///     last_changed: "2024-01-01T00:00:00+00:00".to_owned(),
+    ///     state: state.to_owned(),
+    ///     attributes: json!({ "device_class": device_class }),
+    ///     last_updated: "2024-01-01T00:00:00+00:00".to_owned(),
+    ///     context: None,
+    /// };
+    ///
+    /// let boot = sensor("2024-03-20T18:14:43+01:00", "timestamp");
+    /// let expected = FixedOffset::east_opt(3600)
+    ///     .unwrap()
+    ///     .with_ymd_and_hms(2024, 3, 20, 18, 14, 43)
+    ///     .unwrap();
+    /// assert_eq!(boot.as_sensor().unwrap().as_datetime(), Some(expected));
+    ///
+    /// let holiday = sensor("2024-12-25", "date");
+    /// assert_eq!(holiday.as_sensor().unwrap().as_date(), NaiveDate::from_ymd_opt(2024, 12, 25));
+    /// ```
+    #[cfg(feature = "chrono")]
+    pub fn as_datetime(&self) -> Option<DateTime<FixedOffset>> {
+        if self.device_class() != Some("timestamp") || !self.state.is_available() {
+            return None;
+        }
+        DateTime::parse_from_rfc3339(&self.state.state).ok()
+    }
+
+    /// Returns the day of a `date` sensor, None for the other device classes
+    #[cfg(feature = "chrono")]
+    pub fn as_date(&self) -> Option<NaiveDate> {
+        if self.device_class() != Some("date") || !self.state.is_available() {
+            return None;
+        }
+        self.state.state.parse().ok()
+    }
+
+    /// Formats the value with its unit, rounded to the precision suggested in the registry `entity` options
+    ///
+    /// Without a suggested precision, the value is formatted as it is.
+    pub fn display(&self, entity: &HassEntity) -> Option<String> {
+        let value = self.value()?;
+        let value = match entity.suggested_display_precision() {
+            Some(precision) => format!("{:.*}", usize::from(precision), value),
+            None => value.to_string(),
+        };
+        match self.unit() {
+            Some(unit) => Some(format!("{} {}", value, unit)),
+            None => Some(value),
+        }
+    }
+}
+
+impl HassEntityState {
+    /// Returns the domain of the entity, e.g. `light` for `light.kitchen`
+    pub fn domain(&self) -> &str {
+        self.entity_id
+            .split_once('.')
+            .map_or(self.entity_id.as_str(), |(domain, _)| domain)
+    }
+
+    /// Reads the state of a `binary_sensor` entity, None for the other domains
+    pub fn as_binary_sensor(&self) -> Option<BinarySensorState> {
+        if self.domain() != "binary_sensor" {
+            return None;
+        }
+
+        Some(BinarySensorState {
+            is_on: self.state == "on",
+            device_class: self.string_attribute("device_class"),
+        })
+    }
+
+    /// Reads the state of a `cover` entity, None for the other domains
+    pub fn as_cover(&self) -> Option<CoverState> {
+        if self.domain() != "cover" {
+            return None;
+        }
+
+        Some(CoverState {
+            position: self.percent_attribute("current_position"),
+            is_open: self.state == "open",
+            is_closed: self.state == "closed",
+            tilt: self.percent_attribute("current_tilt_position"),
+        })
+    }
+
+    /// Reads the state of the `sun` entity, None for the other domains or when an attribute is missing
+    pub fn as_sun(&self) -> Option<SunState> {
+        if self.domain() != "sun" {
+            return None;
+        }
+
+        let float = |name| self.attributes.get(name).and_then(Value::as_f64);
+        Some(SunState {
+            above_horizon: self.state == "above_horizon",
+            next_rising: self.string_attribute("next_rising")?,
+            next_setting: self.string_attribute("next_setting")?,
+            next_dawn: self.string_attribute("next_dawn")?,
+            next_dusk: self.string_attribute("next_dusk")?,
+            elevation: float("elevation")?,
+            azimuth: float("azimuth")?,
+            rising: self.attributes.get("rising").and_then(Value::as_bool)?,
+        })
+    }
+
+    /// Reads the state of a `sensor` entity, None for the other domains
+    pub fn as_sensor(&self) -> Option<SensorReading<'_>> {
+        if self.domain() != "sensor" {
+            return None;
+        }
+
+        Some(SensorReading { state: self })
+    }
+
+    /// Reads the state of a `lock` entity, None for the other domains
+    pub fn as_lock(&self) -> Option<LockState> {
+        if self.domain() != "lock" {
+            return None;
+        }
+
+        Some(LockState {
+            is_locked: self.state == "locked",
+            is_jammed: self.state == "jammed",
+        })
+    }
+
+    fn string_attribute(&self, name: &str) -> Option<String> {
+        self.attributes
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+    }
+
+    // the positions are reported as integers between 0 and 100
+    fn percent_attribute(&self, name: &str) -> Option<u8> {
+        self.attributes
+            .get(name)
+            .and_then(Value::as_u64)
+            .and_then(|value| u8::try_from(value.min(100)).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sensor(state: &str, device_class: &str) -> HassEntityState {
+        HassEntityState {
+            entity_id: "sensor.example".to_owned(),
+            last_changed: "2024-01-01T00:00:00+00:00".to_owned(),
+            state: state.to_owned(),
+            attributes: json!({ "device_class": device_class, "unit_of_measurement": "°C" }),
+            last_updated: "2024-01-01T00:00:00+00:00".to_owned(),
+            context: None,
+        }
+    }
+
+    #[test]
+    fn numeric_sensor() {
+        let temperature = sensor("21.5", "temperature");
+        let reading = temperature.as_sensor().unwrap();
+        assert_eq!(reading.as_quantity(), Some((21.5, "°C")));
+        #[cfg(feature = "chrono")]
+        {
+            assert_eq!(reading.as_datetime(), None);
+            assert_eq!(reading.as_date(), None);
+        }
+
+        assert_eq!(
+            sensor("unavailable", "temperature")
+                .as_sensor()
+                .unwrap()
+                .value(),
+            None
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn timestamp_sensor() {
+        let boot = sensor("2024-03-20T18:14:43.542375+00:00", "timestamp");
+        let time = boot.as_sensor().unwrap().as_datetime().unwrap();
+        assert_eq!(time.timestamp(), 1_710_958_483);
+        assert_eq!(time.timestamp_subsec_micros(), 542_375);
+        assert_eq!(time.offset().local_minus_utc(), 0);
+
+        let shifted = sensor("2024-03-20T18:14:43-05:30", "timestamp");
+        let time = shifted.as_sensor().unwrap().as_datetime().unwrap();
+        assert_eq!(time.timestamp(), 1_710_958_483 + 5 * 3600 + 30 * 60);
+
+        assert_eq!(
+            sensor("unknown", "timestamp")
+                .as_sensor()
+                .unwrap()
+                .as_datetime(),
+            None
+        );
+        assert_eq!(
+            sensor("2024-03-20", "timestamp")
+                .as_sensor()
+                .unwrap()
+                .as_datetime(),
+            None
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn date_sensor() {
+        let holiday = sensor("2024-02-29", "date");
+        assert_eq!(
+            holiday.as_sensor().unwrap().as_date(),
+            NaiveDate::from_ymd_opt(2024, 2, 29)
+        );
+        assert_eq!(
+            sensor("2023-02-29", "date").as_sensor().unwrap().as_date(),
+            None
+        );
+        assert_eq!(
+            sensor("2024-02-29", "timestamp")
+                .as_sensor()
+                .unwrap()
+                .as_date(),
+            None
+        );
+    }
+}
@@ -1,12 +1,17 @@
+use crate::types::HassDevice;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// General construct used by HassEntity and HassEvent
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Context {
     pub id: String,
+    #[serde(default)]
     pub parent_id: Option<String>,
+    /// The user who initiated the change, `None` when it was done by Home Assistant itself (e.g. an automation)
+    #[serde(default)]
     pub user_id: Option<String>,
 }
 
@@ -27,7 +32,7 @@ impl fmt::Display for HassEntityState {
 /// This object represents a Home Assistant Entity
 ///
 /// [Entity](https://developers.home-assistant.io/docs/entity_registry_index)
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct HassEntity {
     pub area_id: Option<String>,
     pub config_entry_id: Option<String>,
@@ -47,6 +52,157 @@ pub struct HassEntity {
     pub unique_id: String,
 }
 
+// options holds json values, which are not hashable, so they are left out of the hash
+impl Hash for HassEntity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.area_id.hash(state);
+        self.config_entry_id.hash(state);
+        self.device_id.hash(state);
+        self.disabled_by.hash(state);
+        self.entity_category.hash(state);
+        self.entity_id.hash(state);
+        self.has_entity_name.hash(state);
+        self.hidden_by.hash(state);
+        self.icon.hash(state);
+        self.id.hash(state);
+        self.name.hash(state);
+        self.original_name.hash(state);
+        self.platform.hash(state);
+        self.translation_key.hash(state);
+        self.unique_id.hash(state);
+    }
+}
+
+impl HassEntity {
+    /// Returns the options of the entity's own domain, e.g. `options.sensor` for a sensor
+    pub fn domain_options(&self) -> Option<&serde_json::map::Map<String, serde_json::Value>> {
+        let (domain, _) = self.entity_id.split_once('.')?;
+        self.options.get(domain)?.as_object()
+    }
+
+    /// Returns the number of decimals suggested by the integration to display the sensor's value
+    pub fn suggested_display_precision(&self) -> Option<u8> {
+        self.domain_options()?
+            .get("suggested_display_precision")?
+            .as_u64()
+            .and_then(|precision| u8::try_from(precision).ok())
+    }
+
+    /// Returns the unit the user chose to display the sensor's value in, instead of its native unit
+    pub fn unit_of_measurement_override(&self) -> Option<&str> {
+        self.domain_options()?.get("unit_of_measurement")?.as_str()
+    }
+
+    /// Returns the area of the entity, its own one or else the one of its device, like Home Assistant resolves it
+    ///
+    /// ```
+    /// use hass_rs::{HassDevice, HassEntity};
+    ///
+    /// let device: HassDevice = serde_json::from_value(serde_json::json!({
+    ///     "id": "tv", "name": "TV", "area_id": "living_room", "config_entries": [],
+    ///     "configuration_url": null, "connections": [], "disabled_by": null, "entry_type": null,
+    ///     "hw_version": null, "identifiers": [], "manufacturer": null, "model": null,
+    ///     "name_by_user": null, "serial_number": null, "sw_version": null, "via_device_id": null
+    /// })).unwrap();
+    /// let entity = |entity_id: &str, area_id: Option<&str>| -> HassEntity {
+    ///     serde_json::from_value(serde_json::json!({
+    ///         "area_id": area_id, "config_entry_id": null, "device_id": "tv", "disabled_by": null,
+    ///         "entity_category": null, "entity_id": entity_id, "has_entity_name": true, "hidden_by": null,
+    ///         "icon": null, "id": entity_id, "name": null, "options": {}, "original_name": null,
+    ///         "platform": "cast", "translation_key": null, "unique_id": entity_id
+    ///     })).unwrap()
+    /// };
+    ///
+    /// let devices = [device];
+    /// assert_eq!(entity("media_player.tv", None).area(&devices), Some("living_room"));
+    /// assert_eq!(entity("light.tv_backlight", Some("office")).area(&devices), Some("office"));
+    /// ```
+    pub fn area<'a>(&'a self, devices: &'a [HassDevice]) -> Option<&'a str> {
+        if let Some(area_id) = &self.area_id {
+            return Some(area_id);
+        }
+
+        let device_id = self.device_id.as_deref()?;
+        devices
+            .iter()
+            .find(|device| device.id == device_id)?
+            .area_id
+            .as_deref()
+    }
+
+    /// Returns who disabled the entity, parsed from `disabled_by`, None while it is enabled
+    pub fn disabled_by_kind(&self) -> Option<DisabledBy> {
+        self.disabled_by.as_deref().map(DisabledBy::from)
+    }
+
+    /// Returns who hid the entity, parsed from `hidden_by`, None while it is visible
+    pub fn hidden_by_kind(&self) -> Option<DisabledBy> {
+        self.hidden_by.as_deref().map(DisabledBy::from)
+    }
+}
+
+/// Who disabled or hid a device or an entity
+///
+/// ```
+/// use hass_rs::DisabledBy;
+///
+/// assert_eq!(DisabledBy::from("user"), DisabledBy::User);
+/// assert_eq!(DisabledBy::from("integration"), DisabledBy::Integration);
+/// assert_eq!(DisabledBy::from("config_entry"), DisabledBy::ConfigEntry);
+/// assert_eq!(DisabledBy::from("device"), DisabledBy::Device);
+/// assert_eq!(DisabledBy::from("hass"), DisabledBy::Other("hass".to_owned()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DisabledBy {
+    /// The user, from the UI or the API
+    User,
+    /// The integration, e.g. for the entities which are disabled until they are needed
+    Integration,
+    /// The config entry was disabled, with all its devices and entities
+    ConfigEntry,
+    /// The device of the entity was disabled
+    Device,
+    /// A value unknown to this version of the crate
+    Other(String),
+}
+
+impl From<&str> for DisabledBy {
+    fn from(disabled_by: &str) -> Self {
+        match disabled_by {
+            "user" => DisabledBy::User,
+            "integration" => DisabledBy::Integration,
+            "config_entry" => DisabledBy::ConfigEntry,
+            "device" => DisabledBy::Device,
+            other => DisabledBy::Other(other.to_owned()),
+        }
+    }
+}
+
+/// This object represents the changes to apply to an entity registry entry
+///
+/// Only the fields which are set are sent to Home Assistant.
+/// The clearable fields are double options, `Some(None)` resets the value (e.g. removes the area).
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct EntityRegistryUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub area_id: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_by: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_entity_id: Option<String>,
+}
+
+// this is the result of an entity registry update
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct UpdatedEntity {
+    pub(crate) entity_entry: HassEntity,
+}
+
 /// This object represents a snapshot of a Home Assistant Entity's state
 ///
 /// [Entity](https://developers.home-assistant.io/docs/core/entity/)
@@ -59,3 +215,120 @@ pub struct HassEntityState {
     pub last_updated: String,
     pub context: Option<Context>, //changed
 }
+
+impl HassEntityState {
+    /// Returns false when the state is `unavailable` or `unknown`, e.g. when the device is offline
+    pub fn is_available(&self) -> bool {
+        self.state != "unavailable" && self.state != "unknown"
+    }
+
+    /// Compares this state with a `newer` state of the same entity
+    ///
+    /// The attributes are compared one level deep: a nested object which changed in any way
+    /// is reported as a changed key, without the detail of what changed inside it.
+    ///
+    /// ```
+    /// use hass_rs::HassEntityState;
+    /// use serde_json::json;
+    ///
+    /// let state = |state: &str, attributes| HassEntityState {
+    ///     entity_id: "light.kitchen".to_owned(),
+    ///     last_changed: "2024-01-01T00:00:00+00:00".to_owned(),
+    ///     state: state.to_owned(),
+    ///     attributes,
+    ///     last_updated: "2024-01-01T00:00:00+00:00".to_owned(),
+    ///     context: None,
+    /// };
+    ///
+    /// let older = state("off", json!({ "friendly_name": "Kitchen", "icon": "mdi:lamp" }));
+    /// let newer = state("on", json!({ "friendly_name": "Kitchen", "brightness": 255 }));
+    ///
+    /// let diff = older.diff(&newer);
+    /// assert!(diff.state_changed);
+    /// assert_eq!(diff.added, vec!["brightness"]);
+    /// assert_eq!(diff.removed, vec!["icon"]);
+    /// assert!(diff.changed.is_empty());
+    /// ```
+    pub fn diff(&self, newer: &HassEntityState) -> StateDiff {
+        let empty = serde_json::Map::new();
+        let older_attributes = self.attributes.as_object().unwrap_or(&empty);
+        let newer_attributes = newer.attributes.as_object().unwrap_or(&empty);
+
+        let mut diff = StateDiff {
+            state_changed: self.state != newer.state,
+            ..Default::default()
+        };
+        for (key, value) in newer_attributes {
+            match older_attributes.get(key) {
+                None => diff.added.push(key.clone()),
+                Some(older) if older != value => diff.changed.push(key.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = older_attributes
+            .keys()
+            .filter(|key| !newer_attributes.contains_key(*key))
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+/// The difference between two states of an entity, see `HassEntityState::diff`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// True if the `state` itself changed
+    pub state_changed: bool,
+    /// The attributes which are only in the newer state
+    pub added: Vec<String>,
+    /// The attributes which are only in the older state
+    pub removed: Vec<String>,
+    /// The attributes which are in both states, with different values
+    pub changed: Vec<String>,
+}
+
+impl StateDiff {
+    /// Returns true if neither the state nor the attributes changed
+    pub fn is_empty(&self) -> bool {
+        !self.state_changed
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    fn entity(entity_id: &str, options: Value) -> HassEntity {
+        serde_json::from_value(json!({
+            "area_id": null, "config_entry_id": null, "device_id": null, "disabled_by": null,
+            "entity_category": null, "entity_id": entity_id, "has_entity_name": true,
+            "hidden_by": null, "icon": null, "id": format!("id-{}", entity_id), "name": null,
+            "options": options, "original_name": null, "platform": "hue",
+            "translation_key": null, "unique_id": format!("unique-{}", entity_id)
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn entities_are_deduplicated_in_a_set() {
+        let options = json!({"sensor": {"suggested_display_precision": 1}});
+        let entities: HashSet<HassEntity> = [
+            entity("light.kitchen", options.clone()),
+            entity("light.kitchen", options.clone()),
+            entity("light.hall", options.clone()),
+            // the options are not hashed, but still compared
+            entity("light.hall", json!({})),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(entities.len(), 3);
+        assert!(entities.contains(&entity("light.kitchen", options)));
+        assert!(!entities.contains(&entity("light.porch", json!({}))));
+    }
+}
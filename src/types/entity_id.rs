@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The id of an entity, in the `domain.object_id` form
+///
+/// It is made from a literal with the `entity_id!` macro, which checks the form at compile time,
+/// or from a string with `EntityId::parse`.
+///
+/// ```
+/// use hass_rs::entity_id;
+///
+/// let kitchen = entity_id!("light.kitchen");
+/// assert_eq!(kitchen.domain(), "light");
+/// assert_eq!(kitchen.object_id(), "kitchen");
+/// ```
+///
+/// ```compile_fail
+/// let kitchen = hass_rs::entity_id!("light_kitchen");
+/// ```
+///
+/// ```compile_fail
+/// let kitchen = hass_rs::entity_id!("light.");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct EntityId(String);
+
+impl EntityId {
+    /// Returns true if `id` has exactly one dot, with a non-empty domain and object id around it
+    pub const fn is_valid(id: &str) -> bool {
+        let bytes = id.as_bytes();
+        let mut dot = None;
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'.' {
+                if dot.is_some() {
+                    return false;
+                }
+                dot = Some(i);
+            }
+            i += 1;
+        }
+
+        match dot {
+            Some(dot) => dot > 0 && dot < bytes.len() - 1,
+            None => false,
+        }
+    }
+
+    /// Returns the entity id, or None if `id` is not in the `domain.object_id` form
+    pub fn parse(id: &str) -> Option<Self> {
+        EntityId::is_valid(id).then(|| EntityId(id.to_owned()))
+    }
+
+    // used by the entity_id! macro, once the id was checked
+    #[doc(hidden)]
+    pub fn from_checked(id: &str) -> Self {
+        EntityId(id.to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the domain, e.g. `light` for `light.kitchen`
+    pub fn domain(&self) -> &str {
+        self.0.split_once('.').map_or("", |(domain, _)| domain)
+    }
+
+    /// Returns the object id, e.g. `kitchen` for `light.kitchen`
+    pub fn object_id(&self) -> &str {
+        self.0
+            .split_once('.')
+            .map_or("", |(_, object_id)| object_id)
+    }
+}
+
+/// Returns true if `entity_id` matches `pattern`, an entity id with `*` wildcards in its object id
+///
+/// The domain is matched as it is, a `*` matches any number of characters of the object id.
+///
+/// ```
+/// use hass_rs::entity_id_matches;
+///
+/// assert!(entity_id_matches("light.*", "light.kitchen"));
+/// assert!(!entity_id_matches("light.*", "switch.kitchen"));
+/// assert!(entity_id_matches("sensor.*_temperature", "sensor.living_room_temperature"));
+/// assert!(!entity_id_matches("sensor.*_temperature", "sensor.living_room_humidity"));
+/// assert!(entity_id_matches("light.kitchen", "light.kitchen"));
+/// assert!(!entity_id_matches("light.kitchen", "light.kitchen_2"));
+/// ```
+pub fn entity_id_matches(pattern: &str, entity_id: &str) -> bool {
+    let (Some((pattern_domain, pattern)), Some((domain, object_id))) =
+        (pattern.split_once('.'), entity_id.split_once('.'))
+    else {
+        return false;
+    };
+    if pattern_domain != domain {
+        return false;
+    }
+
+    let mut parts = pattern.split('*');
+    // split always yields a first part, which is the whole pattern without wildcard
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = object_id.strip_prefix(first) else {
+        return false;
+    };
+    let Some(last) = parts.next_back() else {
+        return rest.is_empty();
+    };
+
+    // each part between the wildcards is matched at its first occurrence, leaving the most room to the next ones
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for EntityId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for EntityId {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        match EntityId::is_valid(&id) {
+            true => Ok(EntityId(id)),
+            false => Err(format!("{} is not a domain.object_id entity id", id)),
+        }
+    }
+}
+
+impl From<EntityId> for String {
+    fn from(id: EntityId) -> Self {
+        id.0
+    }
+}
+
+/// Makes an `EntityId` from a literal, checking at compile time that it is in the `domain.object_id` form
+#[macro_export]
+macro_rules! entity_id {
+    ($id:literal) => {{
+        const _: () = assert!(
+            $crate::EntityId::is_valid($id),
+            concat!("not a domain.object_id entity id: ", $id)
+        );
+        $crate::EntityId::from_checked($id)
+    }};
+}
@@ -25,6 +25,72 @@ pub struct EventData {
     pub old_state: Option<HassEntityState>,
 }
 
+impl HassEvent {
+    /// Returns the id of the user who initiated the event, `None` when it wasn't a user (e.g. an automation)
+    pub fn triggered_by_user(&self) -> Option<&str> {
+        self.context.user_id.as_deref()
+    }
+
+    /// Returns where the event was fired, parsed from `origin`
+    ///
+    /// ```
+    /// use hass_rs::EventOrigin;
+    ///
+    /// assert_eq!(EventOrigin::from("LOCAL"), EventOrigin::Local);
+    /// assert_eq!(EventOrigin::from("REMOTE"), EventOrigin::Remote);
+    /// assert_eq!(EventOrigin::from("CLOUD"), EventOrigin::Other("CLOUD".to_owned()));
+    /// ```
+    pub fn origin_kind(&self) -> EventOrigin {
+        EventOrigin::from(self.origin.as_str())
+    }
+}
+
+/// Where an event was fired
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventOrigin {
+    /// Fired by this Home Assistant instance
+    Local,
+    /// Fired through the API, e.g. by another instance or a cloud integration
+    Remote,
+    /// An origin unknown to this version of the crate
+    Other(String),
+}
+
+impl From<&str> for EventOrigin {
+    fn from(origin: &str) -> Self {
+        match origin {
+            "LOCAL" => EventOrigin::Local,
+            "REMOTE" => EventOrigin::Remote,
+            other => EventOrigin::Other(other.to_owned()),
+        }
+    }
+}
+
+impl EventData {
+    /// Returns true when the attribute `key` differs between the old and the new state
+    ///
+    /// Some integrations emit state_changed events when nothing changed, this allows to skip them.
+    /// A missing state (e.g. the entity was added or removed) counts as a missing attribute.
+    pub fn attribute_changed(&self, key: &str) -> bool {
+        let old = self
+            .old_state
+            .as_ref()
+            .and_then(|state| state.attributes.get(key));
+        let new = self
+            .new_state
+            .as_ref()
+            .and_then(|state| state.attributes.get(key));
+        old != new
+    }
+
+    /// Returns true when the state itself differs between the old and the new state
+    pub fn state_changed(&self) -> bool {
+        let old = self.old_state.as_ref().map(|state| &state.state);
+        let new = self.new_state.as_ref().map(|state| &state.state);
+        old != new
+    }
+}
+
 impl fmt::Display for HassEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "HassEvent {{\n")?;
@@ -41,3 +107,150 @@ impl fmt::Display for HassEvent {
         Ok(())
     }
 }
+
+// this is the event pushed by a trigger subscription
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct TriggerEvent {
+    pub(crate) variables: TriggerVariables,
+}
+
+// this is part of TriggerEvent
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct TriggerVariables {
+    pub(crate) trigger: serde_json::Value,
+}
+
+// the variables of a state trigger
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct StateTrigger {
+    pub(crate) entity_id: String,
+    pub(crate) from_state: Option<HassEntityState>,
+    pub(crate) to_state: Option<HassEntityState>,
+}
+
+/// The change reported by an `entity_registry_updated`, `device_registry_updated` or `area_registry_updated` event
+///
+/// The id is the entity id, the device id or the area id, depending on the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryChange {
+    pub action: RegistryAction,
+    pub id: String,
+}
+
+impl RegistryChange {
+    /// Reads the change from the event, the id is read from the `id_key` field of its data, e.g. "device_id"
+    ///
+    /// ```
+    /// use hass_rs::{RegistryAction, RegistryChange};
+    /// use serde_json::json;
+    ///
+    /// let event = json!({
+    ///     "event_type": "device_registry_updated",
+    ///     "data": { "action": "remove", "device_id": "0a1b2c" }
+    /// });
+    /// let change = RegistryChange::from_event(&event, "device_id").unwrap();
+    /// assert_eq!(change.action, RegistryAction::Removed);
+    /// assert_eq!(change.id, "0a1b2c");
+    /// ```
+    pub fn from_event(event: &serde_json::Value, id_key: &str) -> Option<Self> {
+        let data = event.get("data")?;
+        Some(RegistryChange {
+            action: RegistryAction::deserialize(data.get("action")?).ok()?,
+            id: data.get(id_key)?.as_str()?.to_owned(),
+        })
+    }
+}
+
+/// The action of a registry change
+///
+/// ```
+/// use hass_rs::RegistryAction;
+///
+/// let actions: Vec<RegistryAction> =
+///     serde_json::from_str(r#"["create", "update", "remove", "reorder"]"#).unwrap();
+/// assert_eq!(
+///     actions,
+///     [
+///         RegistryAction::Created,
+///         RegistryAction::Updated,
+///         RegistryAction::Removed,
+///         RegistryAction::Other
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryAction {
+    #[serde(rename = "create")]
+    Created,
+    #[serde(rename = "update")]
+    Updated,
+    #[serde(rename = "remove")]
+    Removed,
+    /// An action unknown to this version of the crate, e.g. "reorder" for the areas
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn event(context: Value) -> HassEvent {
+        serde_json::from_value(json!({
+            "data": {"entity_id": "light.kitchen", "new_state": null, "old_state": null},
+            "event_type": "state_changed",
+            "time_fired": "2024-01-01T00:00:00+00:00",
+            "origin": "LOCAL",
+            "context": context
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn an_event_initiated_by_a_user() {
+        let event = event(json!({
+            "id": "01HPRMZAWNXKVVPSP11QFJ53HB",
+            "parent_id": null,
+            "user_id": "31ddb597e03147118cf8d2f8fbea5553"
+        }));
+        assert_eq!(
+            event.context,
+            Context {
+                id: "01HPRMZAWNXKVVPSP11QFJ53HB".to_owned(),
+                parent_id: None,
+                user_id: Some("31ddb597e03147118cf8d2f8fbea5553".to_owned()),
+            }
+        );
+        assert_eq!(
+            event.triggered_by_user(),
+            Some("31ddb597e03147118cf8d2f8fbea5553")
+        );
+    }
+
+    #[test]
+    fn an_event_initiated_by_an_automation() {
+        let event = event(json!({
+            "id": "01HPRMZAWNXKVVPSP11QFJ53HB",
+            "parent_id": "01HPRMYR5TX8Y7FM2Z7QJZV3KD",
+            "user_id": null
+        }));
+        assert_eq!(
+            event.context.parent_id.as_deref(),
+            Some("01HPRMYR5TX8Y7FM2Z7QJZV3KD")
+        );
+        assert_eq!(event.triggered_by_user(), None);
+    }
+
+    #[test]
+    fn a_context_without_the_optional_fields() {
+        // the older versions only send the id
+        let event = event(json!({"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}));
+        assert_eq!(event.context.parent_id, None);
+        assert_eq!(event.context.user_id, None);
+        assert_eq!(event.triggered_by_user(), None);
+    }
+}
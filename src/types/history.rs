@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// This object represents the history of entities, by entity_id, returned by `HassClient::get_history`
+pub type HassHistory = HashMap<String, Vec<HistoryState>>;
+
+/// This object represents a state in the history of an entity
+///
+/// The history is returned in a compact form: the timestamps are in seconds since the Unix epoch,
+/// and the attributes are only included in the first state, unless all of them were requested.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistoryState {
+    #[serde(rename = "s")]
+    pub state: String,
+    #[serde(rename = "lu")]
+    pub last_updated: f64,
+    /// Only set when it differs from `last_updated`
+    #[serde(rename = "lc", default)]
+    pub last_changed: Option<f64>,
+    #[serde(rename = "a", default)]
+    pub attributes: Option<Value>,
+}
+
+/// Returns the fraction of the time between `start` and `end` (timestamps in seconds) the entity was not `unavailable`
+///
+/// Each state lasts until the next one, the last one until `end`, and a state from before `start`
+/// counts from `start`. The time before the first state is unknown, it is left out of the computation.
+/// Returns None when there is no state in the window.
+///
+/// ```
+/// use hass_rs::{availability_ratio, HistoryState};
+///
+/// let state = |state: &str, at: f64| HistoryState {
+///     state: state.to_owned(),
+///     last_updated: at,
+///     last_changed: None,
+///     attributes: None,
+/// };
+/// // on from 0 to 60, unavailable from 60 to 90, then on again until 100
+/// let history = [state("on", 0.0), state("unavailable", 60.0), state("on", 90.0)];
+/// assert_eq!(availability_ratio(&history, 0.0, 100.0), Some(0.7));
+/// ```
+pub fn availability_ratio(history: &[HistoryState], start: f64, end: f64) -> Option<f32> {
+    let mut covered = 0.0;
+    let mut available = 0.0;
+    for (i, state) in history.iter().enumerate() {
+        let from = state.last_updated.max(start);
+        let until = history
+            .get(i + 1)
+            .map_or(end, |next| next.last_updated)
+            .min(end);
+        if until <= from {
+            continue;
+        }
+
+        covered += until - from;
+        if state.state != "unavailable" {
+            available += until - from;
+        }
+    }
+
+    (covered > 0.0).then(|| (available / covered) as f32)
+}
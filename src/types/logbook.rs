@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// This object represents an entry of the Home Assistant Logbook
+///
+/// received when the client is subscribed to the logbook event stream
+/// [Logbook](https://www.home-assistant.io/integrations/logbook/)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LogbookEntry {
+    pub when: f64,
+    pub name: Option<String>,
+    pub message: Option<String>,
+    pub entity_id: Option<String>,
+    pub state: Option<String>,
+    pub domain: Option<String>,
+    pub icon: Option<String>,
+    pub context_user_id: Option<String>,
+}
+
+// this is the event pushed by the logbook event stream
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct LogbookEvents {
+    pub(crate) events: Vec<LogbookEntry>,
+}
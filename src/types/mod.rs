@@ -1,17 +1,37 @@
 //! API types.
 
 mod command;
+mod compact;
 mod config;
+mod domains;
 mod entities;
+mod entity_id;
 mod events;
+mod history;
+mod logbook;
+mod notifications;
 mod panels;
+mod recorder;
 mod response;
+mod script;
+mod service_call;
 mod services;
+mod template;
 
-pub(crate) use command::*;
+pub use command::*;
+pub use compact::*;
 pub use config::*;
+pub use domains::*;
 pub use entities::*;
+pub use entity_id::*;
 pub use events::*;
+pub use history::*;
+pub use logbook::*;
+pub use notifications::*;
 pub use panels::*;
+pub use recorder::*;
 pub use response::*;
+pub use script::*;
+pub use service_call::*;
 pub use services::*;
+pub use template::*;
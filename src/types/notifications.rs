@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// This object represents a persistent notification, shown in the sidebar of the frontend
+///
+/// ```
+/// use hass_rs::PersistentNotification;
+///
+/// let notification: PersistentNotification = serde_json::from_str(
+///     r#"{"notification_id": "backup", "title": null, "message": "Backup done",
+///         "created_at": "2024-01-01T00:00:00+00:00"}"#,
+/// )
+/// .unwrap();
+/// assert_eq!(notification.title, None);
+/// assert_eq!(notification.status, None);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PersistentNotification {
+    pub notification_id: String,
+    pub title: Option<String>,
+    pub message: String,
+    pub created_at: String,
+    /// "unread" or "read", only reported by the older versions
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+/// This object represents a change of the persistent notifications, delivered by `HassClient::subscribe_persistent_notifications`
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct NotificationsUpdate {
+    /// "current" for the notifications present when subscribing, then "added", "updated" or "removed"
+    #[serde(rename = "type")]
+    pub update_type: String,
+    /// The notifications concerned by the change, by notification_id
+    pub notifications: HashMap<String, PersistentNotification>,
+}
@@ -3,18 +3,27 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+/// This object represents the panels registered in the frontend, keyed by their url_path
 pub type HassPanels = HashMap<String, HassPanel>;
 
+/// This object represents a panel of the Home Assistant frontend
+///
+/// [Panels](https://developers.home-assistant.io/docs/frontend/custom-ui/creating-custom-panels)
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct HassPanel {
     pub component_name: String,
     pub config: Option<HassPanelConfig>,
     pub icon: Option<String>,
+    #[serde(default)]
     pub require_admin: bool,
     pub title: Option<String>,
     pub url_path: String,
+    /// The integration configured by the panel, for the panels of the configuration
+    #[serde(default)]
+    pub config_panel_domain: Option<String>,
 }
 
+/// This is part of HassPanel, the lovelace panels hold their `mode` and the custom panels their `_panel_custom`
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct HassPanelConfig {
     #[serde(rename = "_panel_custom")]
@@ -23,12 +32,15 @@ pub struct HassPanelConfig {
     pub title: Option<String>,
 }
 
+/// This is part of HassPanelConfig, the definition of a custom panel
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct HassCustomPanelConfig {
+    #[serde(default)]
     pub embed_iframe: bool,
     pub module_url: Option<String>,
     pub js_url: Option<String>,
     pub name: String,
+    #[serde(default)]
     pub trust_external: bool,
 }
 
@@ -41,6 +53,7 @@ impl fmt::Display for HassPanel {
         write!(f, "  require_admin: {},\n", self.require_admin)?;
         write!(f, "  title: {:?},\n", self.title)?;
         write!(f, "  url_path: {},\n", self.url_path)?;
+        writeln!(f, "  config_panel_domain: {:?},", self.config_panel_domain)?;
         write!(f, "}}")?;
         Ok(())
     }
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// This object represents the metadata of a statistic recorded by Home Assistant
+///
+/// Used to discover the statistic ids before querying the statistics.
+/// [Recorder](https://developers.home-assistant.io/docs/core/entity/sensor/#long-term-statistics)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatisticMeta {
+    pub statistic_id: String,
+    pub name: Option<String>,
+    pub source: String,
+    #[serde(alias = "display_unit_of_measurement")]
+    pub unit_of_measurement: Option<String>,
+    #[serde(default)]
+    pub has_mean: bool,
+    #[serde(default)]
+    pub has_sum: bool,
+    pub statistics_unit_of_measurement: Option<String>,
+}
@@ -1,5 +1,7 @@
 use crate::types::HassEvent;
+use crate::{HassError, HassResult};
 
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde_json::Value;
 
@@ -7,7 +9,7 @@ use serde_json::Value;
 /// next to any other fields of the variant.
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
-pub(crate) enum Response {
+pub enum Response {
     //request to autheticate
     AuthRequired(AuthRequired),
     //authetication suceeded
@@ -24,38 +26,70 @@ pub(crate) enum Response {
     Close(String),
 }
 
+/// A message received from the server, either the response to a command or an event of a subscription
+///
+/// It is yielded by `HassClient::incoming`, to handle both in a single loop.
+#[derive(Debug)]
+pub enum Incoming {
+    Response(Response),
+    Event(WSEvent),
+}
+
+// used to read only the type of a message, skipping the other fields
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct MessageType<'a> {
+    #[serde(rename = "type", borrow)]
+    pub(crate) msg_type: std::borrow::Cow<'a, str>,
+    #[serde(default)]
+    pub(crate) id: Option<u64>,
+}
+
+// used to extract the raw event of a subscription, which doesn't have the HassEvent format
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct WSRawEvent {
+    pub(crate) event: Value,
+}
+
 // this is the first message received from websocket,
 // that ask to provide a authetication method
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
-pub(crate) struct AuthRequired {
-    #[serde(rename = "type")]
-    pub(crate) msg_type: String,
-    pub(crate) ha_version: String,
+pub struct AuthRequired {
+    // the tag of the Response is consumed by the enum, so the type is only known from the variant
+    #[serde(rename = "type", default = "auth_required")]
+    pub msg_type: String,
+    pub ha_version: String,
+}
+
+fn auth_required() -> String {
+    "auth_required".to_owned()
 }
 
 // this is received when the service successfully autheticate
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
-pub(crate) struct AuthOk {
+pub struct AuthOk {
     //  #[serde(rename = "type")]
     //  pub(crate) msg_type: String,
-    pub(crate) ha_version: String,
+    pub ha_version: String,
 }
 
 // this is received if the authetication failed
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
-pub(crate) struct AuthInvalid {
+pub struct AuthInvalid {
     // #[serde(rename = "type")]
     // pub(crate) msg_type: String,
-    pub(crate) message: String,
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
 // this is received as a response to a ping request
 #[derive(Debug, Deserialize, PartialEq)]
-pub(crate) struct WSPong {
-    pub(crate) id: u64,
+pub struct WSPong {
+    pub id: u64,
     // #[serde(rename = "type")]
     // pub(crate) msg_type: String,
 }
@@ -64,6 +98,9 @@ pub(crate) struct WSPong {
 ///
 /// received when the client is subscribed to
 /// [Subscribe to events](https://developers.home-assistant.io/docs/api/websocket/#subscribe-to-events)
+///
+/// The `id` is the id of the subscription which delivered the event, the subscribed event type
+/// can be looked up with it in `HassClient::subscriptions`.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 pub struct WSEvent {
     pub id: u64,
@@ -79,6 +116,7 @@ pub struct WSEvent {
 /// if "suceess" is false, then the "error" should be further explored
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct WSResult {
+    /// The id of the command this is the result of
     pub id: u64,
     // #[serde(rename = "type")]
     // pub(crate) msg_type: String,
@@ -87,8 +125,127 @@ pub struct WSResult {
     pub error: Option<ErrorCode>,
 }
 
+impl WSResult {
+    /// Deserializes the result into the type expected by the request
+    ///
+    /// Returns an error, rather than panicking, when the result is missing or null.
+    /// A deserialization error tells the path of the offending field in the result,
+    /// e.g. `[3].attributes.brightness`, to find out what changed after a Home Assistant upgrade.
+    ///
+    /// ```
+    /// use hass_rs::{HassEntityState, WSResult};
+    ///
+    /// let result: WSResult = serde_json::from_str(
+    ///     r#"{"id": 1, "type": "result", "success": true, "result": [{"entity_id": "sun.sun"}]}"#,
+    /// )
+    /// .unwrap();
+    /// let error = result.typed_result::<Vec<HassEntityState>>().unwrap_err();
+    /// assert!(error.to_string().contains("at `[0]`"));
+    /// ```
+    pub fn typed_result<T: DeserializeOwned>(&self) -> HassResult<T> {
+        let result = self.result.as_ref().ok_or_else(|| {
+            HassError::Generic(format!(
+                "The response {} does not contain a result",
+                self.id
+            ))
+        })?;
+        serde_path_to_error::deserialize(result).map_err(|error| {
+            let path = error.path().to_string();
+            HassError::UnableToDeserialize(serde::de::Error::custom(format!(
+                "{} at `{}`",
+                error.into_inner(),
+                path
+            )))
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct ErrorCode {
     pub code: String,
     pub message: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(id: u64, event_type: &str) -> Value {
+        json!({
+            "id": id,
+            "type": "event",
+            "event": {
+                "data": {"entity_id": "light.kitchen", "new_state": null, "old_state": null},
+                "event_type": event_type,
+                "time_fired": "2024-01-01T00:00:00+00:00",
+                "origin": "LOCAL",
+                "context": {"id": "01HPRMZAWNXKVVPSP11QFJ53HB"}
+            }
+        })
+    }
+
+    #[test]
+    fn each_event_carries_its_subscription_id() {
+        for (id, event_type) in [(3, "state_changed"), (7, "call_service")] {
+            let response: Response = serde_json::from_value(event(id, event_type)).unwrap();
+            match response {
+                Response::Event(event) => {
+                    assert_eq!(event.id, id);
+                    assert_eq!(event.event.event_type, event_type);
+                }
+                other => panic!("expected an event, got {:?}", other),
+            }
+        }
+    }
+
+    fn result(result: Value) -> WSResult {
+        serde_json::from_value(
+            json!({"id": 4, "type": "result", "success": true, "result": result}),
+        )
+        .unwrap()
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Light {
+        brightness: u8,
+    }
+
+    #[test]
+    fn a_null_result_is_reported_as_missing() {
+        let error = result(Value::Null)
+            .typed_result::<Vec<Light>>()
+            .unwrap_err();
+        match error {
+            HassError::Generic(message) => {
+                assert_eq!(message, "The response 4 does not contain a result")
+            }
+            other => panic!("expected a missing result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_result_of_the_wrong_type_reports_where_it_failed() {
+        let lights = json!([{"brightness": 120}, {"brightness": "dim"}]);
+        let error = result(lights).typed_result::<Vec<Light>>().unwrap_err();
+        match error {
+            HassError::UnableToDeserialize(error) => {
+                let error = error.to_string();
+                assert!(error.contains("invalid type: string \"dim\""), "{}", error);
+                assert!(error.contains("at `[1].brightness`"), "{}", error);
+            }
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+
+        let lights = result(json!([{"brightness": 120}])).typed_result::<Vec<Light>>();
+        assert_eq!(lights.unwrap()[0].brightness, 120);
+        let error = result(json!({"brightness": 120}))
+            .typed_result::<Vec<Light>>()
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("expected a sequence"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use crate::types::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// This object represents the outcome of the validation of an automation configuration
+///
+/// The command succeeds even when the configuration is invalid, each section which was sent
+/// holds its own outcome.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConfigValidation {
+    pub trigger: Option<SectionValidation>,
+    pub condition: Option<SectionValidation>,
+    pub action: Option<SectionValidation>,
+}
+
+impl ConfigValidation {
+    /// Returns true when none of the sections has an error
+    pub fn is_valid(&self) -> bool {
+        [&self.trigger, &self.condition, &self.action]
+            .into_iter()
+            .flatten()
+            .all(|section| section.valid)
+    }
+}
+
+/// This is part of ConfigValidation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SectionValidation {
+    pub valid: bool,
+    pub error: Option<String>,
+}
+
+/// This object represents the outcome of a script executed with `execute_script`
+///
+/// The `response` holds the variable of the `stop` action with `response_variable`, if any.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScriptResult {
+    pub context: Context,
+    #[serde(default)]
+    pub response: Option<Value>,
+}
@@ -0,0 +1,310 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// This object represents the targets of a service call: entities, devices and areas
+///
+/// A single call can target any number of each of them, only the non-empty lists are sent to Home Assistant.
+///
+/// ```
+/// use hass_rs::{ServiceCall, Target};
+/// use serde_json::json;
+///
+/// let target = Target::entities(["light.kitchen", "light.hall", "light.porch"]).with_area("garden");
+/// assert_eq!(
+///     serde_json::to_value(&target).unwrap(),
+///     json!({
+///         "entity_id": ["light.kitchen", "light.hall", "light.porch"],
+///         "area_id": ["garden"]
+///     })
+/// );
+///
+/// let call = ServiceCall {
+///     domain: "light".to_owned(),
+///     service: "turn_on".to_owned(),
+///     service_data: None,
+///     target: Some(target),
+/// };
+/// ```
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct Target {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entity_id: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub device_id: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub area_id: Vec<String>,
+}
+
+impl Target {
+    /// Creates a target for a single entity
+    pub fn entity(entity_id: &str) -> Self {
+        Target {
+            entity_id: vec![entity_id.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    /// Creates a target for several entities
+    pub fn entities<I, S>(entity_ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Target {
+            entity_id: entity_ids.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a target for all the entities of an area
+    pub fn area(area_id: &str) -> Self {
+        Target {
+            area_id: vec![area_id.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    /// Creates a target for all the entities of a device
+    pub fn device(device_id: &str) -> Self {
+        Target {
+            device_id: vec![device_id.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    /// Adds an entity to the target
+    pub fn with_entity(mut self, entity_id: &str) -> Self {
+        self.entity_id.push(entity_id.to_owned());
+        self
+    }
+
+    /// Adds a device to the target
+    pub fn with_device(mut self, device_id: &str) -> Self {
+        self.device_id.push(device_id.to_owned());
+        self
+    }
+
+    /// Adds an area to the target
+    pub fn with_area(mut self, area_id: &str) -> Self {
+        self.area_id.push(area_id.to_owned());
+        self
+    }
+
+    /// Returns true if the target has no entity, device or area
+    pub fn is_empty(&self) -> bool {
+        self.entity_id.is_empty() && self.device_id.is_empty() && self.area_id.is_empty()
+    }
+}
+
+/// This object represents a service call, ready to be sent with `HassClient::call`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceCall {
+    pub domain: String,
+    pub service: String,
+    pub service_data: Option<Value>,
+    pub target: Option<Target>,
+}
+
+impl ServiceCall {
+    // the call of the `domain`.`service` service against a single entity
+    fn for_entity(domain: &str, service: &str, entity_id: &str, data: Map<String, Value>) -> Self {
+        ServiceCall {
+            domain: domain.to_owned(),
+            service: service.to_owned(),
+            service_data: (!data.is_empty()).then_some(Value::Object(data)),
+            target: Some(Target::entity(entity_id)),
+        }
+    }
+}
+
+/// Builds the `light.turn_on` service call
+///
+/// ```
+/// use hass_rs::LightTurnOn;
+/// use serde_json::json;
+///
+/// let call = LightTurnOn::new("light.kitchen").brightness_pct(50).rgb(255, 0, 0).build();
+/// assert_eq!((call.domain.as_str(), call.service.as_str()), ("light", "turn_on"));
+/// assert_eq!(call.service_data, Some(json!({ "brightness_pct": 50, "rgb_color": [255, 0, 0] })));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LightTurnOn {
+    entity_id: String,
+    data: Map<String, Value>,
+}
+
+impl LightTurnOn {
+    pub fn new(entity_id: &str) -> Self {
+        LightTurnOn {
+            entity_id: entity_id.to_owned(),
+            data: Map::new(),
+        }
+    }
+
+    /// Sets the brightness, from 0 to 100
+    pub fn brightness_pct(mut self, brightness: u8) -> Self {
+        self.data
+            .insert("brightness_pct".to_owned(), json!(brightness.min(100)));
+        self
+    }
+
+    /// Sets the color
+    pub fn rgb(mut self, red: u8, green: u8, blue: u8) -> Self {
+        self.data
+            .insert("rgb_color".to_owned(), json!([red, green, blue]));
+        self
+    }
+
+    /// Sets the color temperature, in Kelvin
+    pub fn color_temp_kelvin(mut self, kelvin: u32) -> Self {
+        self.data
+            .insert("color_temp_kelvin".to_owned(), json!(kelvin));
+        self
+    }
+
+    /// Sets the duration of the transition, in seconds
+    pub fn transition(mut self, seconds: f32) -> Self {
+        self.data.insert("transition".to_owned(), json!(seconds));
+        self
+    }
+
+    pub fn build(self) -> ServiceCall {
+        ServiceCall::for_entity("light", "turn_on", &self.entity_id, self.data)
+    }
+}
+
+/// Builds the `cover.set_cover_position` service call
+#[derive(Debug, Clone)]
+pub struct CoverSetPosition {
+    entity_id: String,
+    position: u8,
+}
+
+impl CoverSetPosition {
+    /// The position goes from 0 (closed) to 100 (open)
+    pub fn new(entity_id: &str, position: u8) -> Self {
+        CoverSetPosition {
+            entity_id: entity_id.to_owned(),
+            position: position.min(100),
+        }
+    }
+
+    pub fn build(self) -> ServiceCall {
+        let mut data = Map::new();
+        data.insert("position".to_owned(), json!(self.position));
+        ServiceCall::for_entity("cover", "set_cover_position", &self.entity_id, data)
+    }
+}
+
+/// Builds the `climate.set_temperature` service call
+#[derive(Debug, Clone)]
+pub struct ClimateSetTemperature {
+    entity_id: String,
+    data: Map<String, Value>,
+}
+
+impl ClimateSetTemperature {
+    /// The temperature is expressed in the unit system of Home Assistant
+    pub fn new(entity_id: &str, temperature: f64) -> Self {
+        let mut data = Map::new();
+        data.insert("temperature".to_owned(), json!(temperature));
+        ClimateSetTemperature {
+            entity_id: entity_id.to_owned(),
+            data,
+        }
+    }
+
+    /// Also sets the HVAC mode, e.g. "heat" or "cool"
+    pub fn hvac_mode(mut self, mode: &str) -> Self {
+        self.data.insert("hvac_mode".to_owned(), json!(mode));
+        self
+    }
+
+    pub fn build(self) -> ServiceCall {
+        ServiceCall::for_entity("climate", "set_temperature", &self.entity_id, self.data)
+    }
+}
+
+/// Builds the `media_player.play_media` service call
+#[derive(Debug, Clone)]
+pub struct MediaPlayerPlayMedia {
+    entity_id: String,
+    data: Map<String, Value>,
+}
+
+impl MediaPlayerPlayMedia {
+    /// The content type depends on the player, e.g. "music", "video" or "playlist"
+    pub fn new(entity_id: &str, content_id: &str, content_type: &str) -> Self {
+        let mut data = Map::new();
+        data.insert("media_content_id".to_owned(), json!(content_id));
+        data.insert("media_content_type".to_owned(), json!(content_type));
+        MediaPlayerPlayMedia {
+            entity_id: entity_id.to_owned(),
+            data,
+        }
+    }
+
+    /// Adds the media to the queue instead of playing it right away
+    pub fn enqueue(mut self, mode: &str) -> Self {
+        self.data.insert("enqueue".to_owned(), json!(mode));
+        self
+    }
+
+    pub fn build(self) -> ServiceCall {
+        ServiceCall::for_entity("media_player", "play_media", &self.entity_id, self.data)
+    }
+}
+
+/// Builds the transport controls and the volume service calls of the `media_player` domain
+///
+/// ```
+/// use hass_rs::MediaPlayerControl;
+/// use serde_json::json;
+///
+/// let call = MediaPlayerControl::set_volume("media_player.living_room", 0.4).unwrap();
+/// assert_eq!(call.service, "volume_set");
+/// assert_eq!(call.service_data, Some(json!({ "volume_level": 0.4f32 })));
+///
+/// assert!(MediaPlayerControl::set_volume("media_player.living_room", 1.5).is_none());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct MediaPlayerControl;
+
+impl MediaPlayerControl {
+    pub fn play(entity_id: &str) -> ServiceCall {
+        ServiceCall::for_entity("media_player", "media_play", entity_id, Map::new())
+    }
+
+    pub fn pause(entity_id: &str) -> ServiceCall {
+        ServiceCall::for_entity("media_player", "media_pause", entity_id, Map::new())
+    }
+
+    pub fn next_track(entity_id: &str) -> ServiceCall {
+        ServiceCall::for_entity("media_player", "media_next_track", entity_id, Map::new())
+    }
+
+    pub fn previous_track(entity_id: &str) -> ServiceCall {
+        ServiceCall::for_entity(
+            "media_player",
+            "media_previous_track",
+            entity_id,
+            Map::new(),
+        )
+    }
+
+    /// Sets the volume, from 0.0 to 1.0, None is returned for a level out of that range
+    pub fn set_volume(entity_id: &str, level: f32) -> Option<ServiceCall> {
+        if !(0.0..=1.0).contains(&level) {
+            return None;
+        }
+
+        let mut data = Map::new();
+        data.insert("volume_level".to_owned(), json!(level));
+        Some(ServiceCall::for_entity(
+            "media_player",
+            "volume_set",
+            entity_id,
+            data,
+        ))
+    }
+}
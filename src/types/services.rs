@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::fmt;
 
@@ -32,12 +32,216 @@ pub struct HassService {
 pub type FieldName = HashMap<String, Field>;
 
 ///This is part of HassService
+///
+/// A field can also be a collapsible section grouping other fields, which are then in its own `fields`.
+/// The sections are only a presentation, the data of the call stays flat.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Field {
     pub name: Option<String>,
     #[serde(default)]
     pub description: Option<String>,
+    #[serde(default)]
     pub example: Option<Value>,
+    #[serde(default)]
+    pub selector: Option<Selector>,
+    #[serde(default)]
+    pub required: bool,
+    /// Set on the fields which the UI only shows in advanced mode
+    #[serde(default)]
+    pub advanced: bool,
+    /// Set on the sections which are collapsed by default
+    #[serde(default)]
+    pub collapsed: bool,
+    /// The fields of a section, empty for a plain field
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: FieldName,
+}
+
+impl Field {
+    /// Returns true if it is a section grouping other fields
+    pub fn is_section(&self) -> bool {
+        !self.fields.is_empty()
+    }
+}
+
+impl HassService {
+    /// Returns all the fields which are not sections, including the ones nested in the sections
+    ///
+    /// ```
+    /// use hass_rs::HassService;
+    ///
+    /// let turn_on: HassService = serde_json::from_str(r#"{
+    ///     "name": "Turn on",
+    ///     "description": "Turns on one or more lights",
+    ///     "fields": {
+    ///         "transition": {"selector": {"number": {"min": 0, "max": 300}}},
+    ///         "advanced_fields": {
+    ///             "collapsed": true,
+    ///             "fields": {
+    ///                 "rgb_color": {"advanced": true, "selector": {"color_rgb": {}}},
+    ///                 "flash": {"advanced": true, "selector": {"select": {"options": ["long", "short"]}}}
+    ///             }
+    ///         }
+    ///     }
+    /// }"#).unwrap();
+    ///
+    /// let section = &turn_on.fields["advanced_fields"];
+    /// assert!(section.is_section() && section.collapsed);
+    ///
+    /// let mut advanced: Vec<_> = turn_on
+    ///     .all_fields()
+    ///     .into_iter()
+    ///     .filter(|(_, field)| field.advanced)
+    ///     .map(|(name, _)| name)
+    ///     .collect();
+    /// advanced.sort();
+    /// assert_eq!(advanced, ["flash", "rgb_color"]);
+    /// assert_eq!(turn_on.all_fields().len(), 3);
+    /// ```
+    pub fn all_fields(&self) -> Vec<(&str, &Field)> {
+        let mut all = Vec::new();
+        collect_fields(&self.fields, &mut all);
+        all
+    }
+}
+
+// adds the fields to `all`, replacing the sections by their own fields
+fn collect_fields<'a>(fields: &'a FieldName, all: &mut Vec<(&'a str, &'a Field)>) {
+    for (name, field) in fields {
+        match field.is_section() {
+            true => collect_fields(&field.fields, all),
+            false => all.push((name.as_str(), field)),
+        }
+    }
+}
+
+/// This object represents the selector of a service Field, describing the expected UI input
+///
+/// The common selector kinds are parsed, the other ones are kept as `Other` with the raw selector.
+/// [Selectors](https://www.home-assistant.io/docs/blueprint/selectors/)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "Value", into = "Value")]
+pub enum Selector {
+    Number {
+        min: Option<f64>,
+        max: Option<f64>,
+        step: Option<f64>,
+        unit_of_measurement: Option<String>,
+    },
+    Entity {
+        domain: Vec<String>,
+        integration: Option<String>,
+    },
+    Select {
+        options: Vec<SelectOption>,
+    },
+    Boolean,
+    Text,
+    Other(Value),
+}
+
+/// This is part of Selector
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SelectOption {
+    pub label: String,
+    pub value: String,
+}
+
+impl From<Value> for Selector {
+    fn from(selector: Value) -> Self {
+        let (kind, config) = match selector.as_object() {
+            Some(map) if map.len() == 1 => map.iter().next().unwrap(),
+            _ => return Selector::Other(selector),
+        };
+        let string = |key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+        };
+
+        match kind.as_str() {
+            "number" => Selector::Number {
+                min: config.get("min").and_then(Value::as_f64),
+                max: config.get("max").and_then(Value::as_f64),
+                // step can also be "any"
+                step: config.get("step").and_then(Value::as_f64),
+                unit_of_measurement: string("unit_of_measurement"),
+            },
+            "entity" => {
+                // the domain can be a single string or a list
+                let domain = match config.get("domain") {
+                    Some(Value::String(domain)) => vec![domain.clone()],
+                    Some(Value::Array(domains)) => domains
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(ToOwned::to_owned)
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                Selector::Entity {
+                    domain,
+                    integration: string("integration"),
+                }
+            }
+            "select" => {
+                // the options can be plain strings or label/value pairs
+                let options = config
+                    .get("options")
+                    .and_then(Value::as_array)
+                    .map(|options| {
+                        options
+                            .iter()
+                            .filter_map(|option| match option {
+                                Value::String(value) => Some(SelectOption {
+                                    label: value.clone(),
+                                    value: value.clone(),
+                                }),
+                                _ => serde_json::from_value(option.clone()).ok(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Selector::Select { options }
+            }
+            "boolean" => Selector::Boolean,
+            "text" => Selector::Text,
+            _ => Selector::Other(selector),
+        }
+    }
+}
+
+impl From<Selector> for Value {
+    fn from(selector: Selector) -> Self {
+        match selector {
+            Selector::Number {
+                min,
+                max,
+                step,
+                unit_of_measurement,
+            } => json!({ "number": {
+                "min": min,
+                "max": max,
+                "step": step,
+                "unit_of_measurement": unit_of_measurement,
+            }}),
+            Selector::Entity {
+                domain,
+                integration,
+            } => json!({ "entity": { "domain": domain, "integration": integration } }),
+            Selector::Select { options } => json!({ "select": { "options": options } }),
+            Selector::Boolean => json!({ "boolean": {} }),
+            Selector::Text => json!({ "text": {} }),
+            Selector::Other(selector) => selector,
+        }
+    }
+}
+
+// this is the result of a call_service request
+#[cfg(feature = "client")]
+#[derive(Debug, Deserialize)]
+pub(crate) struct ServiceCalled {
+    pub(crate) context: crate::types::Context,
 }
 
 impl fmt::Display for HassServices {
@@ -56,6 +260,7 @@ impl fmt::Display for HassServices {
                     write!(f, "            name: {:?},\n", field.name)?;
                     write!(f, "            description: {:?},\n", field.description)?;
                     write!(f, "            example: {:?},\n", field.example)?;
+                    writeln!(f, "            selector: {:?},", field.selector)?;
                     write!(f, "          }},\n")?;
                 }
                 write!(f, "        }},\n")?;
@@ -79,6 +284,9 @@ impl fmt::Display for HassService {
             write!(f, "          name: {:?},\n", field.name)?;
             write!(f, "          description: {:?},\n", field.description)?;
             write!(f, "          example: {:?},\n", field.example)?;
+            writeln!(f, "          selector: {:?},", field.selector)?;
+            writeln!(f, "          required: {},", field.required)?;
+            writeln!(f, "          advanced: {},", field.advanced)?;
             write!(f, "          }},\n")?;
         }
         Ok(())
@@ -98,4 +306,37 @@ impl HassServices {
                 .collect()
         })
     }
+
+    /// Checks locally that the service exists and that the `data` holds all its required fields
+    ///
+    /// This catches the typos before calling the service, the problems found are returned
+    /// as messages, e.g. the unknown service or each missing field.
+    pub fn validate_call(
+        &self,
+        domain: &str,
+        service: &str,
+        data: &Value,
+    ) -> Result<(), Vec<String>> {
+        let hass_service = self
+            .0
+            .get(domain)
+            .and_then(|services| services.get(service))
+            .ok_or_else(|| vec![format!("unknown service {}.{}", domain, service)])?;
+
+        // the fields of the sections are part of the flat data too
+        let mut missing: Vec<String> = hass_service
+            .all_fields()
+            .into_iter()
+            .filter(|(name, field)| field.required && data.get(name).is_none())
+            .map(|(name, _)| format!("missing required field {}", name))
+            .collect();
+
+        match missing.is_empty() {
+            true => Ok(()),
+            false => {
+                missing.sort();
+                Err(missing)
+            }
+        }
+    }
 }
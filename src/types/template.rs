@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// This object represents an update of a template subscribed with `render_template`
+///
+/// Each time the template is rendered again, either the `result` or the rendering `error` is set.
+/// The errors don't end the subscription, the template is rendered again on the next change.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TemplateRender {
+    #[serde(default)]
+    pub result: Option<Value>,
+    #[serde(default)]
+    pub listeners: Option<TemplateListeners>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+/// This is part of TemplateRender, what triggers the template to be rendered again
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TemplateListeners {
+    pub all: bool,
+    pub domains: Vec<String>,
+    pub entities: Vec<String>,
+    pub time: bool,
+}
@@ -0,0 +1,1167 @@
+//! Websocket connection to the Home Assistant server
+
+use crate::client::{check_if_event, HassClient, LARGE_MESSAGE_SIZE};
+use crate::managed::ManagedStateCache;
+use crate::runtime::{
+    channel, connect_async_with_config, receiver_stream, sleep, spawn, timeout, BroadcastSender,
+};
+use crate::types::{MessageType, WSEvent, WSRawEvent};
+use crate::{HassError, HassResult, Receiver, Sender};
+
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use async_tungstenite::tungstenite::protocol::WebSocketConfig;
+use async_tungstenite::tungstenite::{Error, Message as TungsteniteMessage};
+use futures_util::future::{self, BoxFuture};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use log::warn;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// the default maximum size of a received message, large enough for the get_states of big instances
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+// the listeners of the subscriptions which receive their raw events, by subscription id
+pub(crate) type Listeners = Arc<Mutex<HashMap<u64, Listener>>>;
+
+// the number of events kept for a paused subscription, the older ones are dropped
+pub(crate) const PAUSED_EVENTS_CAPACITY: usize = 1000;
+
+// sent to the subscriptions which are reset when they are renewed on a new connection,
+// Home Assistant never sends a null event
+pub(crate) const RESET_EVENT: Value = Value::Null;
+
+// the sender of a subscription receiving its raw events
+#[derive(Debug)]
+pub(crate) struct Listener {
+    sender: Sender<Value>,
+
+    // the events received while paused, until they are flushed to the sender
+    paused: Option<VecDeque<Value>>,
+
+    // set while the paused events are flushed, the new events are still queued behind them
+    resuming: bool,
+
+    // receives the RESET_EVENT when the subscription is renewed
+    pub(crate) resets: bool,
+}
+
+impl Listener {
+    pub(crate) fn new(sender: Sender<Value>) -> Self {
+        Listener {
+            sender,
+            paused: None,
+            resuming: false,
+            resets: false,
+        }
+    }
+
+    // sends the RESET_EVENT after the events not delivered yet, it is never dropped for a full channel:
+    // the events are then queued behind it, and true is returned when they must be flushed by resume_listener
+    pub(crate) fn reset(&mut self) -> bool {
+        if let Some(paused) = &mut self.paused {
+            paused.push_back(RESET_EVENT);
+            return false;
+        }
+        if self.sender.try_send(RESET_EVENT).is_ok() {
+            return false;
+        }
+        self.paused = Some(VecDeque::from([RESET_EVENT]));
+        self.resuming = true;
+        true
+    }
+
+    // queues the next events, instead of sending them
+    pub(crate) fn pause(&mut self) {
+        self.paused.get_or_insert_with(VecDeque::new);
+        self.resuming = false;
+    }
+
+    // starts flushing the queued events, returns false if the listener is not paused
+    pub(crate) fn start_resuming(&mut self) -> bool {
+        self.resuming = self.paused.is_some();
+        self.resuming
+    }
+
+    // sends the raw event, or queues it when paused, returns false when the listener is closed
+    fn forward(&mut self, data: &str, id: u64) -> bool {
+        let event = match serde_json::from_str::<WSRawEvent>(data) {
+            Ok(raw) => raw.event,
+            Err(err) => {
+                warn!(
+                    "Unable to read the event of the subscription {}: {}",
+                    id, err
+                );
+                return true;
+            }
+        };
+
+        if let Some(paused) = &mut self.paused {
+            if paused.len() >= PAUSED_EVENTS_CAPACITY {
+                warn!(
+                    "The subscription {} is paused with a full buffer, dropping its oldest event",
+                    id
+                );
+                paused.pop_front();
+            }
+            paused.push_back(event);
+            return true;
+        }
+
+        if self.sender.try_send(event).is_err() {
+            if self.sender.is_closed() {
+                return false;
+            }
+            warn!(
+                "The subscription {} channel is full, dropping the event",
+                id
+            );
+        }
+        true
+    }
+}
+
+// sends the events queued while the subscription `id` was paused, then lets the new events through
+//
+// The listener stays paused until its queue is empty, so the events are delivered in order,
+// and the flush stops if the subscription is paused again.
+pub(crate) async fn resume_listener(listeners: Listeners, id: u64) {
+    loop {
+        let (event, sender) = {
+            let mut listeners = listeners.lock().unwrap();
+            let Some(listener) = listeners.get_mut(&id) else {
+                return;
+            };
+            if !listener.resuming {
+                return;
+            }
+            match listener.paused.as_mut().and_then(VecDeque::pop_front) {
+                Some(event) => (event, listener.sender.clone()),
+                None => {
+                    listener.paused = None;
+                    listener.resuming = false;
+                    return;
+                }
+            }
+        };
+
+        if sender.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+// spawns the connection tasks on the caller's executor
+#[derive(Clone)]
+struct TaskSpawner(Arc<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>);
+
+impl fmt::Debug for TaskSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TaskSpawner")
+    }
+}
+
+/// The configuration used to connect the HassClient to the Home Assistant Websocket server
+///
+/// The connection is set up with the fluent setters, then opened with `connect` or `connect_and_auth`.
+///
+/// ```no_run
+/// use hass_rs::ClientConfig;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut client = ClientConfig::default()
+///         .timeout(Duration::from_secs(5))
+///         .channel_capacity(50)
+///         .connect_and_auth("ws://localhost:8123/api/websocket", "your_token")
+///         .await?;
+///
+///     println!("{}", client.get_config().await?);
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    // maximum duration of the Websocket handshake
+    timeout: Option<Duration>,
+
+    // capacity of the channels between the client and the connection tasks
+    channel_capacity: usize,
+
+    // forward the binary, ping and pong frames to the caller
+    raw_frames: bool,
+
+    // path under which Home Assistant is mounted, when behind a reverse proxy
+    base_path: String,
+
+    // spawns the connection tasks instead of the runtime selected by the features
+    spawner: Option<TaskSpawner>,
+
+    // extra headers sent with the handshake request, e.g. for an authenticating proxy
+    headers: Vec<(String, String)>,
+
+    // maximum size of a received message (and of each of its frames), in bytes
+    max_message_size: usize,
+
+    // interval of the Websocket ping frames, and the delay within which their pong is expected
+    keepalive: Option<(Duration, Duration)>,
+
+    // reopens the lost connections, when enabled
+    reconnect: Option<ReconnectPolicy>,
+}
+
+/// How the HassClient reopens a lost connection, see `ClientConfig::reconnect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// The delay after the first failed attempt, doubled after each of the next ones
+    pub min_delay: Duration,
+    /// The longest delay between two attempts
+    pub max_delay: Duration,
+    /// The number of attempts before giving up, the error of the last one is then returned
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            min_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout: None,
+            channel_capacity: 20,
+            raw_frames: false,
+            base_path: String::new(),
+            spawner: None,
+            headers: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            keepalive: None,
+            reconnect: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Sets the maximum duration for establishing the Websocket connection
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the capacity of the channels carrying the messages between the client and the connection
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity;
+        self
+    }
+
+    /// Forwards the binary frames (and the ping/pong frames) to `HassClient::raw_frames_stream`
+    ///
+    /// Those frames are discarded by default, once enabled the caller is responsible for draining them.
+    pub fn raw_frames(mut self, enabled: bool) -> Self {
+        self.raw_frames = enabled;
+        self
+    }
+
+    /// Sets the maximum size of a message received from the server, in bytes, 64 MiB by default
+    ///
+    /// A larger message is rejected while it is read, before being buffered in full or parsed,
+    /// and the command waiting for it fails with `HassError::MessageTooLarge`.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// Keeps the connection alive with Websocket ping frames sent every `interval`, answered by the server itself
+    ///
+    /// It is the `keepalive` with a pong expected before the next ping frame is due.
+    ///
+    /// ```
+    /// # #[tokio::main]
+    /// # async fn main() -> hass_rs::HassResult<()> {
+    /// use async_tungstenite::tungstenite::Message;
+    /// use futures_util::StreamExt;
+    /// use hass_rs::ClientConfig;
+    /// use std::time::Duration;
+    /// use tokio::net::TcpListener;
+    ///
+    /// // a server counting the ping frames it receives, they are answered by tungstenite
+    /// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let url = format!("ws://{}/api/websocket", listener.local_addr().unwrap());
+    /// let server = tokio::spawn(async move {
+    ///     let (socket, _) = listener.accept().await.unwrap();
+    ///     let mut websocket = tokio_tungstenite::accept_async(socket).await.unwrap();
+    ///     let mut pings = 0;
+    ///     let _ = tokio::time::timeout(Duration::from_millis(350), async {
+    ///         while let Some(Ok(message)) = websocket.next().await {
+    ///             if let Message::Ping(_) = message {
+    ///                 pings += 1;
+    ///             }
+    ///         }
+    ///     })
+    ///     .await;
+    ///     pings
+    /// });
+    ///
+    /// let client = ClientConfig::default()
+    ///     .protocol_keepalive(Duration::from_millis(100))
+    ///     .connect(&url)
+    ///     .await?;
+    /// // a ping every 100ms, with some slack for a slow machine
+    /// assert!(server.await.unwrap() >= 2);
+    /// # drop(client);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn protocol_keepalive(self, interval: Duration) -> Self {
+        self.keepalive(interval, interval)
+    }
+
+    /// Keeps the connection alive with Websocket ping frames sent every `interval`, each one expecting its pong
+    /// within `pong_timeout`
+    ///
+    /// Unlike `HassClient::ping`, the ping frames don't use a message id and are not seen by Home Assistant.
+    /// When a pong is not received in time the connection is considered dead: it is dropped, so the command
+    /// waiting for a response fails and the events streams end, or the connection is reopened with `reconnect`.
+    /// It replaces the `ping` heartbeat of the `ManagedStateCache`, which is then not sent.
+    pub fn keepalive(mut self, interval: Duration, pong_timeout: Duration) -> Self {
+        self.keepalive = Some((interval, pong_timeout));
+        self
+    }
+
+    /// Reopens the connection when it is lost, following the `policy`, disabled by default
+    ///
+    /// The connection is reopened by the command which finds it lost, or by a `HassClient::ping_within` without pong,
+    /// e.g. when the `keepalive` dropped a stalled connection. The session is authenticated again
+    /// with the token of `auth_with_longlivedtoken`, and the subscriptions are renewed with new ids,
+    /// which replace the old ones in `subscriptions` and in the events. The receivers of the events
+    /// (`take_events`, `event_broadcast` and the streams of the subscriptions) are kept across the connections.
+    ///
+    /// The command which was waiting for its response is sent again on the new connection when it only reads
+    /// something, e.g. `get_states`. The commands changing something (calling a service, updating a registry...)
+    /// and the raw ones fail with `HassError::CommandInterrupted` instead, as Home Assistant may have executed them
+    /// before the connection was lost. When all the attempts failed, the command fails with the error of the last one,
+    /// and the next command tries again.
+    ///
+    /// ```no_run
+    /// use hass_rs::{ClientConfig, ReconnectPolicy};
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> hass_rs::HassResult<()> {
+    /// let client = ClientConfig::default()
+    ///     .keepalive(Duration::from_secs(30), Duration::from_secs(10))
+    ///     .reconnect(ReconnectPolicy {
+    ///         max_attempts: 5,
+    ///         ..ReconnectPolicy::default()
+    ///     })
+    ///     .connect_and_auth("ws://localhost:8123/api/websocket", "your_token")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Adds a header to the HTTP request upgraded to the Websocket, it can be called several times
+    ///
+    /// This is needed behind the reverse proxies which authenticate the requests,
+    /// e.g. with a Cloudflare Access token. An invalid name or value fails the `connect`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the function spawning the tasks reading and writing the Websocket
+    ///
+    /// By default they are spawned on the runtime selected by the features (`use-tokio` or `use-async-std`),
+    /// this allows to run them on another executor.
+    ///
+    /// ```no_run
+    /// use hass_rs::ClientConfig;
+    ///
+    /// # async fn example() -> hass_rs::HassResult<()> {
+    /// let runtime = tokio::runtime::Handle::current();
+    /// let client = ClientConfig::default()
+    ///     .spawner(move |task| {
+    ///         runtime.spawn(task);
+    ///     })
+    ///     .connect("ws://localhost:8123/api/websocket")
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawner<F>(mut self, spawner: F) -> Self
+    where
+        F: Fn(BoxFuture<'static, ()>) + Send + Sync + 'static,
+    {
+        self.spawner = Some(TaskSpawner(Arc::new(spawner)));
+        self
+    }
+
+    /// Sets the path under which Home Assistant is mounted, e.g. "/homeassistant" behind a reverse proxy
+    ///
+    /// It is used by `connect_to`, the Websocket API path is appended to it.
+    /// A query string is kept at the end of the URL.
+    ///
+    /// ```
+    /// use hass_rs::ClientConfig;
+    ///
+    /// let config = ClientConfig::default().base_path("/homeassistant/?lang=en");
+    /// assert_eq!(
+    ///     config.websocket_url("localhost", 8123),
+    ///     "ws://localhost:8123/homeassistant/api/websocket?lang=en"
+    /// );
+    /// ```
+    pub fn base_path(mut self, path: &str) -> Self {
+        self.base_path = path.to_owned();
+        self
+    }
+
+    /// Returns the URL dialed by `connect_to` for the server at `host`:`port`
+    pub fn websocket_url(&self, host: &str, port: u16) -> String {
+        let (path, query) = match self.base_path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (self.base_path.as_str(), None),
+        };
+
+        let mut url = format!("ws://{}:{}", host, port);
+        let path = path.trim_matches('/');
+        if !path.is_empty() {
+            url.push('/');
+            url.push_str(path);
+        }
+        url.push_str("/api/websocket");
+        if let Some(query) = query.filter(|query| !query.is_empty()) {
+            url.push('?');
+            url.push_str(query);
+        }
+        url
+    }
+
+    /// Connects to the Websocket server of the Home Assistant instance at `host`:`port`
+    ///
+    /// The URL is built with the `base_path`, use `connect` to dial a complete URL instead.
+    pub async fn connect_to(self, host: &str, port: u16) -> HassResult<HassClient> {
+        let url = self.websocket_url(host, port);
+        self.connect(&url).await
+    }
+
+    /// Connects to the Websocket server at `url`, e.g. "ws://localhost:8123/api/websocket"
+    ///
+    /// The reading and the writing of the Websocket are spawned as tasks of the selected runtime,
+    /// the received events are available through `HassClient::take_events`.
+    pub async fn connect(self, url: &str) -> HassResult<HassClient> {
+        //Channels to receive the Events from the Websocket server
+        let (to_events, from_events) = channel::<WSEvent>(self.channel_capacity);
+        //Channels to receive the raw frames from the Websocket server, when enabled
+        let (to_raw, from_raw) = match self.raw_frames {
+            true => {
+                let (to_raw, from_raw) = channel::<TungsteniteMessage>(self.channel_capacity);
+                (Some(to_raw), Some(from_raw))
+            }
+            false => (None, None),
+        };
+        let dispatch = Dispatch {
+            to_events,
+            events_taken: Arc::default(),
+            to_raw,
+            listeners: Listeners::default(),
+            broadcast: BroadcastSender::new(self.channel_capacity),
+        };
+
+        let (to_gateway, from_gateway) = self.open(url, dispatch.clone()).await?;
+
+        let mut client = HassClient::new(to_gateway, from_gateway);
+        client.events = Some(from_events);
+        client.events_taken = dispatch.events_taken.clone();
+        client.raw_frames = from_raw;
+        client.listeners = Some(dispatch.listeners.clone());
+        client.broadcast = Some(dispatch.broadcast.clone());
+        // the dispatch is only kept by the tasks of the connection otherwise, so the events end with it
+        let reconnect = self.reconnect.map(|policy| (policy, dispatch));
+        client.origin = Some(Origin {
+            config: self,
+            url: url.to_owned(),
+            token: None,
+            reconnect,
+        });
+        Ok(client)
+    }
+
+    // opens the Websocket and spawns the tasks reading and writing it,
+    // returns the channels of the commands and of the responses
+    async fn open(
+        &self,
+        url: &str,
+        dispatch: Dispatch,
+    ) -> HassResult<(
+        Sender<TungsteniteMessage>,
+        Receiver<Result<TungsteniteMessage, Error>>,
+    )> {
+        // the handshake request, with the extra headers
+        let mut request = url.into_client_request()?;
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|error| Error::HttpFormat(error.into()))?;
+            let value =
+                HeaderValue::from_str(value).map_err(|error| Error::HttpFormat(error.into()))?;
+            request.headers_mut().append(name, value);
+        }
+
+        let ws_config = WebSocketConfig {
+            max_message_size: Some(self.max_message_size),
+            max_frame_size: Some(self.max_message_size),
+            ..Default::default()
+        };
+
+        let connecting = connect_async_with_config(request, Some(ws_config));
+        let (ws_stream, _) = match self.timeout {
+            Some(duration) => timeout(duration, connecting)
+                .await
+                .ok_or(HassError::Timeout)??,
+            None => connecting.await?,
+        };
+        let (sink, stream) = ws_stream.split();
+
+        //Channels to recieve the Client Command and send it over to the Websocket server
+        let (to_gateway, from_client) = channel::<TungsteniteMessage>(self.channel_capacity);
+        //Channels to receive the Response from the Websocket server and send it over to the Client
+        let (to_client, from_gateway) =
+            channel::<Result<TungsteniteMessage, Error>>(self.channel_capacity);
+
+        let keepalive = self
+            .keepalive
+            .map(|(interval, pong_timeout)| Keepalive::new(interval, pong_timeout));
+        let pongs = keepalive.as_ref().map(|keepalive| keepalive.ponged.clone());
+        //Channel to stop the reading of the Websocket once the writing ended,
+        //when the client was dropped or the keepalive found the connection dead
+        let (to_stop, from_stop) = channel::<()>(1);
+
+        let sender = sender_loop(sink, from_client, keepalive);
+        let receiver = receiver_loop(stream, to_client, dispatch, pongs);
+        self.spawn_task(async move {
+            if let Err(HassError::Timeout) = sender.await {
+                warn!("No pong was received for the ping frame, dropping the connection");
+            }
+            drop(to_stop);
+        });
+        self.spawn_task(async move {
+            let mut from_stop = Box::pin(receiver_stream(from_stop));
+            let _ = future::select(Box::pin(receiver), from_stop.next()).await;
+        });
+
+        Ok((to_gateway, from_gateway))
+    }
+
+    /// Connects to the Websocket server at `url` and authenticates with the long-lived access `token`
+    pub async fn connect_and_auth(self, url: &str, token: &str) -> HassResult<HassClient> {
+        let mut client = self.connect(url).await?;
+        client.auth_with_longlivedtoken(token).await?;
+        Ok(client)
+    }
+
+    /// Connects to `url` and maintains a StateCache of all the entities, reconnecting when the connection is lost
+    ///
+    /// The first connection is opened before returning, so a wrong URL or token is reported here.
+    /// See `ManagedStateCache` for the behavior while Home Assistant is down.
+    pub async fn managed_state_cache(
+        self,
+        url: &str,
+        token: &str,
+    ) -> HassResult<ManagedStateCache> {
+        let capacity = self.channel_capacity;
+        ManagedStateCache::start(self, capacity, url.to_owned(), token.to_owned()).await
+    }
+
+    // the ManagedStateCache doesn't send its own ping heartbeat when the protocol keepalive is enabled
+    pub(crate) fn has_protocol_keepalive(&self) -> bool {
+        self.keepalive.is_some()
+    }
+
+    // the ManagedStateCache reopens its connections by itself, with the delays of the policy
+    pub(crate) fn without_reconnect(mut self) -> Self {
+        self.reconnect = None;
+        self
+    }
+
+    pub(crate) fn reconnect_policy(&self) -> Option<ReconnectPolicy> {
+        self.reconnect
+    }
+
+    // spawns a task on the configured spawner, or on the runtime selected by the features
+    pub(crate) fn spawn_task<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match &self.spawner {
+            Some(TaskSpawner(spawner)) => spawner(Box::pin(task)),
+            None => {
+                spawn(task);
+            }
+        }
+    }
+}
+
+// where the reading of the Websocket dispatches what is not a response,
+// kept by a reconnecting client so its receivers outlive the connections
+#[derive(Debug, Clone)]
+pub(crate) struct Dispatch {
+    to_events: Sender<WSEvent>,
+    // set once the receiver of the events was taken, the events are dropped silently before
+    events_taken: Arc<AtomicBool>,
+    to_raw: Option<Sender<TungsteniteMessage>>,
+    listeners: Listeners,
+    broadcast: BroadcastSender<WSEvent>,
+}
+
+// where a client opened by `ClientConfig::connect` is connected, to connect to it again
+pub(crate) struct Origin {
+    config: ClientConfig,
+    url: String,
+    // the token of the last authentication, used again on the new connections
+    pub(crate) token: Option<String>,
+    // kept when the reconnection is enabled, with the dispatch shared by the connections
+    reconnect: Option<(ReconnectPolicy, Dispatch)>,
+}
+
+// the token is left out
+impl fmt::Debug for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Origin")
+            .field("config", &self.config)
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Origin {
+    pub(crate) fn reconnects(&self) -> bool {
+        self.reconnect.is_some()
+    }
+
+    // opens a new connection, with an increasing delay between the failed attempts
+    pub(crate) async fn reopen(
+        &self,
+    ) -> HassResult<(
+        Sender<TungsteniteMessage>,
+        Receiver<Result<TungsteniteMessage, Error>>,
+    )> {
+        let Some((policy, dispatch)) = &self.reconnect else {
+            return Err(HassError::ConnectionClosed);
+        };
+
+        let mut delay = policy.min_delay;
+        let mut attempt = 1;
+        loop {
+            match self.config.open(&self.url, dispatch.clone()).await {
+                Ok(channels) => return Ok(channels),
+                // e.g. the handshake refused with a 401 won't succeed on the next attempt either
+                Err(error) if attempt >= policy.max_attempts || !error.is_retryable() => {
+                    return Err(error)
+                }
+                Err(error) => {
+                    warn!("Could not reconnect to Home Assistant: {}", error);
+                    trace_event!(debug, error = %error, delay_ms = delay.as_millis() as u64, "reconnection failed");
+                    sleep(delay).await;
+                    delay = (delay * 2).min(policy.max_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // starts a ManagedStateCache on its own connection, authenticated with the token of the client
+    pub(crate) async fn managed_state_cache(&self) -> HassResult<ManagedStateCache> {
+        let token = self.token.as_deref().ok_or_else(|| {
+            HassError::Generic(
+                "The state cache requires the client to be authenticated with a token".to_owned(),
+            )
+        })?;
+        self.config
+            .clone()
+            .managed_state_cache(&self.url, token)
+            .await
+    }
+}
+
+// the state of the keepalive done with the Websocket ping frames
+pub(crate) struct Keepalive {
+    interval: Duration,
+    pong_timeout: Duration,
+    // set by the reading of the Websocket when a pong frame is received
+    ponged: Arc<AtomicBool>,
+}
+
+impl Keepalive {
+    fn new(interval: Duration, pong_timeout: Duration) -> Self {
+        Keepalive {
+            interval,
+            pong_timeout,
+            ponged: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+// writes the commands received from the client to the Websocket, and the ping frames of the keepalive,
+// HassError::Timeout is returned when the pong of a ping frame was not received in time
+pub(crate) async fn sender_loop<S>(
+    mut sink: S,
+    from_client: Receiver<TungsteniteMessage>,
+    keepalive: Option<Keepalive>,
+) -> HassResult<()>
+where
+    S: Sink<TungsteniteMessage, Error = Error> + Unpin,
+{
+    let mut from_client = Box::pin(receiver_stream(from_client));
+    let mut next_ping = keepalive
+        .as_ref()
+        .map(|keepalive| Instant::now() + keepalive.interval);
+    // set while the pong of the last ping frame is awaited
+    let mut pong_deadline: Option<Instant> = None;
+
+    loop {
+        let received = match pong_deadline.or(next_ping) {
+            Some(at) => {
+                let until_due = at.saturating_duration_since(Instant::now());
+                timeout(until_due, from_client.next()).await
+            }
+            None => Some(from_client.next().await),
+        };
+
+        match (received, &keepalive) {
+            (Some(Some(message)), _) => sink.send(message).await?,
+            // the client was dropped, there is nothing left to send
+            (Some(None), _) => {
+                let _ = sink.close().await;
+                return Ok(());
+            }
+            // the pong is due, or the next ping
+            (None, Some(keepalive)) => {
+                let now = Instant::now();
+                if pong_deadline.take().is_some() {
+                    if !keepalive.ponged.load(Ordering::Relaxed) {
+                        return Err(HassError::Timeout);
+                    }
+                    if next_ping.is_some_and(|at| at > now) {
+                        continue;
+                    }
+                }
+                keepalive.ponged.store(false, Ordering::Relaxed);
+                sink.send(TungsteniteMessage::Ping(Vec::new())).await?;
+                pong_deadline = Some(now + keepalive.pong_timeout);
+                next_ping = Some(now + keepalive.interval);
+            }
+            (None, None) => unreachable!("there is no timeout without keepalive"),
+        }
+    }
+}
+
+// reads the Websocket and dispatches the events and the responses,
+// only the events are deserialized here, the responses are left to the client task
+pub(crate) async fn receiver_loop<S>(
+    mut stream: S,
+    to_client: Sender<Result<TungsteniteMessage, Error>>,
+    dispatch: Dispatch,
+    pongs: Option<Arc<AtomicBool>>,
+) -> HassResult<()>
+where
+    S: Stream<Item = Result<TungsteniteMessage, Error>> + Unpin,
+{
+    while let Some(message) = stream.next().await {
+        if let (Some(pongs), Ok(TungsteniteMessage::Pong(_))) = (&pongs, &message) {
+            pongs.store(true, Ordering::Relaxed);
+        }
+
+        // the frames which are not part of the Home Assistant protocol never reach the client
+        if let Ok(
+            TungsteniteMessage::Binary(_)
+            | TungsteniteMessage::Ping(_)
+            | TungsteniteMessage::Pong(_)
+            | TungsteniteMessage::Frame(_),
+        ) = message
+        {
+            if let (Some(to_raw), Ok(frame)) = (&dispatch.to_raw, message) {
+                if to_raw.try_send(frame).is_err() {
+                    warn!("The raw frames channel is full or closed, dropping the frame");
+                }
+            }
+            continue;
+        }
+
+        if let Ok(TungsteniteMessage::Text(data)) = &message {
+            // a large response (e.g. get_states) goes to the client as it is, without being scanned here
+            if data.len() >= LARGE_MESSAGE_SIZE
+                && leading_message_type(data).is_some_and(|kind| kind != "event")
+            {
+                if to_client.send(message).await.is_err() {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            // the events of the subscriptions with their own listener are forwarded as they are
+            if let Some(id) = event_subscription_id(data) {
+                let mut listeners = dispatch.listeners.lock().unwrap();
+                if let Some(listener) = listeners.get_mut(&id) {
+                    trace_event!(trace, subscription_id = id, "event received");
+                    if !listener.forward(data, id) {
+                        // the stream of the subscription was dropped
+                        listeners.remove(&id);
+                    }
+                    continue;
+                }
+            }
+        }
+
+        match check_if_event(&message) {
+            Ok(event) => {
+                trace_event!(trace, subscription_id = event.id, "event received");
+                dispatch.broadcast.send(event.clone());
+
+                // never block the responses on a slow events consumer,
+                // the events are kept until the channel is full, for a receiver taken later
+                if dispatch.to_events.try_send(event).is_err()
+                    && dispatch.events_taken.load(Ordering::Relaxed)
+                {
+                    warn!("The events channel is full or closed, dropping the event");
+                }
+            }
+            Err(_) => {
+                if to_client.send(message).await.is_err() {
+                    // the client was dropped, nobody is waiting for the responses
+                    return Ok(());
+                }
+            }
+        }
+    }
+    trace_event!(debug, "the Websocket connection was closed");
+    Ok(())
+}
+
+// the number of bytes at the beginning of a message searched for its type
+const LEADING_TYPE_SEARCH_LEN: usize = 256;
+
+// returns the type of the message, when it is found at its beginning: Home Assistant writes it
+// before the content, so the type of a large message is known without scanning all of it
+pub(crate) fn leading_message_type(data: &str) -> Option<&str> {
+    let mut end = data.len().min(LEADING_TYPE_SEARCH_LEN);
+    while !data.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let (_, after_key) = data[..end].split_once("\"type\"")?;
+    let value = after_key
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start()
+        .strip_prefix('"')?;
+    value.split_once('"').map(|(kind, _)| kind)
+}
+
+// returns the subscription id of the message, if it is an event
+fn event_subscription_id(data: &str) -> Option<u64> {
+    match serde_json::from_str::<MessageType>(data) {
+        Ok(kind) if kind.msg_type == "event" => kind.id,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{entity_state, listen, reconnecting, state_changed, MockServer};
+    use std::sync::atomic::AtomicUsize;
+
+    // counts a task as running until it ends, or is dropped
+    struct Running(Arc<AtomicUsize>);
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_the_client_stops_its_tasks() {
+        let (listener, url) = listen().await;
+        let running = Arc::new(AtomicUsize::new(0));
+
+        let counter = running.clone();
+        let config = ClientConfig::default().spawner(move |task| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let task_running = Running(counter.clone());
+            tokio::spawn(async move {
+                task.await;
+                drop(task_running);
+            });
+        });
+        let (client, _server) =
+            future::join(config.connect(&url), MockServer::accept(&listener)).await;
+        let client = client.unwrap();
+        assert_eq!(running.load(Ordering::SeqCst), 2);
+
+        // the server keeps the connection open, the tasks must stop by themselves
+        drop(client);
+        let stopped = timeout(Duration::from_secs(5), async {
+            while running.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(
+            stopped.is_some(),
+            "the connection tasks outlived the client"
+        );
+    }
+
+    #[tokio::test]
+    async fn sender_loop_ends_when_the_client_is_dropped() {
+        let (to_gateway, from_client) = channel::<TungsteniteMessage>(1);
+        drop(to_gateway);
+
+        // the keepalive timer must not keep the loop going either
+        let sink = futures::sink::drain().sink_map_err(|never| match never {});
+        let keepalive = Keepalive::new(Duration::from_secs(60), Duration::from_secs(10));
+        let ended = timeout(
+            Duration::from_secs(1),
+            sender_loop(sink, from_client, Some(keepalive)),
+        )
+        .await;
+        assert!(matches!(ended, Some(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn receiver_loop_ends_with_the_connection() {
+        let (to_client, _from_gateway) = channel(1);
+        let (to_events, _from_events) = channel(1);
+        let dispatch = Dispatch {
+            to_events,
+            events_taken: Arc::default(),
+            to_raw: None,
+            listeners: Listeners::default(),
+            broadcast: BroadcastSender::new(1),
+        };
+        let ended = timeout(
+            Duration::from_secs(1),
+            receiver_loop(futures::stream::empty(), to_client, dispatch, None),
+        )
+        .await;
+        assert!(matches!(ended, Some(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn a_client_built_with_several_options() {
+        let (listener, url) = listen().await;
+        let config = ClientConfig::default()
+            .timeout(Duration::from_secs(5))
+            .channel_capacity(5)
+            .keepalive(Duration::from_millis(50), Duration::from_secs(1))
+            .reconnect(ReconnectPolicy {
+                min_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(100),
+                max_attempts: 3,
+            });
+        let (client, mut server) =
+            future::join(config.connect(&url), MockServer::accept(&listener)).await;
+        let mut client = client.unwrap();
+        let (auth, ()) =
+            future::join(client.auth_with_longlivedtoken("token"), server.auth()).await;
+        auth.unwrap();
+        let (subscribed, _) = future::join(
+            client.subscribe_event("state_changed"),
+            server.reply(Value::Null),
+        )
+        .await;
+        let first_id = subscribed.unwrap().id;
+        let mut events = Box::pin(client.event_stream().unwrap());
+
+        // the ping frames of the keepalive are answered, the connection stays open
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (pong, ()) = future::join(client.ping(), async {
+            let ping = server.receive().await;
+            server
+                .send_json(serde_json::json!({"id": ping["id"], "type": "pong"}))
+                .await;
+        })
+        .await;
+        assert_eq!(pong.unwrap(), "pong");
+
+        // the command finding the connection lost reopens it, authenticates and renews the subscription,
+        // then it is sent again
+        drop(server);
+        let (states, (mut server, renewed)) = future::join(client.get_states(), async {
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            let renewed = server.reply(Value::Null).await;
+            server.reply(serde_json::json!([])).await;
+            (server, renewed)
+        })
+        .await;
+        assert!(states.unwrap().is_empty());
+        assert_eq!(renewed["type"], "subscribe_events");
+        assert_eq!(renewed["event_type"], "state_changed");
+        let renewed_id = renewed["id"].as_u64().unwrap();
+        assert!(renewed_id > first_id);
+        assert!(client.subscriptions.contains_key(&renewed_id));
+        assert!(!client.subscriptions.contains_key(&first_id));
+
+        // the events of the new connection reach the receiver taken before
+        server
+            .send_event(renewed_id, state_changed("light.kitchen", Some("on")))
+            .await;
+        let event = timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.id, renewed_id);
+        assert_eq!(event.event.data.entity_id, "light.kitchen");
+    }
+
+    #[tokio::test]
+    async fn a_connection_dropped_without_closing_is_reopened() {
+        let (listener, url) = listen().await;
+        let (mut client, server) = MockServer::connected_to(reconnecting(), &listener, &url).await;
+
+        // the server crashes: the socket is gone without a close frame
+        let (states, ()) = future::join(client.get_states(), async {
+            let mut lost = server;
+            assert_eq!(lost.receive().await["type"], "get_states");
+            lost.drop_connection().await;
+
+            let mut server = MockServer::accept(&listener).await;
+            server.auth().await;
+            server
+                .reply(serde_json::json!([entity_state("light.kitchen", "on")]))
+                .await;
+        })
+        .await;
+        assert_eq!(states.unwrap()[0].entity_id, "light.kitchen");
+    }
+
+    #[tokio::test]
+    async fn a_connection_dropped_without_closing_is_reported_as_closed() {
+        let (mut client, server) = MockServer::connected(ClientConfig::default()).await;
+
+        let (states, ()) = future::join(client.get_states(), async {
+            let mut lost = server;
+            lost.receive().await;
+            lost.drop_connection().await;
+        })
+        .await;
+        assert!(matches!(
+            states,
+            Err(HassError::TungsteniteError(_) | HassError::ConnectionClosed)
+        ));
+        assert!(states.unwrap_err().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_reset_is_not_dropped_by_a_full_channel() {
+        let (sender, receiver) = channel::<Value>(1);
+        let event = |n: u64| format!(r#"{{"id": 1, "type": "event", "event": {{"n": {}}}}}"#, n);
+
+        let mut listener = Listener::new(sender);
+        assert!(listener.forward(&event(1), 1));
+        // the channel is full, the reset and the next events wait behind it
+        assert!(listener.reset());
+        assert!(listener.forward(&event(2), 1));
+
+        let listeners = Listeners::default();
+        listeners.lock().unwrap().insert(1, listener);
+        spawn(resume_listener(listeners, 1));
+        let received: Vec<Value> = timeout(
+            Duration::from_secs(5),
+            receiver_stream(receiver).take(3).collect(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            received,
+            [
+                serde_json::json!({"n": 1}),
+                RESET_EVENT,
+                serde_json::json!({"n": 2})
+            ]
+        );
+    }
+
+    #[test]
+    fn type_at_the_beginning_of_a_message() {
+        let result = r#"{"id": 3, "type": "result", "success": true, "result": []}"#;
+        assert_eq!(leading_message_type(result), Some("result"));
+        let event = r#"{"id":4,"type":"event","event":{}}"#;
+        assert_eq!(leading_message_type(event), Some("event"));
+
+        // not found in the first bytes, the message is then scanned in full
+        let late = format!(r#"{{"result": "{}", "type": "result"}}"#, "é".repeat(200));
+        assert_eq!(leading_message_type(&late), None);
+    }
+
+    #[tokio::test]
+    async fn the_binary_frames_are_forwarded_once_enabled() {
+        for enabled in [true, false] {
+            let config = ClientConfig::default().raw_frames(enabled);
+            let (mut client, mut server) = MockServer::connected(config).await;
+            let raw_frames = client.raw_frames_stream();
+            assert_eq!(raw_frames.is_some(), enabled);
+
+            // the binary frame doesn't get in the way of the command
+            let (pong, ()) = future::join(client.ping(), async {
+                server
+                    .send_frame(TungsteniteMessage::Binary(vec![1, 2, 3]))
+                    .await;
+                server.pong().await;
+            })
+            .await;
+            assert_eq!(pong.unwrap(), "pong");
+
+            if let Some(raw_frames) = raw_frames {
+                let mut raw_frames = Box::pin(receiver_stream(raw_frames));
+                let frame = tokio::time::timeout(Duration::from_secs(5), raw_frames.next())
+                    .await
+                    .unwrap();
+                assert_eq!(frame, Some(TungsteniteMessage::Binary(vec![1, 2, 3])));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_message_over_the_maximum_size_fails_the_command() {
+        let config = ClientConfig::default().max_message_size(1024);
+        let (mut client, mut server) = MockServer::connected(config).await;
+
+        let (states, ()) = future::join(client.get_states(), async {
+            let get_states = server.receive().await;
+            assert_eq!(get_states["type"], "get_states");
+            let states: Vec<Value> = (0..20)
+                .map(|n| entity_state(&format!("light.light_{}", n), "on"))
+                .collect();
+            server
+                .send_json(serde_json::json!({
+                    "id": get_states["id"], "type": "result", "success": true, "result": states
+                }))
+                .await;
+        })
+        .await;
+        match states {
+            Err(HassError::MessageTooLarge { size, max_size }) => {
+                assert_eq!(max_size, 1024);
+                assert!(size > 1024);
+            }
+            other => panic!("expected the message to be too large, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+// the entity_id! macro accepts the ids in the domain.object_id form, and rejects the others at
+// compile time
+#[test]
+fn entity_id_literals() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/entity_id_valid.rs");
+    t.compile_fail("tests/ui/entity_id_no_dot.rs");
+    t.compile_fail("tests/ui/entity_id_two_dots.rs");
+    t.compile_fail("tests/ui/entity_id_empty_domain.rs");
+    t.compile_fail("tests/ui/entity_id_empty_object_id.rs");
+}
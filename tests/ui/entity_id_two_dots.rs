@@ -0,0 +1,5 @@
+use hass_rs::entity_id;
+
+fn main() {
+    let _ = entity_id!("light.kitchen.ceiling");
+}
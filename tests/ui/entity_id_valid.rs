@@ -0,0 +1,10 @@
+use hass_rs::entity_id;
+
+fn main() {
+    let kitchen = entity_id!("light.kitchen");
+    assert_eq!(kitchen.domain(), "light");
+    assert_eq!(kitchen.object_id(), "kitchen");
+
+    let temperature = entity_id!("sensor.living_room_temperature");
+    assert_eq!(temperature.as_str(), "sensor.living_room_temperature");
+}